@@ -1,7 +1,10 @@
 use clap::Parser;
+use clap::Subcommand;
 use clap::ValueEnum;
 use metallb_dyn6_k8s::ranges::V6HostRange;
-use std::net::Ipv6Addr;
+use std::{net::Ipv6Addr, path::PathBuf};
+
+use crate::{pool::PoolSpec, uplink::UplinkSpec};
 
 macro_rules! env_prefix {
     () => {
@@ -11,7 +14,11 @@ macro_rules! env_prefix {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Source of the dynamic IPv6 network that will be injected into MetalLB
     #[arg(
         value_enum,
@@ -21,6 +28,89 @@ pub struct Cli {
     )]
     pub source: NetworkSource,
 
+    /// STUN servers to query when using the stun source, in host:port notation.
+    /// Servers are tried in order until one returns an IPv6 address.
+    #[arg(
+        long = "stun-server",
+        env = concat!(env_prefix!(), "STUN_SERVERS"),
+        value_delimiter = ',',
+        default_value = "stun.l.google.com:19302"
+    )]
+    pub stun_servers: Vec<String>,
+
+    /// Hostname of the MQTT broker to subscribe to when using the mqtt source
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_HOST"),
+        required_if_eq("source", "mqtt")
+    )]
+    pub mqtt_host: Option<String>,
+
+    /// Port of the MQTT broker
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_PORT"),
+        default_value_t = 1883
+    )]
+    pub mqtt_port: u16,
+
+    /// MQTT topic on which the prefix is published
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_TOPIC"),
+        required_if_eq("source", "mqtt")
+    )]
+    pub mqtt_topic: Option<String>,
+
+    /// Client ID to use when connecting to the MQTT broker
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_CLIENT_ID"),
+        default_value = "metallb-dyn6"
+    )]
+    pub mqtt_client_id: String,
+
+    /// Username for authenticating against the MQTT broker
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_USERNAME"),
+        requires = "mqtt_password"
+    )]
+    pub mqtt_username: Option<String>,
+
+    /// Password for authenticating against the MQTT broker
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_PASSWORD"),
+        requires = "mqtt_username",
+        hide_env_values = true
+    )]
+    pub mqtt_password: Option<String>,
+
+    /// Connect to the MQTT broker using TLS
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_TLS"),
+        default_value_t = false
+    )]
+    pub mqtt_tls: bool,
+
+    /// PEM-encoded CA certificate for verifying the MQTT broker. Uses the system roots if unset.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_CA_FILE"),
+        requires = "mqtt_tls"
+    )]
+    pub mqtt_ca_file: Option<PathBuf>,
+
+    /// Parse MQTT messages as JSON and read the prefix from this JSON pointer, such as /wan/prefix.
+    /// If unset, the raw message payload is used.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "MQTT_JSON_POINTER")
+    )]
+    pub mqtt_json_pointer: Option<String>,
+
     /// Override a portion of the prefix (usually the subnet). This value must be a valid IPv6 address.
     /// For example, to set the subnet to :beef: with a /48 dynamic prefix, use: 0:0:0:beef::
     #[arg(
@@ -44,8 +134,92 @@ pub struct Cli {
     /// Must be passed as a range of Ipv6-Host-parts, such as ::1000-::1999
     #[arg(
         env = concat!(env_prefix!(), "HOST_RANGE"),
+        required_unless_present_any = ["discover_pools", "controller", "external_ips_selector", "gateway_selector"],
+        requires = "metallb_pool"
+    )]
+    pub host_range: Option<V6HostRange>,
+
+    /// Configure an additional uplink with its own source, host range and subnet override, for multi-homed sites.
+    /// Can be repeated. The pool contains one range per uplink, including the one configured through --source,
+    /// HOST_RANGE and --subnet-override. Format: name=<name>,source=<source>[,host-range=<range>][,subnet-override=<addr>,prefix-length=<len>][,stun-server=<server>][,mqtt-topic=<topic>]
+    #[arg(
+        long = "uplink",
+        env = concat!(env_prefix!(), "UPLINKS"),
+        value_delimiter = ';'
+    )]
+    pub uplinks: Vec<UplinkSpec>,
+
+    /// Manage an additional IPAddressPool range, for example for a separate pool for internal services.
+    /// Can be repeated. All pools are updated in one batch, with a single MetalLB restart.
+    /// The range uses the prefix of the given uplink (default: the uplink configured through --source).
+    /// Format: name=<pool>,host-range=<range>[,uplink=<name>][,subnet-override=<addr>,prefix-length=<len>]
+    #[arg(
+        long = "pool",
+        env = concat!(env_prefix!(), "POOLS"),
+        value_delimiter = ';'
+    )]
+    pub pools: Vec<PoolSpec>,
+
+    /// Manage every IPAddressPool in the MetalLB namespace that carries the dyn6.spacebird.dev/host-range annotation.
+    /// The pools are re-discovered on every update, so HOST_RANGE and METALLB_POOL become optional.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "DISCOVER_POOLS"),
+        default_value_t = false
+    )]
+    pub discover_pools: bool,
+
+    /// Reconcile DynamicIPv6Pool resources in the MetalLB namespace instead of polling on a fixed schedule.
+    /// Each resource is re-checked every --update-interval seconds and reports its state in its status.
+    /// HOST_RANGE and METALLB_POOL become optional. Install the CRD with `metallb-dyn6 crd | kubectl apply -f -`.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "CONTROLLER"),
+        default_value_t = false
+    )]
+    pub controller: bool,
+
+    /// Elect a leader through a coordination.k8s.io/v1 Lease in the MetalLB namespace, so that only one of several
+    /// replicas updates the pools. Standby replicas take over once the leader stops renewing the lease.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "LEADER_ELECTION"),
+        default_value_t = false
+    )]
+    pub leader_election: bool,
+
+    /// Name of the Lease used for leader election
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "LEADER_ELECTION_LEASE_NAME"),
+        default_value = "metallb-dyn6"
+    )]
+    pub leader_election_lease_name: String,
+
+    /// Time in seconds after which a standby replica takes over if the leader did not renew the lease
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "LEADER_ELECTION_LEASE_DURATION"),
+        default_value_t = 15,
+        value_parser = clap::value_parser!(u64).range(3..)
+    )]
+    pub leader_election_lease_duration: u64,
+
+    /// Unique name of this replica in the leader election. Defaults to the hostname, which is the pod name
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "LEADER_ELECTION_IDENTITY")
+    )]
+    pub leader_election_identity: Option<String>,
+
+    /// Number of consecutive failed prefix lookups after which an uplink is considered gone and its range is removed
+    /// from the pool. Ranges are never removed if all uplinks fail.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "UPLINK_DOWN_AFTER"),
+        default_value_t = 3
     )]
-    pub host_range: V6HostRange,
+    pub uplink_down_after: u32,
 
     /// Time between attempts to refresh the dynamic Prefix and updating the IPAddressPool in seconds
     #[arg(
@@ -64,7 +238,46 @@ pub struct Cli {
     )]
     pub dry_run: bool,
 
+    /// File in which the last successfully applied prefix is recorded.
+    /// If unset, the last applied prefix is not persisted across restarts.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "STATE_FILE")
+    )]
+    pub state_file: Option<PathBuf>,
+
+    /// Load balancer implementation whose pools are managed.
+    /// With cilium, METALLB_POOL names a cluster-scoped CiliumLoadBalancerIPPool whose spec.blocks are updated.
+    /// With kube-vip, it names the range-<pool> and cidr-<pool> keys of the kube-vip ConfigMap, such as "global".
+    /// With calico, it names a Calico IPPool used as a template: for each new CIDR, a copy of it is created and all others are disabled.
+    /// This requires a CIDR-aligned host range.
+    /// These implementations pick up pool changes live, so the MetalLB reload, verification and Service options are rejected.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "BACKEND"),
+        value_enum,
+        default_value_t = Backend::Metallb
+    )]
+    pub backend: Backend,
+
+    /// Namespace of the ConfigMap read by the kube-vip cloud provider, used with --backend kube-vip
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "KUBE_VIP_NAMESPACE"),
+        default_value = "kube-system"
+    )]
+    pub kube_vip_namespace: String,
+
+    /// Name of the ConfigMap read by the kube-vip cloud provider, used with --backend kube-vip
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "KUBE_VIP_CONFIG_MAP"),
+        default_value = "kubevip"
+    )]
+    pub kube_vip_config_map: String,
+
     /// The namespace the MetalLB controller and speakers reside in.
+    /// With the cilium backend, DynamicIPv6Pool resources and the leader election Lease are kept here.
     #[arg(
         long,
         env = concat!(env_prefix!(), "METALLB_NAMESPACE"),
@@ -75,11 +288,126 @@ pub struct Cli {
     /// Name of the IPAddressPool resource to manage
     #[arg(
         env = concat!(env_prefix!(), "METALLB_POOL"),
+        required_unless_present_any = ["discover_pools", "controller", "external_ips_selector", "gateway_selector"],
+        requires = "host_range"
     )]
-    pub metallb_pool: String,
+    pub metallb_pool: Option<String>,
+
+    /// Take ownership of the pool addresses even if another field manager (such as Argo CD or a manual kubectl apply)
+    /// currently manages them. By default, such conflicts are reported as errors and the pool is left unchanged,
+    /// except on the first update of a pool without the dyn6.spacebird.dev/managed-ranges annotation.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "FORCE_CONFLICTS"),
+        default_value_t = false
+    )]
+    pub force_conflicts: bool,
+
+    /// How MetalLB is made to pick up changed pools.
+    /// none skips the restart, for MetalLB versions that reload pools live.
+    /// delete-pods deletes all MetalLB pods at once, rollout-restart restarts the MetalLB Deployments and DaemonSets
+    /// gradually, honouring their update strategy (such as maxUnavailable), and waits for the rollouts to complete.
+    /// auto detects the MetalLB version from the controller image and uses none for v0.13 and newer,
+    /// delete-pods otherwise.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "RELOAD_STRATEGY"),
+        value_enum,
+        default_value_t = ReloadStrategy::Auto
+    )]
+    pub reload_strategy: ReloadStrategy,
+
+    /// Maximum time in seconds to wait for MetalLB to become ready again after a reload.
+    /// If MetalLB is not ready in time, the pool changes are reverted.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "READY_TIMEOUT"),
+        default_value_t = 300
+    )]
+    pub ready_timeout: u64,
+
+    /// Maximum time in seconds to wait for the LoadBalancer Services of an updated pool to receive addresses
+    /// from the new ranges. If any Service still uses an address outside of them, the pool changes are rolled back.
+    /// Requires permission to list Services cluster-wide. Disabled by default (0).
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "VERIFY_TIMEOUT"),
+        default_value_t = 0
+    )]
+    pub verify_timeout: u64,
+
+    /// Namespace in which LoadBalancer Services that keep an IPv6 address outside of the new pool ranges
+    /// are made to request a new address from MetalLB, by clearing their ingress status.
+    /// Can be repeated. Each reallocation is reported as an Event on the Service. Disabled if not set.
+    #[arg(
+        long = "reip-namespace",
+        env = concat!(env_prefix!(), "REIP_NAMESPACES"),
+        value_delimiter = ','
+    )]
+    pub reip_namespaces: Vec<String>,
+
+    /// Keep the host part of the IPv6 address of Services labelled with dyn6.spacebird.dev/rewrite-pinned-ips=true
+    /// across prefix changes. After a change, these Services request their previous host part under the new prefix
+    /// through the metallb.io/loadBalancerIPs annotation. The host part is recorded in the dyn6.spacebird.dev/host-id annotation.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "STABLE_HOST_IDS"),
+        default_value_t = false
+    )]
+    pub stable_host_ids: bool,
+
+    /// Grace period in seconds during which removed ranges are kept in a separate "<pool>-draining" IPAddressPool
+    /// with autoAssign disabled, so that Services keep working on their previous addresses while clients move over.
+    /// The draining pool is deleted after the grace period. Set to 0 to remove ranges right away.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "DRAIN_PERIOD"),
+        default_value_t = 0
+    )]
+    pub drain_period: u64,
+
+    /// Update BGPAdvertisements and L2Advertisements in the MetalLB namespace that carry a
+    /// "dyn6.spacebird.dev/spec-template" annotation whenever the pools change.
+    /// The annotation holds a JSON object with the spec fields to set, in which {prefix}, {prefix_len}
+    /// and {prefix_net} are replaced with the prefix of the uplink named by "dyn6.spacebird.dev/uplink".
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "TEMPLATE_ADVERTISEMENTS"),
+        default_value_t = false
+    )]
+    pub template_advertisements: bool,
+
+    /// Move the peerAddress and sourceAddress of BGPPeers in the MetalLB namespace to the new prefix
+    /// when they lie in the network of a previous managed range, keeping their host part.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "REWRITE_BGP_PEERS"),
+        default_value_t = false
+    )]
+    pub rewrite_bgp_peers: bool,
+
+    /// Keep the externalIPs of the Services matching this label selector in sync with the dynamic prefix,
+    /// for clusters without a LoadBalancer implementation. The host part of each address is taken from the
+    /// `dyn6.spacebird.dev/host-id` annotation of the Service, such as `::10`.
+    /// HOST_RANGE and METALLB_POOL become optional. Not supported with --controller.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "EXTERNAL_IPS_SELECTOR")
+    )]
+    pub external_ips_selector: Option<String>,
+
+    /// Move the IPv6 `spec.addresses` of the Gateway API Gateways matching this label selector to the current prefix,
+    /// keeping their host part. The outcome for each Gateway is recorded as an Event on it.
+    /// HOST_RANGE and METALLB_POOL become optional. Not supported with --controller.
+    #[arg(
+        long,
+        env = concat!(env_prefix!(), "GATEWAY_SELECTOR")
+    )]
+    pub gateway_selector: Option<String>,
 
     /// Use this label selector to filter pods when force-deleting MetalLB to refresh its configuration.
-    /// Only pods that match this selector will be deleted.
+    /// Only pods that match this selector will be deleted. With --reload-strategy rollout-restart, the selector
+    /// is used to find the MetalLB Deployments and DaemonSets instead.
     /// Only adjust this if your MetalLB instance is installed with a custom label name/instance.
     #[arg(
         long,
@@ -89,9 +417,55 @@ pub struct Cli {
     pub metallb_pods_label_selector: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Subcommand)]
+pub enum Command {
+    /// Show the last prefix that was successfully applied to the pool
+    State {
+        /// File in which the last successfully applied prefix is recorded
+        #[arg(
+            long,
+            env = concat!(env_prefix!(), "STATE_FILE")
+        )]
+        state_file: PathBuf,
+    },
+    /// Print the DynamicIPv6Pool CustomResourceDefinition
+    Crd,
+}
+
+/// Which load balancer implementation to manage pools for
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Backend {
+    Metallb,
+    Cilium,
+    KubeVip,
+    Calico,
+}
+
+/// How MetalLB is made to pick up changed pools
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+pub enum ReloadStrategy {
+    None,
+    DeletePods,
+    RolloutRestart,
+    Auto,
+}
+
+impl From<ReloadStrategy> for metallb_dyn6_k8s::ReloadStrategy {
+    fn from(value: ReloadStrategy) -> Self {
+        match value {
+            ReloadStrategy::None => metallb_dyn6_k8s::ReloadStrategy::None,
+            ReloadStrategy::DeletePods => metallb_dyn6_k8s::ReloadStrategy::DeletePods,
+            ReloadStrategy::RolloutRestart => metallb_dyn6_k8s::ReloadStrategy::RolloutRestart,
+            ReloadStrategy::Auto => metallb_dyn6_k8s::ReloadStrategy::Auto,
+        }
+    }
+}
+
 /// Which source to use for our Ipv4 address
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
 pub enum NetworkSource {
     //Interface,
     MyIp,
+    Stun,
+    Mqtt,
 }
@@ -0,0 +1,557 @@
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use futures::StreamExt;
+use ipnet::Ipv6Net;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::{
+    runtime::{
+        controller::Action,
+        finalizer::{finalizer, Event},
+        reflector::Store,
+        watcher, Controller,
+    },
+    ResourceExt,
+};
+use metallb_dyn6_k8s::dynamic_pool::{
+    DynamicIPv6Pool, DynamicIPv6PoolStatus, DynamicPoolClient, READY_CONDITION,
+};
+use tokio::sync::{watch, Mutex};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    pool::{self, PoolTarget},
+    ranges, RuntimeConfig, UplinkStatus,
+};
+
+/// Finalizer that removes the range of a DynamicIPv6Pool from its IPAddressPool on deletion
+const CLEANUP_FINALIZER: &str = "dyn6.spacebird.dev/cleanup";
+
+/// Latest status of each uplink by name
+type UplinkStatuses = HashMap<String, UplinkStatus>;
+
+struct Context {
+    config: RuntimeConfig,
+    dynamic_pools: DynamicPoolClient,
+    /// Refreshed once per update interval by a single task, so that sources are not queried (and their failures
+    /// not counted) once per reconcile
+    uplinks: watch::Receiver<UplinkStatuses>,
+    store: Store<DynamicIPv6Pool>,
+    update_interval: Duration,
+    /// Serializes pool updates, as several DynamicIPv6Pools may share one IPAddressPool
+    pool_lock: Mutex<()>,
+}
+
+/// Error returned by the reconciler, kube-runtime requires a [std::error::Error]
+#[derive(Debug)]
+struct ReconcileError(anyhow::Error);
+
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for ReconcileError {}
+
+/// Result of applying a single DynamicIPv6Pool
+struct Applied {
+    prefix: Option<Ipv6Net>,
+    range: Option<String>,
+}
+
+/// Reconcile DynamicIPv6Pool resources in the MetalLB namespace until a shutdown signal is received.
+/// The uplinks are polled once per `update_interval`, and all resources are reconciled again when their status changes.
+pub(crate) async fn run(
+    config: RuntimeConfig,
+    dynamic_pools: DynamicPoolClient,
+    update_interval: Duration,
+) {
+    let (sender, uplinks) = watch::channel(poll_uplinks(&config).await);
+    let changes = futures::stream::unfold(uplinks.clone(), |mut uplinks| async move {
+        uplinks.changed().await.ok().map(|()| ((), uplinks))
+    });
+    let controller =
+        Controller::new(dynamic_pools.api(), watcher::Config::default()).reconcile_all_on(changes);
+    let context = Arc::new(Context {
+        store: controller.store(),
+        config,
+        dynamic_pools,
+        uplinks,
+        update_interval,
+        pool_lock: Mutex::new(()),
+    });
+
+    let poller = async {
+        loop {
+            tokio::time::sleep(update_interval).await;
+            let statuses = poll_uplinks(&context.config).await;
+            sender.send_if_modified(|current| {
+                let changed = *current != statuses;
+                *current = statuses;
+                changed
+            });
+        }
+    };
+
+    info!(msg = "Starting DynamicIPv6Pool controller");
+    let mut controller = controller.shutdown_on_signal();
+    if let Some(leadership) = context.config.leadership.clone() {
+        // Reconciles that are in progress are finished before stopping
+        controller = controller.graceful_shutdown_on(leadership.lost());
+    }
+    let reconciler = controller
+        .run(reconcile, error_policy, context.clone())
+        .for_each(|res| async move {
+            match res {
+                Ok((obj, _)) => debug!(msg = "Reconciled DynamicIPv6Pool", name = obj.name),
+                Err(e) => warn!(msg = "Reconcile failed", error = e.to_string()),
+            }
+        });
+    tokio::select! {
+        _ = reconciler => {},
+        _ = poller => {},
+    }
+}
+
+/// Query the source of every uplink once
+async fn poll_uplinks(config: &RuntimeConfig) -> UplinkStatuses {
+    let mut statuses = HashMap::new();
+    let mut errors = Vec::new();
+    for uplink in &config.uplinks {
+        let status = crate::poll_uplink(config, uplink, None, &mut errors).await;
+        statuses.insert(uplink.name.clone(), status);
+    }
+    if !errors.is_empty() {
+        warn!(msg = "Some uplinks are unavailable", errors = ?errors);
+    }
+    statuses
+}
+
+async fn reconcile(
+    obj: Arc<DynamicIPv6Pool>,
+    ctx: Arc<Context>,
+) -> Result<Action, kube::runtime::finalizer::Error<ReconcileError>> {
+    let api = ctx.dynamic_pools.api();
+    finalizer(&api, CLEANUP_FINALIZER, obj, |event| async {
+        match event {
+            Event::Apply(obj) => apply(&obj, &ctx).await,
+            Event::Cleanup(obj) => cleanup(&obj, &ctx).await,
+        }
+        .map_err(ReconcileError)
+    })
+    .await
+}
+
+fn error_policy(
+    _obj: Arc<DynamicIPv6Pool>,
+    _error: &kube::runtime::finalizer::Error<ReconcileError>,
+    ctx: Arc<Context>,
+) -> Action {
+    Action::requeue(ctx.update_interval)
+}
+
+#[instrument(skip_all, fields(name = obj.name_any()))]
+async fn apply(obj: &DynamicIPv6Pool, ctx: &Context) -> Result<Action> {
+    let result = apply_range(obj, ctx).await;
+    let status = applied_status(obj, &result, Time(Utc::now()));
+
+    if ctx.config.dry_run {
+        info!(msg = "Skipping status update due to dry-run mode being enabled", status = ?status);
+    } else if obj.status.as_ref() != Some(&status) {
+        ctx.dynamic_pools
+            .set_status(&obj.name_any(), status)
+            .await?;
+    }
+
+    result.map(|_| Action::requeue(ctx.update_interval))
+}
+
+/// The status of a resource after applying it. `now` is recorded as the time of a range change or Ready transition.
+fn applied_status(
+    obj: &DynamicIPv6Pool,
+    result: &Result<Applied>,
+    now: Time,
+) -> DynamicIPv6PoolStatus {
+    let mut status = obj.status.clone().unwrap_or_default();
+    status.observed_generation = obj.metadata.generation;
+    match result {
+        Ok(Applied {
+            prefix: Some(prefix),
+            range,
+        }) => {
+            status.prefix = Some(prefix.to_string());
+            if status.range != *range {
+                status.range = range.clone();
+                status.last_change_time = Some(now.clone());
+            }
+            set_ready(
+                &mut status,
+                obj,
+                "True",
+                "RangeApplied",
+                "Range is in place",
+                now,
+            );
+        }
+        Ok(Applied { prefix: None, .. }) => set_ready(
+            &mut status,
+            obj,
+            "False",
+            "SourceUnavailable",
+            "Source did not return a prefix, the current range is kept",
+            now,
+        ),
+        Err(e) => set_ready(
+            &mut status,
+            obj,
+            "False",
+            "Failed",
+            &format!("{:#}", e),
+            now,
+        ),
+    }
+    status
+}
+
+/// Update the IPAddressPool of the resource, returning the prefix of its uplink and the resulting range
+async fn apply_range(obj: &DynamicIPv6Pool, ctx: &Context) -> Result<Applied> {
+    let target = pool::dynamic_target(&obj.spec, &uplink_sources(ctx))?;
+    let uplink = ctx
+        .config
+        .uplinks
+        .iter()
+        .find(|u| u.name == target.uplink)
+        .ok_or_else(|| anyhow!("Unknown uplink {}", target.uplink))?;
+
+    let _guard = ctx.pool_lock.lock().await;
+    let mut targets = pool_targets(ctx, &target.pool, &obj.name_any()).await;
+    targets.push(target.clone());
+    validate(ctx, &targets)?;
+    let statuses = uplink_statuses(ctx, &targets);
+
+    let UplinkStatus::Available(prefix) = statuses[uplink.name.as_str()] else {
+        return Ok(Applied {
+            prefix: None,
+            range: None,
+        });
+    };
+    update_pool(ctx, &target.pool, &targets, &statuses).await?;
+
+    let range = ranges::desired_range(prefix, target.host_range, target.subnet_override);
+    Ok(Applied {
+        prefix: Some(prefix),
+        range: Some(range.to_string()),
+    })
+}
+
+/// Remove the range of a deleted resource from its IPAddressPool
+#[instrument(skip_all, fields(name = obj.name_any()))]
+async fn cleanup(obj: &DynamicIPv6Pool, ctx: &Context) -> Result<Action> {
+    let _guard = ctx.pool_lock.lock().await;
+    let targets = pool_targets(ctx, &obj.spec.pool, &obj.name_any()).await;
+    validate(ctx, &targets)?;
+    let statuses = uplink_statuses(ctx, &targets);
+    update_pool(ctx, &obj.spec.pool, &targets, &statuses).await?;
+    info!(
+        msg = "Removed range of deleted DynamicIPv6Pool",
+        pool = obj.spec.pool
+    );
+    Ok(Action::await_change())
+}
+
+/// All ranges that belong into `pool`: the ones configured on the command line or through annotations,
+/// plus the ones of all other DynamicIPv6Pools targeting it
+async fn pool_targets(ctx: &Context, pool: &str, exclude: &str) -> Vec<PoolTarget> {
+    let mut errors = Vec::new();
+    let mut targets = crate::current_targets(&ctx.config, &mut errors).await;
+    if !errors.is_empty() {
+        warn!(msg = "Could not collect all configured pools", errors = ?errors);
+    }
+    targets.retain(|t| t.pool == pool);
+    targets.extend(dynamic_targets(
+        &ctx.store.state(),
+        pool,
+        exclude,
+        &uplink_sources(ctx),
+    ));
+    targets
+}
+
+/// The ranges of the DynamicIPv6Pools targeting `pool`, except for `exclude` and resources that are being deleted
+fn dynamic_targets(
+    dynamic_pools: &[Arc<DynamicIPv6Pool>],
+    pool: &str,
+    exclude: &str,
+    uplinks: &[(&str, &str)],
+) -> Vec<PoolTarget> {
+    dynamic_pools
+        .iter()
+        .filter(|other| {
+            other.spec.pool == pool
+                && other.name_any() != exclude
+                && other.metadata.deletion_timestamp.is_none()
+        })
+        .filter_map(|other| match pool::dynamic_target(&other.spec, uplinks) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                debug!(msg = "Skipping invalid DynamicIPv6Pool", name = other.name_any(), error = ?e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn validate(ctx: &Context, targets: &[PoolTarget]) -> Result<()> {
+    let uplink_names = ctx
+        .config
+        .uplinks
+        .iter()
+        .map(|u| u.name.as_str())
+        .collect::<Vec<_>>();
+    pool::validate_targets(targets, &uplink_names)
+}
+
+/// The names of the configured uplinks along with the name of their source
+fn uplink_sources(ctx: &Context) -> Vec<(&str, &str)> {
+    ctx.config
+        .uplinks
+        .iter()
+        .map(|u| (u.name.as_str(), u.source_name.as_str()))
+        .collect()
+}
+
+/// The last polled status of the uplinks used by `targets`
+fn uplink_statuses<'a>(ctx: &'a Context, targets: &[PoolTarget]) -> HashMap<&'a str, UplinkStatus> {
+    let polled = ctx.uplinks.borrow();
+    ctx.config
+        .uplinks
+        .iter()
+        .filter(|u| targets.iter().any(|t| t.uplink == u.name))
+        .filter_map(|u| Some((u.name.as_str(), *polled.get(&u.name)?)))
+        .collect()
+}
+
+async fn update_pool(
+    ctx: &Context,
+    pool: &str,
+    targets: &[PoolTarget],
+    statuses: &HashMap<&str, UplinkStatus>,
+) -> Result<()> {
+    let pool_errors =
+        crate::update_pools(&ctx.config, &[pool.to_string()], targets, statuses).await?;
+    if let Some(e) = pool_errors.first() {
+        bail!("{}", e);
+    }
+    Ok(())
+}
+
+/// Set the Ready condition, keeping the transition time if the condition status did not change
+fn set_ready(
+    status: &mut DynamicIPv6PoolStatus,
+    obj: &DynamicIPv6Pool,
+    ready: &str,
+    reason: &str,
+    message: &str,
+    now: Time,
+) {
+    let last_transition_time = status
+        .conditions
+        .iter()
+        .find(|c| c.type_ == READY_CONDITION && c.status == ready)
+        .map_or(now, |c| c.last_transition_time.clone());
+    status.conditions.retain(|c| c.type_ != READY_CONDITION);
+    status.conditions.push(Condition {
+        type_: READY_CONDITION.to_string(),
+        status: ready.to_string(),
+        reason: reason.to_string(),
+        message: message.to_string(),
+        observed_generation: obj.metadata.generation,
+        last_transition_time,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use metallb_dyn6_k8s::{
+        dynamic_pool::DynamicIPv6PoolSpec,
+        ranges::{MetalLbAddressRange, V6Range},
+        PoolAddresses,
+    };
+
+    use super::*;
+    use crate::ranges::UplinkPrefix;
+
+    const UPLINKS: &[(&str, &str)] = &[("default", "my-ip")];
+
+    fn time(secs: i64) -> Time {
+        Time(DateTime::from_timestamp(secs, 0).unwrap())
+    }
+
+    fn dynamic_pool(
+        name: &str,
+        host_range: &str,
+        status: Option<DynamicIPv6PoolStatus>,
+    ) -> DynamicIPv6Pool {
+        let mut obj = DynamicIPv6Pool::new(
+            name,
+            DynamicIPv6PoolSpec {
+                uplink: None,
+                source: None,
+                host_range: host_range.to_string(),
+                subnet_override: None,
+                pool: "public".to_string(),
+            },
+        );
+        obj.metadata.generation = Some(2);
+        obj.status = status;
+        obj
+    }
+
+    fn applied(range: &str) -> Result<Applied> {
+        Ok(Applied {
+            prefix: Some("2001:db8:aaaa:bb00::/64".parse().unwrap()),
+            range: Some(range.to_string()),
+        })
+    }
+
+    fn ready(status: &DynamicIPv6PoolStatus) -> &Condition {
+        status
+            .conditions
+            .iter()
+            .find(|c| c.type_ == READY_CONDITION)
+            .unwrap()
+    }
+
+    #[test]
+    fn records_applied_range() {
+        let obj = dynamic_pool("web", "::1000-::1999", None);
+        let status = applied_status(
+            &obj,
+            &applied("2001:db8:aaaa:bb00::1000-2001:db8:aaaa:bb00::1999"),
+            time(100),
+        );
+        assert_eq!(status.prefix.as_deref(), Some("2001:db8:aaaa:bb00::/64"));
+        assert_eq!(
+            status.range.as_deref(),
+            Some("2001:db8:aaaa:bb00::1000-2001:db8:aaaa:bb00::1999")
+        );
+        assert_eq!(status.last_change_time, Some(time(100)));
+        assert_eq!(status.observed_generation, Some(2));
+        let ready = ready(&status);
+        assert_eq!(
+            (ready.status.as_str(), ready.reason.as_str()),
+            ("True", "RangeApplied")
+        );
+        assert_eq!(ready.last_transition_time, time(100));
+        assert_eq!(ready.observed_generation, Some(2));
+    }
+
+    #[test]
+    fn keeps_change_time_of_unchanged_range() {
+        let range = "2001:db8:aaaa:bb00::1000-2001:db8:aaaa:bb00::1999";
+        let obj = dynamic_pool("web", "::1000-::1999", None);
+        let previous = applied_status(&obj, &applied(range), time(100));
+        let obj = dynamic_pool("web", "::1000-::1999", Some(previous));
+
+        let status = applied_status(&obj, &applied(range), time(200));
+        assert_eq!(status.last_change_time, Some(time(100)));
+        assert_eq!(ready(&status).last_transition_time, time(100));
+        assert_eq!(obj.status, Some(status.clone()));
+
+        let status = applied_status(
+            &obj,
+            &applied("2001:db8:aaaa:bb00::2000-2001:db8:aaaa:bb00::2999"),
+            time(300),
+        );
+        assert_eq!(status.last_change_time, Some(time(300)));
+        assert_eq!(ready(&status).last_transition_time, time(100));
+    }
+
+    #[test]
+    fn sets_transition_time_when_ready_changes() {
+        let range = "2001:db8:aaaa:bb00::1000-2001:db8:aaaa:bb00::1999";
+        let obj = dynamic_pool("web", "::1000-::1999", None);
+        let previous = applied_status(&obj, &applied(range), time(100));
+        let obj = dynamic_pool("web", "::1000-::1999", Some(previous));
+
+        let unavailable = applied_status(
+            &obj,
+            &Ok(Applied {
+                prefix: None,
+                range: None,
+            }),
+            time(200),
+        );
+        // The range stays in the pool while the source is unavailable
+        assert_eq!(unavailable.range.as_deref(), Some(range));
+        assert_eq!(unavailable.last_change_time, Some(time(100)));
+        let condition = ready(&unavailable);
+        assert_eq!(
+            (condition.status.as_str(), condition.reason.as_str()),
+            ("False", "SourceUnavailable")
+        );
+        assert_eq!(condition.last_transition_time, time(200));
+        assert_eq!(unavailable.conditions.len(), 1);
+
+        let obj = dynamic_pool("web", "::1000-::1999", Some(unavailable));
+        let failed = applied_status(&obj, &Err(anyhow!("Pool public not found")), time(300));
+        let condition = ready(&failed);
+        assert_eq!(
+            (condition.reason.as_str(), condition.message.as_str()),
+            ("Failed", "Pool public not found")
+        );
+        assert_eq!(condition.last_transition_time, time(200));
+
+        let obj = dynamic_pool("web", "::1000-::1999", Some(failed));
+        let recovered = applied_status(&obj, &applied(range), time(400));
+        assert_eq!(ready(&recovered).last_transition_time, time(400));
+    }
+
+    #[test]
+    fn cleanup_removes_range_of_deleted_resource() {
+        let prefix: Ipv6Net = "2001:db8:aaaa:bb00::/64".parse().unwrap();
+        let range = |host_range: &str| {
+            MetalLbAddressRange::V6Range(V6Range::from_host_range(
+                prefix,
+                host_range.parse().unwrap(),
+            ))
+        };
+        let mut deleting = dynamic_pool("api", "::3000-::3999", None);
+        deleting.metadata.deletion_timestamp = Some(time(100));
+        let mut other_pool = dynamic_pool("mail", "::4000-::4999", None);
+        other_pool.spec.pool = "private".to_string();
+        let dynamic_pools = [
+            dynamic_pool("web", "::1000-::1999", None),
+            dynamic_pool("db", "::2000-::2999", None),
+            deleting,
+            other_pool,
+        ]
+        .map(Arc::new);
+
+        let targets = dynamic_targets(&dynamic_pools, "public", "db", UPLINKS);
+        assert_eq!(
+            targets.iter().map(|t| t.host_range).collect::<Vec<_>>(),
+            vec!["::1000-::1999".parse().unwrap()]
+        );
+
+        let current = PoolAddresses {
+            ranges: vec![range("::1000-::1999"), range("::2000-::2999")],
+            managed: Some(vec![range("::1000-::1999"), range("::2000-::2999")]),
+            resource_version: None,
+        };
+        let uplinks = targets
+            .iter()
+            .map(|t| UplinkPrefix {
+                host_range: t.host_range,
+                subnet_override: t.subnet_override,
+                prefix: Some(prefix),
+            })
+            .collect::<Vec<_>>();
+        let desired = ranges::calculate_changed_ranges(&current, &uplinks).unwrap();
+        assert_eq!(desired.ranges, vec![range("::1000-::1999")]);
+        assert_eq!(desired.managed, vec![range("::1000-::1999")]);
+    }
+}
@@ -0,0 +1,76 @@
+use std::net::Ipv6Addr;
+
+use anyhow::{bail, Context, Result};
+use ipnet::Ipv6Net;
+use metallb_dyn6_k8s::{ExternalIpService, HOST_ID_ANNOTATION, SUBNET_OVERRIDE_ANNOTATION};
+
+use crate::subnet_override::SubnetOverride;
+
+/// Calculate the external IP of a Service for a given prefix, from its host ID and optional subnet override annotations
+pub(crate) fn desired_address(
+    service: &ExternalIpService,
+    prefix_net: Ipv6Net,
+) -> Result<Ipv6Addr> {
+    let Some(host_id) = &service.host_id else {
+        bail!("Missing {} annotation", HOST_ID_ANNOTATION);
+    };
+    let host_id: Ipv6Addr = host_id
+        .parse()
+        .with_context(|| format!("Invalid {} annotation {}", HOST_ID_ANNOTATION, host_id))?;
+    if u128::from(host_id) >> 64 != 0 {
+        bail!(
+            "Invalid {} annotation {}, the prefix part must be empty",
+            HOST_ID_ANNOTATION,
+            host_id
+        );
+    }
+    let subnet_override = service
+        .subnet_override
+        .as_deref()
+        .map(str::parse::<SubnetOverride>)
+        .transpose()
+        .with_context(|| format!("Invalid {} annotation", SUBNET_OVERRIDE_ANNOTATION))?;
+    let prefix_net = subnet_override.map_or(prefix_net, |ovr| ovr.apply(prefix_net));
+    Ok(Ipv6Addr::from(
+        u128::from(prefix_net.network()) | u128::from(host_id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(host_id: Option<&str>, subnet_override: Option<&str>) -> ExternalIpService {
+        ExternalIpService {
+            namespace: "default".to_string(),
+            name: "web".to_string(),
+            host_id: host_id.map(str::to_string),
+            subnet_override: subnet_override.map(str::to_string),
+            uplink: None,
+            external_ips: vec![],
+            managed: None,
+            resource_version: None,
+        }
+    }
+
+    #[test]
+    fn test_desired_address() {
+        let prefix = "2001:db8:aaaa:bb00::/64".parse().unwrap();
+        assert_eq!(
+            desired_address(&service(Some("::10"), None), prefix).unwrap(),
+            "2001:db8:aaaa:bb00::10".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            desired_address(&service(Some("::10"), Some("0:0:0:cd::/56")), prefix).unwrap(),
+            "2001:db8:aaaa:bbcd::10".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_desired_address_invalid_annotations() {
+        let prefix = "2001:db8:aaaa:bb00::/64".parse().unwrap();
+        assert!(desired_address(&service(None, None), prefix).is_err());
+        assert!(desired_address(&service(Some("2001:db8::10"), None), prefix).is_err());
+        assert!(desired_address(&service(Some("::10"), Some("::cd")), prefix).is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use anyhow::{Context, Result};
+use ipnet::Ipv6Net;
+use metallb_dyn6_k8s::{AddressedGateway, SUBNET_OVERRIDE_ANNOTATION};
+
+use crate::subnet_override::SubnetOverride;
+
+/// Calculate the network to move the addresses of a Gateway to, from its optional subnet override annotation
+pub(crate) fn desired_network(gateway: &AddressedGateway, prefix_net: Ipv6Net) -> Result<Ipv6Net> {
+    let subnet_override = gateway
+        .subnet_override
+        .as_deref()
+        .map(str::parse::<SubnetOverride>)
+        .transpose()
+        .with_context(|| format!("Invalid {} annotation", SUBNET_OVERRIDE_ANNOTATION))?;
+    Ok(subnet_override.map_or(prefix_net, |ovr| ovr.apply(prefix_net)))
+}
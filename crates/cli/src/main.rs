@@ -1,36 +1,148 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
-use cli::Cli;
+use cli::{Cli, Command};
 
-use metallb_dyn6_k8s::{ranges::V6HostRange, MetalLbUpdater, MetalLbUpdaterConfig};
-use metallb_dyn6_sources::{MyIpSource, NetworkSource};
+use ipnet::Ipv6Net;
+use kube::CustomResourceExt;
+use metallb_dyn6_k8s::{
+    dynamic_pool::{DynamicIPv6Pool, DynamicPoolClient},
+    LeaderElectionConfig, LeaderElector, Leadership,
+};
+use metallb_dyn6_k8s::{
+    AdvertisementUpdate, CalicoUpdater, CalicoUpdaterConfig, CiliumUpdater, CiliumUpdaterConfig,
+    ExternalIpUpdater, GatewayUpdater, KubeVipUpdater, KubeVipUpdaterConfig, MetalLbUpdater,
+    MetalLbUpdaterConfig, PoolBackend, PoolUpdate,
+};
+use metallb_dyn6_sources::{MqttSource, MqttSourceConfig, MyIpSource, NetworkSource, StunSource};
+use pool::PoolTarget;
+use ranges::UplinkPrefix;
+use state::{AppliedPrefix, AppliedPrefixes, StateCache};
 use subnet_override::SubnetOverride;
 use tracing::{debug, error, info, instrument, warn};
 use tracing_subscriber::EnvFilter;
+use uplink::{Uplink, UplinkSpec, DEFAULT_UPLINK};
 
 mod cli;
+mod controller;
+mod external_ips;
+mod gateway;
+mod pool;
 mod ranges;
+mod spec;
+mod state;
 mod subnet_override;
+mod uplink;
+
+/// Number of attempts to update the pools if they are modified concurrently
+const UPDATE_ATTEMPTS: usize = 3;
 
 #[derive(Debug)]
 struct RuntimeConfig {
-    source: Box<dyn NetworkSource>,
-    pool: MetalLbUpdater,
-    subnet_override: Option<SubnetOverride>,
-    host_range: V6HostRange,
+    uplinks: Vec<Uplink>,
+    uplink_down_after: u32,
+    /// Pool ranges configured on the command line
+    targets: Vec<PoolTarget>,
+    discover_pools: bool,
+    template_advertisements: bool,
+    pools: Box<dyn PoolBackend>,
+    /// Keeps the externalIPs of selected Services in sync, if enabled
+    external_ips: Option<ExternalIpUpdater>,
+    /// Moves the addresses of selected Gateways to the current prefix, if enabled
+    gateways: Option<GatewayUpdater>,
     dry_run: bool,
+    state: Option<StateCache>,
+    /// Whether this replica holds the lease, if leader election is enabled
+    leadership: Option<Leadership>,
 }
 
 #[instrument(skip(cli))]
-fn get_source(cli: &Cli) -> Result<Box<dyn NetworkSource>> {
-    Ok(Box::new(match cli.source {
+fn get_source(cli: &Cli, uplink: &UplinkSpec) -> Result<Box<dyn NetworkSource>> {
+    Ok(match uplink.source {
         cli::NetworkSource::MyIp => {
-            info!(msg = "Using MyIP as address source");
-            MyIpSource::new()
+            info!(msg = "Using MyIP as address source", uplink = uplink.name);
+            Box::new(MyIpSource::new())
+        }
+        cli::NetworkSource::Stun => {
+            let servers = if uplink.stun_servers.is_empty() {
+                cli.stun_servers.clone()
+            } else {
+                uplink.stun_servers.clone()
+            };
+            info!(msg = "Using STUN as address source", uplink = uplink.name, servers = ?servers);
+            Box::new(StunSource::new(servers))
+        }
+        cli::NetworkSource::Mqtt => {
+            let topic = uplink
+                .mqtt_topic
+                .clone()
+                .or_else(|| cli.mqtt_topic.clone())
+                .ok_or_else(|| anyhow!("No MQTT topic set for uplink {}", uplink.name))?;
+            // Each uplink needs its own client ID, as brokers only allow one connection per ID
+            let client_id = if uplink.name == DEFAULT_UPLINK {
+                cli.mqtt_client_id.clone()
+            } else {
+                format!("{}-{}", cli.mqtt_client_id, uplink.name)
+            };
+            info!(msg = "Using MQTT as address source", uplink = uplink.name, host = ?cli.mqtt_host, topic = ?topic);
+            Box::new(MqttSource::new(MqttSourceConfig {
+                host: cli
+                    .mqtt_host
+                    .clone()
+                    .context("--mqtt-host is required for the mqtt source")?,
+                port: cli.mqtt_port,
+                client_id,
+                topic,
+                username: cli.mqtt_username.clone(),
+                password: cli.mqtt_password.clone(),
+                tls: cli.mqtt_tls,
+                ca_file: cli.mqtt_ca_file.clone(),
+                json_pointer: cli.mqtt_json_pointer.clone(),
+                reconnect_delay: Duration::from_secs(5),
+            })?)
         }
-    }))
+    })
+}
+
+/// The set options that only apply to the MetalLB backend
+fn metallb_only_flags(cli: &Cli) -> Vec<&'static str> {
+    [
+        (cli.verify_timeout > 0, "--verify-timeout"),
+        (cli.drain_period > 0, "--drain-period"),
+        (!cli.reip_namespaces.is_empty(), "--reip-namespace"),
+        (cli.stable_host_ids, "--stable-host-ids"),
+        (cli.rewrite_bgp_peers, "--rewrite-bgp-peers"),
+        (cli.template_advertisements, "--template-advertisements"),
+        (
+            cli.reload_strategy != cli::ReloadStrategy::Auto,
+            "--reload-strategy",
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(set, flag)| set.then_some(flag))
+    .collect()
+}
+
+/// Collect the configured uplinks: the default one built from the top-level arguments, plus any additional ones
+fn uplink_specs(cli: &Cli) -> Result<Vec<UplinkSpec>> {
+    let subnet_override = match (cli.subnet_override, cli.prefix_length) {
+        (Some(or), Some(len)) => Some(SubnetOverride::new(or, len)?),
+        (None, None) => None,
+        // Prevented by claps mutual requires
+        _ => unreachable!("subnet_override or prefix_length must be specified together"),
+    };
+    let default = UplinkSpec {
+        name: DEFAULT_UPLINK.to_string(),
+        source: cli.source,
+        host_range: cli.host_range,
+        subnet_override,
+        stun_servers: Vec::new(),
+        mqtt_topic: None,
+    };
+    Ok(std::iter::once(default)
+        .chain(cli.uplinks.iter().cloned())
+        .collect())
 }
 
 #[tokio::main]
@@ -42,26 +154,100 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
-    let subnet_override = match (cli.subnet_override, cli.prefix_length) {
-        (Some(or), Some(len)) => Some(SubnetOverride::new(or, len)?),
-        (None, None) => None,
-        // Prevented by claps mutual requires
-        _ => unreachable!("subnet_override or prefix_length must be specified together"),
-    };
+    match cli.command {
+        Some(Command::State { state_file }) => return show_state(&StateCache::new(state_file)),
+        Some(Command::Crd) => {
+            println!("{}", serde_json::to_string_pretty(&DynamicIPv6Pool::crd())?);
+            return Ok(());
+        }
+        None => {}
+    }
 
-    let source = get_source(&cli)?;
+    if cli.discover_pools && cli.backend == cli::Backend::KubeVip {
+        bail!("--discover-pools is not supported with the kube-vip backend, configure the pools with --pool");
+    }
+    if cli.backend != cli::Backend::Metallb {
+        if let Some(flag) = metallb_only_flags(&cli).first() {
+            bail!("{} is only supported with the metallb backend", flag);
+        }
+    }
+    if cli.controller && cli.external_ips_selector.is_some() {
+        bail!("--external-ips-selector is not supported with --controller");
+    }
+    if cli.controller && cli.gateway_selector.is_some() {
+        bail!("--gateway-selector is not supported with --controller");
+    }
+    let specs = uplink_specs(&cli)?;
+    uplink::validate_uplinks(&specs)?;
+    let targets = pool::pool_targets(cli.metallb_pool.as_deref(), &specs, &cli.pools)?;
+    let uplink_names = specs.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    pool::validate_targets(&targets, &uplink_names)?;
+    if cli.backend == cli::Backend::Calico {
+        pool::validate_calico_targets(&targets)?;
+    }
+    let uplinks = specs
+        .iter()
+        .map(|spec| Ok(Uplink::new(spec, get_source(&cli, spec)?)))
+        .collect::<Result<Vec<_>>>()?;
 
-    let config = RuntimeConfig {
-        source,
-        pool: MetalLbUpdater::new(MetalLbUpdaterConfig {
-            ip_pool: cli.metallb_pool,
-            namespace: cli.metallb_namespace,
-            label_selector: cli.metallb_pods_label_selector,
-        })
-        .await?,
-        subnet_override,
-        host_range: cli.host_range,
+    let mut config = RuntimeConfig {
+        uplinks,
+        uplink_down_after: cli.uplink_down_after,
+        pools: match cli.backend {
+            cli::Backend::Metallb => Box::new(
+                MetalLbUpdater::new(MetalLbUpdaterConfig {
+                    ip_pools: pool::pool_names(&targets),
+                    namespace: cli.metallb_namespace.clone(),
+                    label_selector: cli.metallb_pods_label_selector,
+                    force_conflicts: cli.force_conflicts,
+                    reload_strategy: cli.reload_strategy.into(),
+                    ready_timeout: Duration::from_secs(cli.ready_timeout),
+                    verify_timeout: (cli.verify_timeout > 0)
+                        .then(|| Duration::from_secs(cli.verify_timeout)),
+                    reip_namespaces: cli.reip_namespaces,
+                    stable_host_ids: cli.stable_host_ids,
+                    drain_period: (cli.drain_period > 0)
+                        .then(|| Duration::from_secs(cli.drain_period)),
+                    rewrite_bgp_peers: cli.rewrite_bgp_peers,
+                })
+                .await?,
+            ),
+            cli::Backend::Cilium => Box::new(
+                CiliumUpdater::new(CiliumUpdaterConfig {
+                    ip_pools: pool::pool_names(&targets),
+                    force_conflicts: cli.force_conflicts,
+                })
+                .await?,
+            ),
+            cli::Backend::KubeVip => Box::new(
+                KubeVipUpdater::new(KubeVipUpdaterConfig {
+                    config_map_namespace: cli.kube_vip_namespace,
+                    config_map: cli.kube_vip_config_map,
+                    ip_pools: pool::pool_names(&targets),
+                })
+                .await?,
+            ),
+            cli::Backend::Calico => Box::new(
+                CalicoUpdater::new(CalicoUpdaterConfig {
+                    ip_pools: pool::pool_names(&targets),
+                })
+                .await?,
+            ),
+        },
+        external_ips: match cli.external_ips_selector {
+            Some(selector) => Some(ExternalIpUpdater::new(selector).await?),
+            None => None,
+        },
+        gateways: match cli.gateway_selector {
+            Some(selector) => Some(GatewayUpdater::new(selector).await?),
+            None => None,
+        },
+        targets,
+        discover_pools: cli.discover_pools,
+        template_advertisements: cli.template_advertisements,
         dry_run: cli.dry_run,
+        state: cli.state_file.map(StateCache::new),
+        leadership: None,
     };
     info!(runtime_config = ?config);
 
@@ -69,40 +255,585 @@ async fn main() -> Result<()> {
         warn!("Running in dry-run mode - no changes will be made");
     }
 
+    if let Some(state) = &config.state {
+        match state.load() {
+            Ok(applied) if applied.is_empty() => {
+                info!(msg = "No previously applied prefix recorded", path = ?state.path())
+            }
+            Ok(applied) => {
+                for (uplink, last) in applied {
+                    info!(
+                        msg = "Found last applied prefix",
+                        uplink,
+                        prefix = ?last.source_prefix,
+                        ranges = ?last.ranges,
+                        source = last.source,
+                        applied_at = ?last.applied_at
+                    );
+                }
+            }
+            Err(e) => warn!(msg = "Could not load state", error = e.to_string()),
+        }
+    }
+
+    let leader = if cli.leader_election {
+        let identity = cli
+            .leader_election_identity
+            .clone()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .context("--leader-election-identity is required if HOSTNAME is not set")?;
+        Some(
+            LeaderElector::new(LeaderElectionConfig {
+                namespace: cli.metallb_namespace.clone(),
+                lease_name: cli.leader_election_lease_name.clone(),
+                identity,
+                lease_duration: Duration::from_secs(cli.leader_election_lease_duration),
+            })
+            .await?,
+        )
+    } else {
+        None
+    };
+    config.leadership = leader.as_ref().map(LeaderElector::leadership);
+
+    let dynamic_pools = if cli.controller {
+        Some(DynamicPoolClient::new(&cli.metallb_namespace).await?)
+    } else {
+        None
+    };
+
+    let update_interval = Duration::from_secs(cli.update_interval);
+    let Some(leader) = leader else {
+        return serve(config, dynamic_pools, update_interval).await;
+    };
+    tokio::select! {
+        _ = leader.acquire() => {},
+        _ = shutdown_signal() => return Ok(()),
+    }
+    // Losing the lease does not interrupt serve, it stops once the current update is finished or rolled back
+    let mut serving = std::pin::pin!(serve(config, dynamic_pools, update_interval));
+    let result = tokio::select! {
+        r = &mut serving => r,
+        e = leader.keep_leading() => {
+            warn!(msg = "Lost leadership, stopping after the current update", error = e.to_string());
+            serving.await.and(Err(e).context("Lost leadership"))
+        }
+    };
+    if let Err(e) = leader.release().await {
+        warn!(msg = "Could not release leadership", error = e.to_string());
+    }
+    result
+}
+
+/// Keep the pools updated until a shutdown signal is received or leadership is lost.
+/// With `dynamic_pools`, the DynamicIPv6Pool resources are reconciled instead of updating the pools periodically.
+async fn serve(
+    config: RuntimeConfig,
+    dynamic_pools: Option<DynamicPoolClient>,
+    update_interval: Duration,
+) -> Result<()> {
+    if let Some(dynamic_pools) = dynamic_pools {
+        controller::run(config, dynamic_pools, update_interval).await;
+        return Ok(());
+    }
+
+    let mut shutdown = std::pin::pin!(shutdown_signal());
+    let lost = config.leadership.clone().map(Leadership::lost);
+    let mut lost = std::pin::pin!(async move {
+        match lost {
+            Some(lost) => lost.await,
+            None => std::future::pending().await,
+        }
+    });
     loop {
         let r = run(&config).await;
         if let Err(e) = r {
             let text = e.to_string();
             error!(msg = "Run completed with errors", error = text);
         }
-        tokio::time::sleep(Duration::from_secs(cli.update_interval)).await;
+        tokio::select! {
+            _ = tokio::time::sleep(update_interval) => {},
+            _ = &mut shutdown => {
+                info!("Received shutdown signal, stopping");
+                return Ok(());
+            }
+            _ = &mut lost => return Ok(()),
+        }
     }
 }
 
+/// Fail if leader election is enabled and this replica no longer holds the lease
+fn ensure_leading(config: &RuntimeConfig) -> Result<()> {
+    if config.leadership.as_ref().is_some_and(|l| !l.is_leading()) {
+        bail!("Lost leadership, not making any changes");
+    }
+    Ok(())
+}
+
+/// Resolves once SIGINT or SIGTERM is received.
+/// The handlers are registered immediately, so signals received before the future is first polled are not lost.
+fn shutdown_signal() -> impl std::future::Future<Output = ()> {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Could not register SIGTERM handler");
+    async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+    }
+}
+
+/// Print the last applied prefixes recorded in the state file
+fn show_state(state: &StateCache) -> Result<()> {
+    let applied = state.load()?;
+    if applied.is_empty() {
+        println!("No prefix has been applied yet");
+    } else {
+        println!("{}", serde_json::to_string_pretty(&applied)?);
+    }
+    Ok(())
+}
+
+/// Load the last applied prefixes, treating an unreadable state file as empty
+fn load_last_applied(config: &RuntimeConfig) -> AppliedPrefixes {
+    let Some(state) = &config.state else {
+        return AppliedPrefixes::new();
+    };
+    state.load().unwrap_or_else(|e| {
+        warn!(msg = "Could not load state", error = e.to_string());
+        AppliedPrefixes::new()
+    })
+}
+
+/// Collect the pool ranges to manage: the statically configured ones, plus all discovered pools.
+/// Discovered pools that are invalid or conflict with the static configuration are skipped.
+async fn current_targets(config: &RuntimeConfig, errors: &mut Vec<String>) -> Vec<PoolTarget> {
+    let mut targets = config.targets.clone();
+    if !config.discover_pools {
+        return targets;
+    }
+    let annotated = match config.pools.discover_pools().await {
+        Ok(a) => a,
+        Err(e) => {
+            errors.push(e.to_string());
+            return targets;
+        }
+    };
+    let uplink_names = config
+        .uplinks
+        .iter()
+        .map(|u| u.name.as_str())
+        .collect::<Vec<_>>();
+    for pool in annotated {
+        if targets.iter().any(|t| t.pool == pool.name) {
+            warn!(
+                msg = "Pool is configured on the command line, ignoring its annotations",
+                pool = pool.name
+            );
+            continue;
+        }
+        let target = pool::discovered_target(&pool).and_then(|t| {
+            pool::validate_targets(std::slice::from_ref(&t), &uplink_names).map(|_| t)
+        });
+        match target {
+            Ok(t) => {
+                debug!(msg = "Discovered pool", target = ?t);
+                targets.push(t);
+            }
+            Err(e) => errors.push(format!("{}: {:#}", pool.name, e)),
+        }
+    }
+    targets
+}
+
+/// Result of querying the source of an uplink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UplinkStatus {
+    Available(Ipv6Net),
+    /// The source failed, the current ranges of the uplink are kept
+    Unavailable,
+    /// The source failed too often, the ranges of the uplink are removed
+    Gone,
+}
+
+async fn poll_uplink(
+    config: &RuntimeConfig,
+    uplink: &Uplink,
+    last: Option<&AppliedPrefix>,
+    errors: &mut Vec<String>,
+) -> UplinkStatus {
+    let prefix_net = match uplink.source.get().await {
+        Ok(p) => {
+            uplink.record_lookup(true);
+            p
+        }
+        Err(e) => {
+            let failures = uplink.record_lookup(false);
+            errors.push(format!("{}: {}", uplink.name, e));
+            if failures >= config.uplink_down_after {
+                warn!(
+                    msg = "Uplink unavailable for too long, removing its ranges",
+                    uplink = uplink.name,
+                    failures
+                );
+                return UplinkStatus::Gone;
+            }
+            if let Some(last) = last {
+                warn!(
+                    msg = "Source unavailable, last applied prefix remains in place",
+                    uplink = uplink.name,
+                    prefix = ?last.source_prefix,
+                    applied_at = ?last.applied_at
+                );
+            }
+            return UplinkStatus::Unavailable;
+        }
+    };
+    info!(msg = "Retrieved dynamic prefix", uplink = uplink.name, prefix = ?prefix_net);
+    assert_eq!(prefix_net.prefix_len(), 64);
+
+    if let Some(last) = last.filter(|l| l.source_prefix != prefix_net) {
+        info!(
+            msg = "Source returned a different prefix than the one last applied",
+            uplink = uplink.name,
+            last_prefix = ?last.source_prefix,
+            new_prefix = ?prefix_net,
+            last_applied_at = ?last.applied_at
+        );
+    }
+    UplinkStatus::Available(prefix_net)
+}
+
 #[instrument(skip(config))]
 async fn run(config: &RuntimeConfig) -> Result<()> {
-    let prefix_net = config.source.get().await?;
-    info!(msg = "Retrieved dynamic prefix", prefix = ?prefix_net);
-    assert_eq!(prefix_net.prefix_len(), 64);
+    let last_applied = load_last_applied(config);
+    let mut applied = AppliedPrefixes::new();
+    let mut statuses = HashMap::new();
+    let mut errors = Vec::new();
+    let mut pool_errors = Vec::new();
+    let targets = current_targets(config, &mut pool_errors).await;
+
+    for uplink in &config.uplinks {
+        let last = last_applied.get(&uplink.name);
+        let status = poll_uplink(config, uplink, last, &mut errors).await;
+        match status {
+            UplinkStatus::Available(prefix_net) => {
+                let ranges = targets
+                    .iter()
+                    .filter(|t| t.uplink == uplink.name)
+                    .map(|t| {
+                        let range =
+                            ranges::desired_range(prefix_net, t.host_range, t.subnet_override);
+                        (t.pool.clone(), range.to_string())
+                    })
+                    .collect();
+                applied.insert(
+                    uplink.name.clone(),
+                    AppliedPrefix {
+                        source: uplink.source_name.clone(),
+                        source_prefix: prefix_net,
+                        ranges,
+                        applied_at: chrono::Utc::now(),
+                    },
+                );
+            }
+            UplinkStatus::Unavailable => {
+                if let Some(last) = last {
+                    applied.insert(uplink.name.clone(), last.clone());
+                }
+            }
+            UplinkStatus::Gone => {}
+        }
+        statuses.insert(uplink.name.as_str(), status);
+    }
 
-    let current_ranges = config.pool.get_addresses().await?;
-    debug!(current_ranges = ?current_ranges);
+    // Never touch the pools if we have no information at all, the sources may just be down
+    if !statuses
+        .values()
+        .any(|s| matches!(s, UplinkStatus::Available(_)))
+    {
+        bail!("No uplink returned a prefix: [{}]", errors.join(", "));
+    }
 
-    let Some(desired_ranges) = ranges::calculate_changed_ranges(
-        &current_ranges,
-        prefix_net,
-        config.host_range,
-        config.subnet_override,
-    ) else {
-        info!("Desired address ranges match current ranges, nothing to do");
-        return Ok(());
-    };
+    pool_errors
+        .extend(update_pools(config, &pool::pool_names(&targets), &targets, &statuses).await?);
+    ensure_leading(config)?;
+    pool_errors.extend(update_external_ips(config, &statuses).await);
+    pool_errors.extend(update_gateways(config, &statuses).await);
+    // Record the prefixes even if the pools already matched, e.g. on first start
+    if !config.dry_run && pool_errors.is_empty() {
+        record_applied(config, &last_applied, applied);
+    }
+
+    errors.extend(pool_errors);
+    if !errors.is_empty() {
+        bail!(
+            "Some uplinks or pools are unavailable: [{}]",
+            errors.join(", ")
+        );
+    }
+    Ok(())
+}
 
+/// Calculate and apply the new address ranges of the given pools.
+/// If a pool is modified concurrently, the updates are recalculated from the current pools and applied again.
+/// Returns the errors of pools that could not be read, these pools are skipped.
+async fn update_pools(
+    config: &RuntimeConfig,
+    pools: &[String],
+    targets: &[PoolTarget],
+    statuses: &HashMap<&str, UplinkStatus>,
+) -> Result<Vec<String>> {
     if !config.dry_run {
-        config.pool.set_addresses(desired_ranges).await?;
-    } else {
-        info!("Skipping applying changes due to dry-run mode being enabled")
+        ensure_leading(config)?;
+        if let Err(e) = config.pools.remove_expired_draining_pools().await {
+            warn!(
+                msg = "Could not remove expired draining pools",
+                error = e.to_string()
+            );
+        }
     }
 
-    Ok(())
+    let mut attempt = 1;
+    loop {
+        let mut updates = Vec::new();
+        let mut pool_errors = Vec::new();
+        for pool in pools {
+            match pool_update(config, pool, targets, statuses).await {
+                Ok(Some(update)) => updates.push(update),
+                Ok(None) => {}
+                Err(e) => pool_errors.push(format!("{}: {}", pool, e)),
+            }
+        }
+
+        if updates.is_empty() {
+            return Ok(pool_errors);
+        }
+        let advertisements =
+            advertisement_updates(config, targets, statuses, &mut pool_errors).await;
+        if config.dry_run {
+            info!("Skipping applying changes due to dry-run mode being enabled");
+            return Ok(pool_errors);
+        }
+        ensure_leading(config)?;
+        match config.pools.set_addresses(updates, advertisements).await {
+            Ok(()) => return Ok(pool_errors),
+            Err(e) if e.is_conflict() && attempt < UPDATE_ATTEMPTS => {
+                warn!(
+                    msg = "Pool was modified concurrently, recalculating changes",
+                    error = e.to_string(),
+                    attempt
+                );
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Point the managed external IP of each selected Service at the current prefix of its uplink.
+/// Services whose uplink returned no prefix are skipped.
+/// Returns the errors of Services that could not be updated.
+async fn update_external_ips(
+    config: &RuntimeConfig,
+    statuses: &HashMap<&str, UplinkStatus>,
+) -> Vec<String> {
+    let Some(updater) = &config.external_ips else {
+        return Vec::new();
+    };
+    let services = match updater.discover_services().await {
+        Ok(s) => s,
+        Err(e) => return vec![e.to_string()],
+    };
+    let mut errors = Vec::new();
+    for service in services {
+        let name = format!("{}/{}", service.namespace, service.name);
+        let uplink = service.uplink.as_deref().unwrap_or(DEFAULT_UPLINK);
+        let Some(UplinkStatus::Available(prefix_net)) = statuses.get(uplink).copied() else {
+            debug!(
+                msg = "Uplink has no prefix, skipping Service",
+                service = name,
+                uplink
+            );
+            continue;
+        };
+        let address = match external_ips::desired_address(&service, prefix_net) {
+            Ok(address) => address,
+            Err(e) => {
+                errors.push(format!("{}: {:#}", name, e));
+                continue;
+            }
+        };
+        if config.dry_run {
+            info!(msg = "Skipping external IP update due to dry-run mode being enabled", service = name, %address);
+            continue;
+        }
+        if let Err(e) = updater.set_external_ip(&service, address).await {
+            errors.push(format!("{}: {}", name, e));
+        }
+    }
+    errors
+}
+
+/// Move the addresses of each selected Gateway to the current prefix of its uplink.
+/// Gateways whose uplink returned no prefix are skipped.
+/// Returns the errors of Gateways that could not be updated.
+async fn update_gateways(
+    config: &RuntimeConfig,
+    statuses: &HashMap<&str, UplinkStatus>,
+) -> Vec<String> {
+    let Some(updater) = &config.gateways else {
+        return Vec::new();
+    };
+    let gateways = match updater.discover_gateways().await {
+        Ok(g) => g,
+        Err(e) => return vec![e.to_string()],
+    };
+    let mut errors = Vec::new();
+    for gateway in gateways {
+        let name = format!("{}/{}", gateway.namespace, gateway.name);
+        let uplink = gateway.uplink.as_deref().unwrap_or(DEFAULT_UPLINK);
+        let Some(UplinkStatus::Available(prefix_net)) = statuses.get(uplink).copied() else {
+            debug!(
+                msg = "Uplink has no prefix, skipping Gateway",
+                gateway = name,
+                uplink
+            );
+            continue;
+        };
+        let network = match gateway::desired_network(&gateway, prefix_net) {
+            Ok(network) => network,
+            Err(e) => {
+                errors.push(format!("{}: {:#}", name, e));
+                continue;
+            }
+        };
+        if config.dry_run {
+            info!(msg = "Skipping Gateway update due to dry-run mode being enabled", gateway = name, %network);
+            continue;
+        }
+        if let Err(e) = updater.set_network(&gateway, network).await {
+            errors.push(format!("{}: {}", name, e));
+        }
+    }
+    errors
+}
+
+/// Render the spec templates of the annotated advertisements with the networks of their uplinks,
+/// which are the prefixes after the subnet override of the pools using them.
+/// Advertisements whose uplink returned no prefix are skipped, errors are added to `errors`.
+async fn advertisement_updates(
+    config: &RuntimeConfig,
+    targets: &[PoolTarget],
+    statuses: &HashMap<&str, UplinkStatus>,
+    errors: &mut Vec<String>,
+) -> Vec<AdvertisementUpdate> {
+    if !config.template_advertisements {
+        return Vec::new();
+    }
+    let templates = match config.pools.discover_advertisements().await {
+        Ok(t) => t,
+        Err(e) => {
+            errors.push(e.to_string());
+            return Vec::new();
+        }
+    };
+    let mut updates = Vec::new();
+    for template in templates {
+        let uplink = template.uplink.as_deref().unwrap_or(DEFAULT_UPLINK);
+        let Some(UplinkStatus::Available(prefix_net)) = statuses.get(uplink).copied() else {
+            debug!(
+                msg = "Uplink has no prefix, skipping advertisement",
+                advertisement = template.name,
+                uplink
+            );
+            continue;
+        };
+        let rendered = pool::uplink_network(targets, uplink, prefix_net)
+            .map_err(|e| e.to_string())
+            .and_then(|network| template.render(network).map_err(|e| e.to_string()));
+        match rendered {
+            Ok(update) => {
+                info!(msg = "Rendered advertisement", advertisement = template.name, spec = %update.spec);
+                updates.push(update);
+            }
+            Err(e) => errors.push(format!("{}: {}", template.name, e)),
+        }
+    }
+    updates
+}
+
+/// Calculate the new address ranges of a pool from the ranges of all its targets.
+/// Returns None if the pool is already up to date.
+async fn pool_update(
+    config: &RuntimeConfig,
+    pool: &str,
+    targets: &[PoolTarget],
+    statuses: &HashMap<&str, UplinkStatus>,
+) -> Result<Option<PoolUpdate>> {
+    let uplink_prefixes = targets
+        .iter()
+        .filter(|t| t.pool == pool)
+        .filter_map(|t| {
+            // Uplinks that were not polled keep their ranges
+            let status = statuses.get(t.uplink.as_str()).copied();
+            let prefix = match status.unwrap_or(UplinkStatus::Unavailable) {
+                UplinkStatus::Available(prefix_net) => Some(prefix_net),
+                UplinkStatus::Unavailable => None,
+                UplinkStatus::Gone => return None,
+            };
+            Some(UplinkPrefix {
+                host_range: t.host_range,
+                subnet_override: t.subnet_override,
+                prefix,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let current_ranges = config.pools.get_addresses(pool).await?;
+    debug!(pool, current_ranges = ?current_ranges);
+
+    Ok(
+        match ranges::calculate_changed_ranges(&current_ranges, &uplink_prefixes) {
+            Some(desired_ranges) => Some(PoolUpdate {
+                pool: pool.to_string(),
+                addresses: desired_ranges.ranges,
+                managed: desired_ranges.managed,
+                resource_version: current_ranges.resource_version,
+            }),
+            None => {
+                info!(
+                    msg = "Desired address ranges match current ranges, nothing to do",
+                    pool
+                );
+                None
+            }
+        },
+    )
+}
+
+/// Persist the applied prefixes, if they differ from the ones last recorded
+fn record_applied(config: &RuntimeConfig, last: &AppliedPrefixes, mut applied: AppliedPrefixes) {
+    let Some(state) = &config.state else {
+        return;
+    };
+    // Keep the original timestamp for prefixes that did not change
+    for (uplink, prefix) in applied.iter_mut() {
+        if let Some(l) = last
+            .get(uplink)
+            .filter(|l| l.source_prefix == prefix.source_prefix && l.ranges == prefix.ranges)
+        {
+            *prefix = l.clone();
+        }
+    }
+    if &applied == last {
+        return;
+    }
+    match state.store(&applied) {
+        Ok(()) => debug!(msg = "Recorded applied prefixes", applied = ?applied),
+        Err(e) => warn!(
+            msg = "Could not record applied prefixes",
+            error = e.to_string()
+        ),
+    }
 }
@@ -0,0 +1,451 @@
+use std::{collections::HashSet, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use ipnet::Ipv6Net;
+use metallb_dyn6_k8s::{
+    dynamic_pool::DynamicIPv6PoolSpec,
+    ranges::{V6HostRange, V6Range},
+    AnnotatedPool,
+};
+
+use crate::{
+    spec::parse_spec,
+    subnet_override::SubnetOverride,
+    uplink::{UplinkSpec, DEFAULT_UPLINK},
+};
+
+/// Configuration of an additional pool range, as passed via --pool.
+/// The value is a comma-separated list of key=value pairs, for example:
+/// name=internal,host-range=::2000-::2999,uplink=isp2
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolSpec {
+    pub(crate) name: String,
+    /// Uplink whose prefix is used for the range
+    pub(crate) uplink: String,
+    pub(crate) host_range: V6HostRange,
+    pub(crate) subnet_override: Option<SubnetOverride>,
+}
+
+impl FromStr for PoolSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut name, mut uplink) = (None, None);
+        let options = parse_spec(s, |key, value| {
+            match key {
+                "name" => name = Some(value.to_string()),
+                "uplink" => uplink = Some(value.to_string()),
+                other => return Err(format!("Unknown pool key {other}")),
+            }
+            Ok(())
+        })?;
+
+        Ok(PoolSpec {
+            name: name.ok_or("Missing pool name")?,
+            uplink: uplink.unwrap_or_else(|| DEFAULT_UPLINK.to_string()),
+            host_range: options.host_range.ok_or("Missing pool host-range")?,
+            subnet_override: options.subnet_override,
+        })
+    }
+}
+
+/// A single managed range: the prefix of `uplink`, combined with `host_range`, in the pool `pool`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PoolTarget {
+    pub(crate) pool: String,
+    pub(crate) uplink: String,
+    pub(crate) host_range: V6HostRange,
+    pub(crate) subnet_override: Option<SubnetOverride>,
+}
+
+/// Build the managed ranges: one per uplink in the default pool (if any), plus all additionally configured pool ranges
+pub(crate) fn pool_targets(
+    default_pool: Option<&str>,
+    uplinks: &[UplinkSpec],
+    pools: &[PoolSpec],
+) -> Result<Vec<PoolTarget>> {
+    let mut targets = Vec::new();
+    if let Some(default_pool) = default_pool {
+        for u in uplinks {
+            let Some(host_range) = u.host_range else {
+                bail!(
+                    "Uplink {} needs a host-range for pool {}",
+                    u.name,
+                    default_pool
+                );
+            };
+            targets.push(PoolTarget {
+                pool: default_pool.to_string(),
+                uplink: u.name.clone(),
+                host_range,
+                subnet_override: u.subnet_override,
+            });
+        }
+    }
+    targets.extend(pools.iter().map(|p| PoolTarget {
+        pool: p.name.clone(),
+        uplink: p.uplink.clone(),
+        host_range: p.host_range,
+        subnet_override: p.subnet_override,
+    }));
+    Ok(targets)
+}
+
+/// Build the managed range of a pool discovered through its annotations
+pub(crate) fn discovered_target(pool: &AnnotatedPool) -> Result<PoolTarget> {
+    parse_target(
+        &pool.name,
+        pool.uplink.as_deref(),
+        &pool.host_range,
+        pool.subnet_override.as_deref(),
+    )
+}
+
+/// Build the managed range of a DynamicIPv6Pool resource.
+/// `uplinks` are the names of the configured uplinks along with the name of their source.
+pub(crate) fn dynamic_target(
+    spec: &DynamicIPv6PoolSpec,
+    uplinks: &[(&str, &str)],
+) -> Result<PoolTarget> {
+    let uplink = match (spec.uplink.as_deref(), spec.source.as_deref()) {
+        (uplink, None) => uplink,
+        (Some(uplink), Some(source)) => match uplinks.iter().find(|(name, _)| *name == uplink) {
+            Some((_, used)) if *used != source => {
+                bail!("Uplink {} uses source {}, not {}", uplink, used, source)
+            }
+            _ => Some(uplink),
+        },
+        (None, Some(source)) => {
+            let mut matching = uplinks.iter().filter(|(_, used)| *used == source);
+            match (matching.next(), matching.next()) {
+                (Some((name, _)), None) => Some(*name),
+                (None, _) => bail!("No uplink uses source {}", source),
+                (Some(_), Some(_)) => {
+                    bail!(
+                        "Several uplinks use source {}, select one with uplink",
+                        source
+                    )
+                }
+            }
+        }
+    };
+    parse_target(
+        &spec.pool,
+        uplink,
+        &spec.host_range,
+        spec.subnet_override.as_deref(),
+    )
+}
+
+fn parse_target(
+    pool: &str,
+    uplink: Option<&str>,
+    host_range: &str,
+    subnet_override: Option<&str>,
+) -> Result<PoolTarget> {
+    Ok(PoolTarget {
+        pool: pool.to_string(),
+        uplink: uplink.unwrap_or(DEFAULT_UPLINK).to_string(),
+        host_range: host_range
+            .parse()
+            .with_context(|| format!("Invalid host range {}", host_range))?,
+        subnet_override: subnet_override.map(str::parse).transpose()?,
+    })
+}
+
+/// Ensure that all targets reference a known uplink and that ranges within a pool can be told apart
+pub(crate) fn validate_targets(targets: &[PoolTarget], uplinks: &[&str]) -> Result<()> {
+    let mut host_ranges = HashSet::new();
+    let mut pool_uplinks = HashSet::new();
+    for target in targets {
+        if !uplinks.contains(&target.uplink.as_str()) {
+            bail!(
+                "Pool {} references unknown uplink {}",
+                target.pool,
+                target.uplink
+            );
+        }
+        if !host_ranges.insert((&target.pool, target.host_range)) {
+            bail!(
+                "Pool {} uses the same host range for multiple uplinks",
+                target.pool
+            );
+        }
+        if !pool_uplinks.insert((&target.pool, &target.uplink)) {
+            bail!(
+                "Pool {} has multiple ranges for uplink {}",
+                target.pool,
+                target.uplink
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Ensure that every pool can be stored in a single Calico IPPool CIDR:
+/// it has a single target, whose host range is aligned to a prefix length
+pub(crate) fn validate_calico_targets(targets: &[PoolTarget]) -> Result<()> {
+    let mut pools = HashSet::new();
+    for target in targets {
+        if !pools.insert(&target.pool) {
+            bail!(
+                "Calico pool {} can only hold a single CIDR, but has several ranges",
+                target.pool
+            );
+        }
+        let range = V6Range::from_host_range(Ipv6Net::default(), target.host_range);
+        if range.to_cidr().is_none() {
+            bail!(
+                "The host range of Calico pool {} is not a CIDR, use a host range that is aligned to a prefix length such as ::1000-::1fff",
+                target.pool
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Names of all pools referenced by the targets, in order of first appearance
+pub(crate) fn pool_names(targets: &[PoolTarget]) -> Vec<String> {
+    let mut names = Vec::new();
+    for target in targets {
+        if !names.contains(&target.pool) {
+            names.push(target.pool.clone());
+        }
+    }
+    names
+}
+
+/// The network that the ranges of an uplink live in: its prefix after the subnet override of its targets.
+/// Fails if the targets of the uplink use different subnet overrides, as there is no single network then.
+pub(crate) fn uplink_network(
+    targets: &[PoolTarget],
+    uplink: &str,
+    prefix: Ipv6Net,
+) -> Result<Ipv6Net> {
+    let mut networks = targets
+        .iter()
+        .filter(|t| t.uplink == uplink)
+        .map(|t| t.subnet_override.map_or(prefix, |ovr| ovr.apply(prefix)));
+    let Some(network) = networks.next() else {
+        return Ok(prefix);
+    };
+    if networks.any(|n| n != network) {
+        bail!(
+            "The pools of uplink {} use different subnet overrides",
+            uplink
+        );
+    }
+    Ok(network)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    fn uplink(name: &str, host_range: &str) -> UplinkSpec {
+        format!("name={name},source=stun,host-range={host_range}")
+            .parse()
+            .unwrap()
+    }
+
+    fn names(uplinks: &[UplinkSpec]) -> Vec<&str> {
+        uplinks.iter().map(|u| u.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_pool_spec() {
+        let spec =
+            "name=internal,host-range=::2000-::2999,subnet-override=0:0:0:cd::,prefix-length=56"
+                .parse::<PoolSpec>()
+                .unwrap();
+        assert_eq!(
+            spec,
+            PoolSpec {
+                name: "internal".to_string(),
+                uplink: DEFAULT_UPLINK.to_string(),
+                host_range: "::2000-::2999".parse().unwrap(),
+                subnet_override: Some(
+                    SubnetOverride::new(Ipv6Addr::new(0, 0, 0, 0xcd, 0, 0, 0, 0), 56).unwrap()
+                ),
+            }
+        );
+        "host-range=::2000-::2999".parse::<PoolSpec>().unwrap_err();
+    }
+
+    #[test]
+    fn builds_targets_for_all_pools() {
+        let uplinks = vec![uplink(DEFAULT_UPLINK, "::1-::2"), uplink("isp2", "::3-::4")];
+        let pools = vec!["name=internal,host-range=::5-::6,uplink=isp2"
+            .parse::<PoolSpec>()
+            .unwrap()];
+        let targets = pool_targets(Some("public"), &uplinks, &pools).unwrap();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(pool_names(&targets), vec!["public", "internal"]);
+        validate_targets(&targets, &names(&uplinks)).unwrap();
+
+        let targets = pool_targets(None, &uplinks, &pools).unwrap();
+        assert_eq!(pool_names(&targets), vec!["internal"]);
+    }
+
+    #[test]
+    fn default_pool_needs_host_ranges() {
+        let uplinks = vec!["name=isp1,source=stun".parse::<UplinkSpec>().unwrap()];
+        pool_targets(Some("public"), &uplinks, &[]).unwrap_err();
+        assert!(pool_targets(None, &uplinks, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_ambiguous_targets() {
+        let uplinks = vec![uplink(DEFAULT_UPLINK, "::1-::2"), uplink("isp2", "::1-::2")];
+        let targets = pool_targets(Some("public"), &uplinks, &[]).unwrap();
+        validate_targets(&targets, &names(&uplinks)).unwrap_err();
+
+        let uplinks = vec![uplink(DEFAULT_UPLINK, "::1-::2")];
+        let pools = vec!["name=public,host-range=::5-::6"
+            .parse::<PoolSpec>()
+            .unwrap()];
+        let targets = pool_targets(Some("public"), &uplinks, &pools).unwrap();
+        validate_targets(&targets, &names(&uplinks)).unwrap_err();
+
+        let pools = vec!["name=internal,host-range=::5-::6,uplink=isp3"
+            .parse::<PoolSpec>()
+            .unwrap()];
+        let targets = pool_targets(Some("public"), &uplinks, &pools).unwrap();
+        validate_targets(&targets, &names(&uplinks)).unwrap_err();
+    }
+
+    #[test]
+    fn applies_subnet_override_to_uplink_network() {
+        let prefix = "2001:db8:aaaa:ab::/64".parse().unwrap();
+        let target = |pool: &str, subnet_override: Option<&str>| PoolTarget {
+            pool: pool.to_string(),
+            uplink: "isp2".to_string(),
+            host_range: "::1000-::1999".parse().unwrap(),
+            subnet_override: subnet_override.map(|s| s.parse().unwrap()),
+        };
+        assert_eq!(uplink_network(&[], "isp2", prefix).unwrap(), prefix);
+        assert_eq!(
+            uplink_network(&[target("public", Some("0:0:0:cd::/56"))], "isp2", prefix).unwrap(),
+            "2001:db8:aaaa:cd::/64".parse().unwrap()
+        );
+        assert_eq!(
+            uplink_network(&[target("public", None)], "isp2", prefix).unwrap(),
+            prefix
+        );
+        uplink_network(
+            &[
+                target("public", Some("0:0:0:cd::/56")),
+                target("internal", None),
+            ],
+            "isp2",
+            prefix,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn calico_targets_need_single_aligned_range() {
+        let target = |pool: &str, uplink: &str, host_range: &str| PoolTarget {
+            pool: pool.to_string(),
+            uplink: uplink.to_string(),
+            host_range: host_range.parse().unwrap(),
+            subnet_override: None,
+        };
+        validate_calico_targets(&[
+            target("public", DEFAULT_UPLINK, "::1000-::1fff"),
+            target("internal", DEFAULT_UPLINK, "::2000-::2fff"),
+        ])
+        .unwrap();
+        validate_calico_targets(&[target("public", DEFAULT_UPLINK, "::1000-::1999")]).unwrap_err();
+        validate_calico_targets(&[
+            target("public", DEFAULT_UPLINK, "::1000-::1fff"),
+            target("public", "isp2", "::2000-::2fff"),
+        ])
+        .unwrap_err();
+    }
+
+    #[test]
+    fn builds_target_from_annotations() {
+        let pool = AnnotatedPool {
+            name: "public".to_string(),
+            host_range: "::1000-::1999".to_string(),
+            subnet_override: Some("0:0:0:cd::/56".to_string()),
+            uplink: None,
+        };
+        assert_eq!(
+            discovered_target(&pool).unwrap(),
+            PoolTarget {
+                pool: "public".to_string(),
+                uplink: DEFAULT_UPLINK.to_string(),
+                host_range: "::1000-::1999".parse().unwrap(),
+                subnet_override: Some(
+                    SubnetOverride::new(Ipv6Addr::new(0, 0, 0, 0xcd, 0, 0, 0, 0), 56).unwrap()
+                ),
+            }
+        );
+
+        discovered_target(&AnnotatedPool {
+            host_range: "::1000/120".to_string(),
+            ..pool.clone()
+        })
+        .unwrap_err();
+        discovered_target(&AnnotatedPool {
+            subnet_override: Some("0:0:0:cd::".to_string()),
+            ..pool
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn builds_target_from_dynamic_pool() {
+        let spec = DynamicIPv6PoolSpec {
+            uplink: Some("isp2".to_string()),
+            source: None,
+            host_range: "::1000-::1999".to_string(),
+            subnet_override: None,
+            pool: "public".to_string(),
+        };
+        let uplinks = [(DEFAULT_UPLINK, "my-ip"), ("isp2", "stun")];
+        assert_eq!(
+            dynamic_target(&spec, &uplinks).unwrap(),
+            PoolTarget {
+                pool: "public".to_string(),
+                uplink: "isp2".to_string(),
+                host_range: "::1000-::1999".parse().unwrap(),
+                subnet_override: None,
+            }
+        );
+    }
+
+    #[test]
+    fn selects_uplink_by_source() {
+        let spec = DynamicIPv6PoolSpec {
+            uplink: None,
+            source: Some("stun".to_string()),
+            host_range: "::1000-::1999".to_string(),
+            subnet_override: None,
+            pool: "public".to_string(),
+        };
+        let uplinks = [(DEFAULT_UPLINK, "my-ip"), ("isp2", "stun")];
+        assert_eq!(dynamic_target(&spec, &uplinks).unwrap().uplink, "isp2");
+
+        let with_uplink = DynamicIPv6PoolSpec {
+            uplink: Some("isp2".to_string()),
+            ..spec.clone()
+        };
+        assert_eq!(
+            dynamic_target(&with_uplink, &uplinks).unwrap().uplink,
+            "isp2"
+        );
+        let mismatch = DynamicIPv6PoolSpec {
+            uplink: Some(DEFAULT_UPLINK.to_string()),
+            ..spec.clone()
+        };
+        dynamic_target(&mismatch, &uplinks).unwrap_err();
+
+        dynamic_target(&spec, &[(DEFAULT_UPLINK, "my-ip")]).unwrap_err();
+        dynamic_target(&spec, &[(DEFAULT_UPLINK, "stun"), ("isp2", "stun")]).unwrap_err();
+    }
+}
@@ -1,45 +1,99 @@
 use std::{collections::HashSet, hash::RandomState};
 
 use ipnet::Ipv6Net;
-use metallb_dyn6_k8s::ranges::{MetalLbAddressRange, V6HostRange, V6Range};
+use metallb_dyn6_k8s::{
+    ranges::{MetalLbAddressRange, V6HostRange, V6Range},
+    PoolAddresses,
+};
 use tracing::info;
 
 use crate::subnet_override::SubnetOverride;
 
-/// Calculate the new address range list to apply to the pool.
-/// If no changes are needed, the return value is None.
-pub(crate) fn calculate_changed_ranges(
-    current: &[MetalLbAddressRange],
+/// Calculate the IPv6 address range that should be present in the pool for a given prefix
+pub(crate) fn desired_range(
     prefix_net: Ipv6Net,
     host_range: V6HostRange,
     subnet_override: Option<SubnetOverride>,
-) -> Option<Vec<MetalLbAddressRange>> {
+) -> MetalLbAddressRange {
     let prefix_net = subnet_override.map_or(prefix_net, |ovr| ovr.apply(prefix_net));
+    MetalLbAddressRange::V6Range(V6Range::from_host_range(prefix_net, host_range))
+}
+
+/// The prefix of a single uplink, along with the settings used to build its address range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct UplinkPrefix {
+    pub(crate) host_range: V6HostRange,
+    pub(crate) subnet_override: Option<SubnetOverride>,
+    /// Current prefix of the uplink, or None if it is temporarily unavailable.
+    /// The existing range of an unavailable uplink is kept in the pool.
+    pub(crate) prefix: Option<Ipv6Net>,
+}
 
-    let desired_v6_range =
-        MetalLbAddressRange::V6Range(V6Range::from_host_range(prefix_net, host_range));
-    info!(msg = "Desired address range", range = ?desired_v6_range);
-
-    let mut desired_ranges = current
-        .iter()
-        .filter(|r| {
-            // Remove any pre-existing IPv6 address ranges
-            !matches!(
-                r,
-                MetalLbAddressRange::V6Cidr(_) | MetalLbAddressRange::V6Range(_)
-            )
+/// The address ranges to write to the pool
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct DesiredRanges {
+    /// All ranges of the pool
+    pub(crate) ranges: Vec<MetalLbAddressRange>,
+    /// The subset of ranges that is owned by metallb-dyn6
+    pub(crate) managed: Vec<MetalLbAddressRange>,
+}
+
+/// Calculate the new address range list to apply to the pool, with one range per uplink.
+/// Only ranges owned by metallb-dyn6 are touched, all other ranges are left as-is.
+/// Owned ranges of uplinks that are not listed are removed.
+/// If no changes are needed, the return value is None.
+pub(crate) fn calculate_changed_ranges(
+    current: &PoolAddresses,
+    uplinks: &[UplinkPrefix],
+) -> Option<DesiredRanges> {
+    let is_managed = |r: &MetalLbAddressRange| match &current.managed {
+        Some(managed) => managed.contains(r),
+        // Pools without ownership information: claim the ranges matching the host range of an uplink
+        None => matches!(r, MetalLbAddressRange::V6Range(v6r) if uplinks
+            .iter()
+            .any(|u| v6r.has_host_range(u.host_range))),
+    };
+
+    let (owned, mut desired_ranges): (Vec<_>, Vec<_>) =
+        current.ranges.iter().cloned().partition(is_managed);
+
+    // Keep the ranges of temporarily unavailable uplinks
+    let mut managed = owned
+        .into_iter()
+        .filter(|r| match r {
+            MetalLbAddressRange::V6Range(v6r) => uplinks
+                .iter()
+                .any(|u| u.prefix.is_none() && v6r.has_host_range(u.host_range)),
+            _ => false,
         })
-        .cloned()
         .collect::<Vec<_>>();
-    desired_ranges.push(desired_v6_range);
+
+    for uplink in uplinks {
+        let Some(prefix_net) = uplink.prefix else {
+            continue;
+        };
+        let desired_v6_range = desired_range(prefix_net, uplink.host_range, uplink.subnet_override);
+        info!(msg = "Desired address range", range = ?desired_v6_range);
+        managed.push(desired_v6_range);
+    }
+    desired_ranges.extend(managed.iter().cloned());
     info!(
         desired_ranges = ?desired_ranges
     );
 
-    if HashSet::<_, RandomState>::from_iter(&desired_ranges) == HashSet::from_iter(current) {
+    let ranges_match = HashSet::<_, RandomState>::from_iter(&desired_ranges)
+        == HashSet::from_iter(&current.ranges);
+    let managed_match = current
+        .managed
+        .as_ref()
+        .is_some_and(|m| HashSet::<_, RandomState>::from_iter(m) == HashSet::from_iter(&managed));
+    if ranges_match && managed_match {
         None
     } else {
-        Some(desired_ranges)
+        Some(DesiredRanges {
+            ranges: desired_ranges,
+            managed,
+        })
     }
 }
 
@@ -64,104 +118,268 @@ mod tests {
             *DESIRED_HOST_RANGE,
         ))
     });
+    static DESIRED_UPLINK: LazyLock<UplinkPrefix> = LazyLock::new(|| UplinkPrefix {
+        host_range: *DESIRED_HOST_RANGE,
+        subnet_override: None,
+        prefix: Some(DESIRED_PREFIX_NET),
+    });
     static V4_RANGE: MetalLbAddressRange =
         MetalLbAddressRange::V4Cidr(Ipv4Net::new_assert(Ipv4Addr::new(10, 0, 0, 0), 24));
+    static OLD_V6_RANGE: LazyLock<MetalLbAddressRange> = LazyLock::new(|| {
+        MetalLbAddressRange::V6Range(V6Range::from_host_range(
+            Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0xd00f, 0xffff, 0, 0, 0, 0), 64),
+            *DESIRED_HOST_RANGE,
+        ))
+    });
+    static STATIC_ULA_RANGE: LazyLock<MetalLbAddressRange> =
+        LazyLock::new(|| "fd00:aaaa::/112".parse().unwrap());
+
+    fn pool(
+        ranges: Vec<MetalLbAddressRange>,
+        managed: Option<Vec<MetalLbAddressRange>>,
+    ) -> PoolAddresses {
+        PoolAddresses {
+            ranges,
+            managed,
+            resource_version: None,
+        }
+    }
+
+    fn calculate(
+        current: &PoolAddresses,
+        uplinks: &[UplinkPrefix],
+    ) -> Option<(
+        HashSet<MetalLbAddressRange, RandomState>,
+        HashSet<MetalLbAddressRange, RandomState>,
+    )> {
+        calculate_changed_ranges(current, uplinks)
+            .map(|d| (HashSet::from_iter(d.ranges), HashSet::from_iter(d.managed)))
+    }
 
     #[test]
     fn missing_desired_range_gets_added() {
-        let current = vec![V4_RANGE];
-        let calculated =
-            calculate_changed_ranges(&current, DESIRED_PREFIX_NET, *DESIRED_HOST_RANGE, None)
-                .map(HashSet::<_, RandomState>::from_iter);
+        let current = pool(vec![V4_RANGE], Some(vec![]));
         assert_eq!(
-            calculated,
-            Some(HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]))
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
         );
     }
 
     #[test]
     fn desired_range_prefix_gets_updated() {
-        let current = vec![
-            V4_RANGE,
-            MetalLbAddressRange::V6Range(V6Range::from_host_range(
-                Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0xd00f, 0xffff, 0, 0, 0, 0), 64),
-                *DESIRED_HOST_RANGE,
-            )),
-        ];
-        let calculated =
-            calculate_changed_ranges(&current, DESIRED_PREFIX_NET, *DESIRED_HOST_RANGE, None)
-                .map(HashSet::<_, RandomState>::from_iter);
+        let current = pool(vec![V4_RANGE, *OLD_V6_RANGE], Some(vec![*OLD_V6_RANGE]));
         assert_eq!(
-            calculated,
-            Some(HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]))
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
         );
     }
 
     #[test]
     fn desired_range_hosts_range_gets_update() {
-        let current = vec![
-            V4_RANGE,
-            MetalLbAddressRange::V6Range(V6Range::from_host_range(
-                DESIRED_PREFIX_NET,
-                "::2000-::2100".parse().unwrap(),
-            )),
-        ];
-        let calculated =
-            calculate_changed_ranges(&current, DESIRED_PREFIX_NET, *DESIRED_HOST_RANGE, None)
-                .map(HashSet::<_, RandomState>::from_iter);
+        let old_range = MetalLbAddressRange::V6Range(V6Range::from_host_range(
+            DESIRED_PREFIX_NET,
+            "::2000-::2100".parse().unwrap(),
+        ));
+        let current = pool(vec![V4_RANGE, old_range], Some(vec![old_range]));
         assert_eq!(
-            calculated,
-            Some(HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]))
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
         );
     }
 
     #[test]
     fn matching_desired_range_recognized() {
-        let current = vec![V4_RANGE, *DESIRED_V6_RANGE];
-        let calculated =
-            calculate_changed_ranges(&current, DESIRED_PREFIX_NET, *DESIRED_HOST_RANGE, None)
-                .map(HashSet::<_, RandomState>::from_iter);
-        assert_eq!(calculated, None);
+        let current = pool(
+            vec![V4_RANGE, *DESIRED_V6_RANGE],
+            Some(vec![*DESIRED_V6_RANGE]),
+        );
+        assert_eq!(calculate(&current, &[*DESIRED_UPLINK]), None);
+    }
+
+    #[test]
+    fn extra_managed_v6_prefixes_removed() {
+        let extra_range = MetalLbAddressRange::V6Range(V6Range::from_host_range(
+            Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0x4405, 0x417, 0, 0, 0, 0), 64),
+            "::2000-::2100".parse().unwrap(),
+        ));
+        let current = pool(
+            vec![V4_RANGE, *DESIRED_V6_RANGE, *OLD_V6_RANGE, extra_range],
+            Some(vec![*DESIRED_V6_RANGE, *OLD_V6_RANGE, extra_range]),
+        );
+        assert_eq!(
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
+        );
+    }
+
+    #[test]
+    fn unmanaged_v6_ranges_kept() {
+        let static_gua = MetalLbAddressRange::V6Range(V6Range::from_host_range(
+            Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0x4405, 0x417, 0, 0, 0, 0), 64),
+            *DESIRED_HOST_RANGE,
+        ));
+        let current = pool(
+            vec![V4_RANGE, *STATIC_ULA_RANGE, static_gua, *OLD_V6_RANGE],
+            Some(vec![*OLD_V6_RANGE]),
+        );
+        assert_eq!(
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![
+                    V4_RANGE,
+                    *STATIC_ULA_RANGE,
+                    static_gua,
+                    *DESIRED_V6_RANGE
+                ]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
+        );
+    }
+
+    #[test]
+    fn unannotated_pool_claims_matching_host_range() {
+        let other_range = MetalLbAddressRange::V6Range(V6Range::from_host_range(
+            Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0x4405, 0x417, 0, 0, 0, 0), 64),
+            "::2000-::2100".parse().unwrap(),
+        ));
+        let current = pool(
+            vec![V4_RANGE, *STATIC_ULA_RANGE, other_range, *OLD_V6_RANGE],
+            None,
+        );
+        assert_eq!(
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![
+                    V4_RANGE,
+                    *STATIC_ULA_RANGE,
+                    other_range,
+                    *DESIRED_V6_RANGE
+                ]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
+        );
+    }
+
+    #[test]
+    fn missing_annotation_gets_added() {
+        let current = pool(vec![V4_RANGE, *DESIRED_V6_RANGE], None);
+        assert_eq!(
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
+        );
+    }
+
+    static SECOND_PREFIX_NET: Ipv6Net =
+        Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0xaaaa, 0xbbbb, 0, 0, 0, 0), 64);
+    static SECOND_HOST_RANGE: LazyLock<V6HostRange> =
+        LazyLock::new(|| "::2000-::2100".parse().unwrap());
+
+    fn second_uplink(prefix: Option<Ipv6Net>) -> UplinkPrefix {
+        UplinkPrefix {
+            host_range: *SECOND_HOST_RANGE,
+            subnet_override: None,
+            prefix,
+        }
+    }
+
+    fn second_v6_range(prefix: Ipv6Net) -> MetalLbAddressRange {
+        MetalLbAddressRange::V6Range(V6Range::from_host_range(prefix, *SECOND_HOST_RANGE))
     }
 
     #[test]
-    fn extra_v6_prefixes_removed() {
-        let current = vec![
-            V4_RANGE,
-            MetalLbAddressRange::V6Range(V6Range::from_host_range(
-                Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0xd00f, 0xffff, 0, 0, 0, 0), 64),
-                *DESIRED_HOST_RANGE,
-            )),
-            MetalLbAddressRange::V6Range(V6Range::from_host_range(
-                Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0x4405, 0x417, 0, 0, 0, 0), 64),
-                "::2000-::2100".parse().unwrap(),
-            )),
-        ];
-        let calculated =
-            calculate_changed_ranges(&current, DESIRED_PREFIX_NET, *DESIRED_HOST_RANGE, None)
-                .map(HashSet::<_, RandomState>::from_iter);
+    fn one_range_per_uplink() {
+        let current = pool(vec![V4_RANGE], Some(vec![]));
         assert_eq!(
-            calculated,
-            Some(HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]))
+            calculate(
+                &current,
+                &[*DESIRED_UPLINK, second_uplink(Some(SECOND_PREFIX_NET))]
+            ),
+            Some((
+                HashSet::from_iter(vec![
+                    V4_RANGE,
+                    *DESIRED_V6_RANGE,
+                    second_v6_range(SECOND_PREFIX_NET)
+                ]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE, second_v6_range(SECOND_PREFIX_NET)])
+            ))
         );
     }
 
     #[test]
-    fn matching_range_extra_v6_prefixes_removed() {
-        let current = vec![
-            V4_RANGE,
-            *DESIRED_V6_RANGE,
-            MetalLbAddressRange::V6Range(V6Range::from_host_range(
-                Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0x4405, 0x417, 0, 0, 0, 0), 64),
-                "::2000-::2100".parse().unwrap(),
-            )),
-        ];
-        let calculated =
-            calculate_changed_ranges(&current, DESIRED_PREFIX_NET, *DESIRED_HOST_RANGE, None)
-                .map(HashSet::<_, RandomState>::from_iter);
+    fn uplinks_updated_independently() {
+        let old_second = second_v6_range(Ipv6Net::new_assert(
+            Ipv6Addr::new(0x2001, 0xdb8, 0x4405, 0x417, 0, 0, 0, 0),
+            64,
+        ));
+        let current = pool(
+            vec![V4_RANGE, *DESIRED_V6_RANGE, old_second],
+            Some(vec![*DESIRED_V6_RANGE, old_second]),
+        );
+        assert_eq!(
+            calculate(
+                &current,
+                &[*DESIRED_UPLINK, second_uplink(Some(SECOND_PREFIX_NET))]
+            ),
+            Some((
+                HashSet::from_iter(vec![
+                    V4_RANGE,
+                    *DESIRED_V6_RANGE,
+                    second_v6_range(SECOND_PREFIX_NET)
+                ]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE, second_v6_range(SECOND_PREFIX_NET)])
+            ))
+        );
+    }
+
+    #[test]
+    fn unavailable_uplink_range_kept() {
+        let managed = vec![*DESIRED_V6_RANGE, second_v6_range(SECOND_PREFIX_NET)];
+        let current = pool(
+            vec![
+                V4_RANGE,
+                *DESIRED_V6_RANGE,
+                second_v6_range(SECOND_PREFIX_NET),
+            ],
+            Some(managed),
+        );
+        assert_eq!(
+            calculate(&current, &[*DESIRED_UPLINK, second_uplink(None)]),
+            None
+        );
+    }
+
+    #[test]
+    fn removed_uplink_range_removed() {
+        let managed = vec![*DESIRED_V6_RANGE, second_v6_range(SECOND_PREFIX_NET)];
+        let current = pool(
+            vec![
+                V4_RANGE,
+                *DESIRED_V6_RANGE,
+                second_v6_range(SECOND_PREFIX_NET),
+            ],
+            Some(managed),
+        );
         assert_eq!(
-            calculated,
-            Some(HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]))
+            calculate(&current, &[*DESIRED_UPLINK]),
+            Some((
+                HashSet::from_iter(vec![V4_RANGE, *DESIRED_V6_RANGE]),
+                HashSet::from_iter(vec![*DESIRED_V6_RANGE])
+            ))
         );
     }
 }
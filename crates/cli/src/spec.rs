@@ -0,0 +1,103 @@
+use std::net::Ipv6Addr;
+
+use metallb_dyn6_k8s::ranges::V6HostRange;
+
+use crate::subnet_override::SubnetOverride;
+
+/// The range settings shared by --uplink and --pool values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RangeOptions {
+    pub(crate) host_range: Option<V6HostRange>,
+    pub(crate) subnet_override: Option<SubnetOverride>,
+}
+
+/// Parse a comma-separated list of key=value pairs, such as `name=isp1,host-range=::1000-::1999`.
+/// The `host-range`, `subnet-override` and `prefix-length` keys are parsed into the returned options,
+/// all other keys are passed to `other`, which rejects unknown ones.
+pub(crate) fn parse_spec(
+    s: &str,
+    mut other: impl FnMut(&str, &str) -> Result<(), String>,
+) -> Result<RangeOptions, String> {
+    let (mut host_range, mut subnet, mut prefix_length) = (None, None, None);
+    for pair in s.split(',') {
+        let Some((key, value)) = pair.split_once('=') else {
+            return Err(format!("Expected key=value, got {pair}"));
+        };
+        match key.trim() {
+            "host-range" => {
+                host_range = Some(
+                    value
+                        .parse::<V6HostRange>()
+                        .map_err(|e| format!("Invalid host-range {value}: {e}"))?,
+                )
+            }
+            "subnet-override" => {
+                subnet = Some(
+                    value
+                        .parse::<Ipv6Addr>()
+                        .map_err(|e| format!("Invalid subnet-override {value}: {e}"))?,
+                )
+            }
+            "prefix-length" => {
+                prefix_length = Some(
+                    value
+                        .parse::<u8>()
+                        .map_err(|e| format!("Invalid prefix-length {value}: {e}"))?,
+                )
+            }
+            key => other(key, value)?,
+        }
+    }
+
+    let subnet_override = match (subnet, prefix_length) {
+        (Some(or), Some(len)) => Some(SubnetOverride::new(or, len).map_err(|e| e.to_string())?),
+        (None, None) => None,
+        _ => return Err("subnet-override and prefix-length must be set together".to_string()),
+    };
+    Ok(RangeOptions {
+        host_range,
+        subnet_override,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let mut names = Vec::new();
+        let options = parse_spec(
+            "name=isp1,host-range=::1000-::1999,subnet-override=0:0:0:cd::,prefix-length=56",
+            |key, value| match key {
+                "name" => {
+                    names.push(value.to_string());
+                    Ok(())
+                }
+                other => Err(format!("Unknown key {other}")),
+            },
+        )
+        .unwrap();
+        assert_eq!(names, ["isp1"]);
+        assert_eq!(
+            options,
+            RangeOptions {
+                host_range: Some("::1000-::1999".parse().unwrap()),
+                subnet_override: Some(
+                    SubnetOverride::new("0:0:0:cd::".parse().unwrap(), 56).unwrap()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_spec_errors() {
+        let reject = |key: &str, _: &str| Err(format!("Unknown key {key}"));
+        assert_eq!(
+            parse_spec("color=blue", reject),
+            Err("Unknown key color".to_string())
+        );
+        assert!(parse_spec("host-range", reject).is_err());
+        assert!(parse_spec("subnet-override=0:0:0:cd::", reject).is_err());
+    }
+}
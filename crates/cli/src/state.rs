@@ -0,0 +1,112 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ipnet::Ipv6Net;
+use serde::{Deserialize, Serialize};
+
+/// Record of the last prefix that was successfully applied to the pool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AppliedPrefix {
+    /// Name of the source that returned the prefix
+    pub(crate) source: String,
+    /// Prefix as returned by the source, before any subnet override
+    pub(crate) source_prefix: Ipv6Net,
+    /// Address ranges that were written, keyed by pool name
+    pub(crate) ranges: BTreeMap<String, String>,
+    pub(crate) applied_at: DateTime<Utc>,
+}
+
+/// Last applied prefix of each uplink, keyed by uplink name
+pub(crate) type AppliedPrefixes = BTreeMap<String, AppliedPrefix>;
+
+/// Persists the last applied prefixes in a local JSON file, so that they survive restarts
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct StateCache {
+    path: PathBuf,
+}
+
+impl StateCache {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        StateCache { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the last applied prefixes. Returns an empty map if nothing has been recorded yet.
+    pub(crate) fn load(&self) -> Result<AppliedPrefixes> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(AppliedPrefixes::new()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Could not read state file {}", self.path.display()))
+            }
+        };
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid state file {}", self.path.display()))
+    }
+
+    pub(crate) fn store(&self, applied: &AppliedPrefixes) -> Result<()> {
+        // Write to a temporary file first so that the state file is never left half-written
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(applied)?)
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .with_context(|| format!("Could not write state file {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn missing_file_is_empty_state() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = StateCache::new(dir.path().join("state.json"));
+        assert_eq!(cache.load()?, AppliedPrefixes::new());
+        Ok(())
+    }
+
+    #[test]
+    fn stored_prefixes_are_loaded() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = StateCache::new(dir.path().join("state.json"));
+        let applied = AppliedPrefixes::from([(
+            "default".to_string(),
+            AppliedPrefix {
+                source: "my-ip".to_string(),
+                source_prefix: Ipv6Net::new_assert(
+                    Ipv6Addr::new(0x2001, 0xdb8, 0xdead, 0xbeef, 0, 0, 0, 0),
+                    64,
+                ),
+                ranges: BTreeMap::from([(
+                    "public".to_string(),
+                    "2001:db8:dead:beef::1000-2001:db8:dead:beef::1999".to_string(),
+                )]),
+                applied_at: Utc::now(),
+            },
+        )]);
+        cache.store(&applied)?;
+        assert_eq!(cache.load()?, applied);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_file_errors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        fs::write(&path, "not json")?;
+        StateCache::new(path).load().unwrap_err();
+        Ok(())
+    }
+}
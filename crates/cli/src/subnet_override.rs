@@ -1,6 +1,6 @@
-use std::net::Ipv6Addr;
+use std::{net::Ipv6Addr, str::FromStr};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use ipnet::Ipv6Net;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -36,6 +36,25 @@ impl SubnetOverride {
     }
 }
 
+/// Parses the `<subnet>/<prefix length>` form, such as `0:0:0:cd::/56`
+impl FromStr for SubnetOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (subnet, prefix_length) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Expected <subnet>/<prefix length>, got {s}"))?;
+        SubnetOverride::new(
+            subnet
+                .parse()
+                .with_context(|| format!("Invalid subnet {subnet}"))?,
+            prefix_length
+                .parse()
+                .with_context(|| format!("Invalid prefix length {prefix_length}"))?,
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -64,4 +83,15 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn parses_subnet_override() -> Result<()> {
+        assert_eq!(
+            "0:0:0:cd::/56".parse::<SubnetOverride>()?,
+            SubnetOverride::new(Ipv6Addr::new(0, 0, 0, 0xcd, 0, 0, 0, 0), 56)?
+        );
+        "0:0:0:cd::".parse::<SubnetOverride>().unwrap_err();
+        "0:0:0:cd::/64".parse::<SubnetOverride>().unwrap_err();
+        Ok(())
+    }
 }
@@ -0,0 +1,163 @@
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use metallb_dyn6_k8s::ranges::V6HostRange;
+use metallb_dyn6_sources::NetworkSource;
+
+use crate::{cli, spec::parse_spec, subnet_override::SubnetOverride};
+
+/// Name of the uplink configured through the top-level --source and HOST_RANGE arguments
+pub(crate) const DEFAULT_UPLINK: &str = "default";
+
+/// Configuration of a single additional uplink, as passed via --uplink.
+/// The value is a comma-separated list of key=value pairs, for example:
+/// name=isp1,source=mqtt,mqtt-topic=router/wan1/prefix,host-range=::1000-::1999
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UplinkSpec {
+    pub(crate) name: String,
+    pub(crate) source: cli::NetworkSource,
+    /// Host range of this uplinks range in the default pool.
+    /// Only required if a default pool is configured, discovered pools set their own host range.
+    pub(crate) host_range: Option<V6HostRange>,
+    pub(crate) subnet_override: Option<SubnetOverride>,
+    /// STUN servers for this uplink, uses the global --stun-server if empty
+    pub(crate) stun_servers: Vec<String>,
+    /// MQTT topic for this uplink, uses the global --mqtt-topic if unset
+    pub(crate) mqtt_topic: Option<String>,
+}
+
+impl FromStr for UplinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut name, mut source) = (None, None);
+        let (mut stun_servers, mut mqtt_topic) = (Vec::new(), None);
+        let options = parse_spec(s, |key, value| {
+            match key {
+                "name" => name = Some(value.to_string()),
+                "source" => source = Some(cli::NetworkSource::from_str(value, true)?),
+                "stun-server" => stun_servers.push(value.to_string()),
+                "mqtt-topic" => mqtt_topic = Some(value.to_string()),
+                other => return Err(format!("Unknown uplink key {other}")),
+            }
+            Ok(())
+        })?;
+
+        Ok(UplinkSpec {
+            name: name.ok_or("Missing uplink name")?,
+            source: source.ok_or("Missing uplink source")?,
+            host_range: options.host_range,
+            subnet_override: options.subnet_override,
+            stun_servers,
+            mqtt_topic,
+        })
+    }
+}
+
+/// Ensure that uplinks can be told apart by their name
+pub(crate) fn validate_uplinks(specs: &[UplinkSpec]) -> Result<()> {
+    let mut names = HashSet::new();
+    for spec in specs {
+        if !names.insert(&spec.name) {
+            bail!("Duplicate uplink name {}", spec.name);
+        }
+    }
+    Ok(())
+}
+
+/// A single uplink with its own prefix source
+#[derive(Debug)]
+pub(crate) struct Uplink {
+    pub(crate) name: String,
+    pub(crate) source: Box<dyn NetworkSource>,
+    pub(crate) source_name: String,
+    /// Number of consecutive failed prefix lookups
+    failures: AtomicU32,
+}
+
+impl Uplink {
+    pub(crate) fn new(spec: &UplinkSpec, source: Box<dyn NetworkSource>) -> Self {
+        Uplink {
+            name: spec.name.clone(),
+            source,
+            source_name: source_name(spec.source),
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Record the outcome of a lookup, returning the number of consecutive failures
+    pub(crate) fn record_lookup(&self, success: bool) -> u32 {
+        if success {
+            self.failures.store(0, Ordering::Relaxed);
+            0
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed) + 1
+        }
+    }
+}
+
+pub(crate) fn source_name(source: cli::NetworkSource) -> String {
+    source
+        .to_possible_value()
+        .map_or_else(|| format!("{source:?}"), |v| v.get_name().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn parses_uplink_spec() {
+        let spec = "name=isp1,source=mqtt,mqtt-topic=wan1,host-range=::1000-::1999,subnet-override=0:0:0:cd::,prefix-length=56"
+            .parse::<UplinkSpec>()
+            .unwrap();
+        assert_eq!(
+            spec,
+            UplinkSpec {
+                name: "isp1".to_string(),
+                source: cli::NetworkSource::Mqtt,
+                host_range: Some("::1000-::1999".parse().unwrap()),
+                subnet_override: Some(
+                    SubnetOverride::new(Ipv6Addr::new(0, 0, 0, 0xcd, 0, 0, 0, 0), 56).unwrap()
+                ),
+                stun_servers: vec![],
+                mqtt_topic: Some("wan1".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_uplink_spec() {
+        "source=stun,host-range=::1-::2"
+            .parse::<UplinkSpec>()
+            .unwrap_err();
+        "name=isp1,host-range=::1-::2"
+            .parse::<UplinkSpec>()
+            .unwrap_err();
+        "name=isp1,source=stun,host-range=::1-::2,prefix-length=56"
+            .parse::<UplinkSpec>()
+            .unwrap_err();
+        "name=isp1,source=stun,host-range=::1-::2,foo=bar"
+            .parse::<UplinkSpec>()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn rejects_duplicate_uplinks() {
+        let isp1 = "name=isp1,source=stun,host-range=::1-::2"
+            .parse::<UplinkSpec>()
+            .unwrap();
+        let isp2 = "name=isp2,source=stun,host-range=::1-::2"
+            .parse::<UplinkSpec>()
+            .unwrap();
+        validate_uplinks(&[isp1.clone(), isp1.clone()]).unwrap_err();
+        validate_uplinks(&[isp1, isp2]).unwrap();
+    }
+}
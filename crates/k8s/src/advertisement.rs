@@ -0,0 +1,169 @@
+use std::fmt;
+
+use ipnet::Ipv6Net;
+use serde_json::Value;
+
+use crate::{
+    v1beta1::{bgpadvertisement::BGPAdvertisementSpec, l2advertisement::L2AdvertisementSpec},
+    K8sError,
+};
+
+/// Annotation on a BGPAdvertisement or L2Advertisement with a JSON template for the parts of its spec
+/// that depend on the prefix, such as `{"communities": ["65000:{prefix_len}"]}`
+pub const SPEC_TEMPLATE_ANNOTATION: &str = "dyn6.spacebird.dev/spec-template";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AdvertisementKind {
+    Bgp,
+    L2,
+}
+
+impl fmt::Display for AdvertisementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvertisementKind::Bgp => write!(f, "BGPAdvertisement"),
+            AdvertisementKind::L2 => write!(f, "L2Advertisement"),
+        }
+    }
+}
+
+/// A MetalLB advertisement that carries the [SPEC_TEMPLATE_ANNOTATION], along with its unparsed dyn6 annotations
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AdvertisementTemplate {
+    pub kind: AdvertisementKind,
+    pub name: String,
+    pub template: String,
+    pub uplink: Option<String>,
+}
+
+/// New values for some fields of an advertisement spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdvertisementUpdate {
+    pub kind: AdvertisementKind,
+    pub name: String,
+    pub spec: Value,
+}
+
+impl AdvertisementTemplate {
+    /// Fill in the template for the network that the ranges of the uplink live in. The following placeholders are replaced:
+    /// `{prefix}` with the network address, `{prefix_len}` with the prefix length
+    /// and `{prefix_net}` with the network in CIDR notation.
+    pub fn render(&self, prefix: Ipv6Net) -> Result<AdvertisementUpdate, K8sError> {
+        let rendered = self
+            .template
+            .replace("{prefix_net}", &prefix.trunc().to_string())
+            .replace("{prefix_len}", &prefix.prefix_len().to_string())
+            .replace("{prefix}", &prefix.network().to_string());
+        let spec = serde_json::from_str::<Value>(&rendered).map_err(|e| self.invalid(e))?;
+        if !spec.is_object() {
+            return Err(self.invalid("the template must be a JSON object"));
+        }
+        // Check field types against the CRD, all fields of the specs are optional
+        match self.kind {
+            AdvertisementKind::Bgp => {
+                serde_json::from_value::<BGPAdvertisementSpec>(spec.clone()).map(|_| ())
+            }
+            AdvertisementKind::L2 => {
+                serde_json::from_value::<L2AdvertisementSpec>(spec.clone()).map(|_| ())
+            }
+        }
+        .map_err(|e| self.invalid(e))?;
+
+        Ok(AdvertisementUpdate {
+            kind: self.kind,
+            name: self.name.clone(),
+            spec,
+        })
+    }
+
+    fn invalid(&self, e: impl fmt::Display) -> K8sError {
+        K8sError::new(format!(
+            "Invalid spec template on {} {}: {}",
+            self.kind, self.name, e
+        ))
+    }
+}
+
+/// Build the spec fields that restore the fields set by `patch` to their values in `original`.
+/// Fields that did not exist in `original` are null.
+pub(crate) fn revert_patch(original: &Value, patch: &Value) -> Value {
+    match patch {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| {
+                    let original = original.get(k).unwrap_or(&Value::Null);
+                    let reverted = match (original, v) {
+                        (Value::Object(_), Value::Object(_)) => revert_patch(original, v),
+                        _ => original.clone(),
+                    };
+                    (k.clone(), reverted)
+                })
+                .collect(),
+        ),
+        _ => original.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn template(kind: AdvertisementKind, template: &str) -> AdvertisementTemplate {
+        AdvertisementTemplate {
+            kind,
+            name: "public".to_string(),
+            template: template.to_string(),
+            uplink: None,
+        }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let update = template(
+            AdvertisementKind::Bgp,
+            r#"{"aggregationLengthV6": {prefix_len}, "communities": ["65000:100"], "peers": ["{prefix}", "{prefix_net}"]}"#,
+        )
+        .render("2001:db8:aaaa:cd::/64".parse().unwrap())
+        .unwrap();
+        assert_eq!(
+            update.spec,
+            json!({
+                "aggregationLengthV6": 64,
+                "communities": ["65000:100"],
+                "peers": ["2001:db8:aaaa:cd::", "2001:db8:aaaa:cd::/64"],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let prefix = "2001:db8:aaaa:cd::/64".parse().unwrap();
+        assert!(template(AdvertisementKind::L2, "[]")
+            .render(prefix)
+            .is_err());
+        assert!(template(AdvertisementKind::L2, r#"{"interfaces": "eth0"}"#)
+            .render(prefix)
+            .is_err());
+        assert!(
+            template(AdvertisementKind::L2, r#"{"interfaces": ["eth0"]}"#)
+                .render(prefix)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn revert_patch_restores_original_fields() {
+        let original = json!({
+            "aggregationLengthV6": 64,
+            "nodeSelectors": [{ "matchLabels": { "uplink": "isp1" } }],
+        });
+        let patch = json!({ "aggregationLengthV6": 48, "communities": ["65000:48"] });
+        assert_eq!(
+            revert_patch(&original, &patch),
+            json!({ "aggregationLengthV6": 64, "communities": null })
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::{fmt, fmt::Debug, ops::Deref};
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    runtime::events::{Event, EventType, Recorder, Reporter},
+    Api, Client,
+};
+use serde::de::DeserializeOwned;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    updater::conflict, AdvertisementTemplate, AdvertisementUpdate, AnnotatedPool, K8sError,
+    PoolAddresses, PoolUpdate, FIELD_MANAGER,
+};
+
+/// A [PoolBackend] manages the address pools of a load balancer implementation, such as MetalLB or Cilium LB-IPAM.
+/// Address ranges are exchanged as [crate::ranges::MetalLbAddressRange]s and converted to the format of the backend.
+#[async_trait]
+pub trait PoolBackend: Send + Debug + Sync {
+    /// List all pools that are annotated for management by metallb-dyn6
+    async fn discover_pools(&self) -> Result<Vec<AnnotatedPool>, K8sError>;
+
+    /// Read the current address ranges of a pool
+    async fn get_addresses(&self, pool: &str) -> Result<PoolAddresses, K8sError>;
+
+    /// Replace the address ranges of one or more pools, along with the rendered advertisements.
+    /// If any pool can not be updated, all pools are reverted to their original state.
+    /// If a pool was modified since its addresses were read, the error is a conflict (see [K8sError::is_conflict]).
+    async fn set_addresses(
+        &self,
+        updates: Vec<PoolUpdate>,
+        advertisements: Vec<AdvertisementUpdate>,
+    ) -> Result<(), K8sError>;
+
+    /// List all advertisements whose spec is rendered from a template.
+    /// Backends without advertisements have none.
+    async fn discover_advertisements(&self) -> Result<Vec<AdvertisementTemplate>, K8sError> {
+        Ok(Vec::new())
+    }
+
+    /// Delete the draining pools whose grace period is over.
+    /// Backends without draining pools have nothing to do.
+    async fn remove_expired_draining_pools(&self) -> Result<(), K8sError> {
+        Ok(())
+    }
+}
+
+/// Publishes Events as metallb-dyn6.
+/// Wraps the [Recorder], which does not implement Debug, so that the structs holding it can derive it.
+#[derive(Clone)]
+pub(crate) struct EventRecorder(Recorder);
+
+impl EventRecorder {
+    pub(crate) fn new(client: Client) -> Self {
+        EventRecorder(Recorder::new(
+            client,
+            Reporter {
+                controller: FIELD_MANAGER.to_string(),
+                instance: None,
+            },
+        ))
+    }
+}
+
+impl Deref for EventRecorder {
+    type Target = Recorder;
+
+    fn deref(&self) -> &Recorder {
+        &self.0
+    }
+}
+
+impl Debug for EventRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventRecorder").finish_non_exhaustive()
+    }
+}
+
+/// Reads and writes single pools of a backend, so that [update_pools] can update several of them
+/// and revert all of them if one fails
+#[async_trait]
+pub(crate) trait PoolStore: Send + Sync {
+    /// State of a pool before an update, which is restored on rollback
+    type Original: Send + Sync;
+    /// Changes made by an update, which are undone on rollback
+    type Applied: Default + Send + Sync;
+
+    fn recorder(&self) -> &Recorder;
+
+    /// Read the current state of a pool
+    async fn read(&self, pool: &str) -> Result<Self::Original, K8sError>;
+
+    /// The version that has to match the one the update was calculated from
+    fn resource_version(original: &Self::Original) -> Option<String>;
+
+    /// Write the update of a pool. Each change is added to `applied` as soon as it is made, also if a later one fails.
+    async fn write(
+        &self,
+        update: &PoolUpdate,
+        original: &Self::Original,
+        applied: &mut Self::Applied,
+    ) -> Result<(), K8sError>;
+
+    /// Undo the changes of an update, only where the pool still has the version written by us.
+    /// Returns whether the pool was restored, see [skip_conflict].
+    async fn revert(
+        &self,
+        original: &Self::Original,
+        applied: &Self::Applied,
+    ) -> Result<bool, K8sError>;
+
+    /// The object that rollbacks of a pool are recorded on, along with a description of the restored state
+    fn rollback_target(original: &Self::Original) -> (ObjectReference, String);
+
+    /// Clean up after all updates succeeded
+    async fn finish(&self, _applied: &[(Self::Original, Self::Applied)]) {}
+}
+
+/// Update one or more pools of a [PoolStore].
+/// If any pool can not be updated, all pools are reverted to their original state
+/// and every rollback is recorded as a Warning Event on the pool, with the error as the reason.
+/// If a pool was modified since its addresses were read, the error is a conflict (see [K8sError::is_conflict]).
+pub(crate) async fn update_pools<S: PoolStore>(
+    store: &S,
+    updates: Vec<PoolUpdate>,
+) -> Result<(), K8sError> {
+    let mut applied = Vec::new();
+    for update in updates {
+        let written = match store.read(&update.pool).await {
+            Ok(original)
+                if update.resource_version.is_some()
+                    && S::resource_version(&original) != update.resource_version =>
+            {
+                Err(conflict(&update.pool))
+            }
+            Ok(original) => {
+                let mut changes = S::Applied::default();
+                let written = store.write(&update, &original, &mut changes).await;
+                applied.push((original, changes));
+                written
+            }
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(()) => info!(msg = "Pool updated", pool = update.pool),
+            Err(e) => {
+                error!(
+                    msg = "Error while updating Pool, reverting previous Pool changes...",
+                    pool = update.pool,
+                    error = e.to_string()
+                );
+                for (original, changes) in applied.iter().rev() {
+                    if store.revert(original, changes).await? {
+                        let (reference, restored) = S::rollback_target(original);
+                        record_rollback(store.recorder(), &reference, restored, &e).await;
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+    store.finish(&applied).await;
+    Ok(())
+}
+
+/// Whether a rollback step was applied.
+/// Conflicts are logged and skipped, so that changes made by others since are kept.
+pub(crate) fn skip_conflict<T>(result: Result<T, K8sError>, pool: &str) -> Result<bool, K8sError> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.is_conflict() => {
+            warn!(
+                msg = "Pool was modified concurrently, not reverting it",
+                pool
+            );
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Publish an Event on a pool, so that the rollback shows up in `kubectl describe`
+pub(crate) async fn record_rollback(
+    recorder: &Recorder,
+    reference: &ObjectReference,
+    restored: String,
+    cause: &K8sError,
+) {
+    let event = Event {
+        type_: EventType::Warning,
+        reason: "RolledBack".to_string(),
+        note: Some(format!("{}: {}", restored, cause)),
+        action: "Rollback".to_string(),
+        secondary: None,
+    };
+    if let Err(e) = recorder.publish(&event, reference).await {
+        warn!(msg = "Could not record rollback", error = e.to_string());
+    }
+}
+
+/// Map the error of a server-side apply to `pool`, whose `fields` are owned by metallb-dyn6.
+/// Field manager conflicts can not be resolved by retrying, unlike version mismatches,
+/// so only the latter are reported as conflicts (see [K8sError::is_conflict]).
+pub(crate) fn apply_error(e: kube::Error, pool: &str, fields: &str) -> K8sError {
+    match e {
+        kube::Error::Api(ae) if ae.code == 409 && ae.message.starts_with("Apply failed") => {
+            K8sError::new(format!(
+                "Conflict while updating pool {}, the {} are managed by another field manager: {}",
+                pool, fields, ae.message
+            ))
+        }
+        kube::Error::Api(ae) if ae.code == 409 => conflict(pool),
+        e => e.into(),
+    }
+}
+
+pub(crate) async fn get_pool<K>(api: &Api<K>, name: &str) -> Result<K, K8sError>
+where
+    K: Clone + DeserializeOwned + Debug,
+{
+    api.get(name)
+        .await
+        .map(|p| {
+            debug!(pool = ?p);
+            p
+        })
+        .map_err(|e| K8sError::new(format!("Error reading pool {}: {}", name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ErrorResponse;
+
+    use super::*;
+
+    fn api_error(code: u16, message: &str) -> kube::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: message.to_string(),
+            reason: "Conflict".to_string(),
+            code,
+        })
+    }
+
+    #[test]
+    fn only_version_mismatches_are_conflicts() {
+        let field_manager = apply_error(
+            api_error(
+                409,
+                "Apply failed with 1 conflict: conflict with \"kubectl\"",
+            ),
+            "public",
+            "addresses",
+        );
+        assert!(!field_manager.is_conflict());
+        assert!(field_manager
+            .to_string()
+            .contains("the addresses are managed by another field manager"));
+
+        let version = apply_error(
+            api_error(409, "the object has been modified"),
+            "public",
+            "addresses",
+        );
+        assert!(version.is_conflict());
+        assert!(!apply_error(api_error(500, "internal"), "public", "addresses").is_conflict());
+    }
+}
@@ -0,0 +1,624 @@
+use async_trait::async_trait;
+use ipnet::IpNet;
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PostParams, Preconditions},
+    runtime::events::Recorder,
+    Api, Client, Resource, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    backend::{get_pool, skip_conflict, update_pools, EventRecorder, PoolStore},
+    patch::with_resource_version,
+    ranges::{MetalLbAddressRange, V6Range},
+    updater::{annotated_pool, conflict, managed_ranges},
+    v3::ippool::{IPPool, IPPoolSpec},
+    AdvertisementUpdate, AnnotatedPool, K8sError, PoolAddresses, PoolBackend, PoolUpdate,
+    FIELD_MANAGER, MANAGED_RANGES_ANNOTATION,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CalicoUpdaterConfig {
+    /// Names of the IPPools to manage
+    pub ip_pools: Vec<String>,
+}
+
+/// Label on the IPPools created by metallb-dyn6 with the name of the configured pool they were created for
+pub const BASE_POOL_LABEL: &str = "dyn6.spacebird.dev/base-pool";
+
+/// Manages Calico IPPools used for LoadBalancer IPAM.
+/// Calico does not allow changing the CIDR of an IPPool, so each configured (base) pool is kept as a template:
+/// for every new CIDR, a copy of it with that CIDR is created, and all other pools of the base pool are disabled.
+/// Pools that were already disabled before an update are deleted once it succeeded.
+/// Each IPPool holds a single CIDR, so the managed range must be aligned to a prefix length.
+/// Calico picks up pool changes live, so no pods have to be restarted.
+#[derive(Debug)]
+pub struct CalicoUpdater {
+    pool_api: Api<IPPool>,
+    recorder: EventRecorder,
+}
+
+/// A configured IPPool along with the pools created for it
+#[derive(Debug, Clone)]
+pub(crate) struct CalicoPools {
+    base: IPPool,
+    created: Vec<IPPool>,
+}
+
+impl CalicoPools {
+    /// The enabled pool whose CIDR is in use, or the base pool if none is enabled
+    fn active(&self) -> &IPPool {
+        std::iter::once(&self.base)
+            .chain(&self.created)
+            .find(|p| !is_disabled(p))
+            .unwrap_or(&self.base)
+    }
+}
+
+/// The changes that make the pool with a CIDR the only enabled pool of a base pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PoolPlan {
+    /// Name of the pool with the CIDR
+    target: String,
+    /// Whether the target has to be created
+    create: bool,
+    /// Pools whose `disabled` flag has to change, with its new value
+    toggle: Vec<(String, bool)>,
+    /// Created pools that were already disabled, which are deleted once the update succeeded
+    delete: Vec<String>,
+}
+
+/// The changes made to the pools of a base pool, so that they can be reverted
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AppliedPools {
+    /// Version of the base pool written by us
+    base_version: Option<String>,
+    /// Name and version of the pool created by us
+    created: Option<(String, Option<String>)>,
+    /// Pools whose `disabled` flag was changed, with its previous value and the version written by us
+    toggled: Vec<(String, bool, Option<String>)>,
+    /// Created pools that were already disabled, which are deleted once all updates succeeded
+    delete: Vec<String>,
+}
+
+impl CalicoUpdater {
+    #[instrument]
+    pub async fn new(config: CalicoUpdaterConfig) -> Result<Self, K8sError> {
+        debug!(
+            msg = "Creating k8s Client for Calico access",
+            pools = ?config.ip_pools
+        );
+        let client = Client::try_default().await?;
+
+        let updater = CalicoUpdater {
+            pool_api: Api::all(client.clone()),
+            recorder: EventRecorder::new(client),
+        };
+        info!(
+            msg = "Created k8s Client for Calico Pools",
+            pool_names = ?config.ip_pools
+        );
+        for name in &config.ip_pools {
+            let pool = get_pool(&updater.pool_api, name).await?;
+            debug!(?pool);
+        }
+        Ok(updater)
+    }
+
+    /// Read a base pool and all pools created for it
+    #[instrument(skip(self))]
+    async fn get_pools(&self, name: &str) -> Result<CalicoPools, K8sError> {
+        let base = get_pool(&self.pool_api, name).await?;
+        let created = self
+            .pool_api
+            .list(&ListParams::default().labels(&format!("{}={}", BASE_POOL_LABEL, name)))
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing pools of {}: {}", name, e)))?
+            .items;
+        Ok(CalicoPools { base, created })
+    }
+
+    /// Apply a merge patch to a pool.
+    /// If the patch has a resourceVersion, the pool is only updated if it still has this version.
+    #[instrument(skip(self))]
+    async fn patch_pool(&self, name: &str, patch: serde_json::Value) -> Result<IPPool, K8sError> {
+        debug!(patch = ?patch);
+        let params = PatchParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+        self.pool_api
+            .patch(name, &params, &Patch::Merge(&patch))
+            .await
+            .map(|p| {
+                debug!(pool = ?p);
+                p
+            })
+            .map_err(|e| match e {
+                kube::Error::Api(ae) if ae.code == 409 => conflict(name),
+                e => e.into(),
+            })
+    }
+}
+
+#[async_trait]
+impl PoolStore for CalicoUpdater {
+    type Original = CalicoPools;
+    type Applied = AppliedPools;
+
+    fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    async fn read(&self, pool: &str) -> Result<CalicoPools, K8sError> {
+        self.get_pools(pool).await
+    }
+
+    /// Version of the base pool, which carries the managed ranges
+    fn resource_version(original: &CalicoPools) -> Option<String> {
+        original.base.resource_version()
+    }
+
+    /// Make a pool with the CIDR of the update the only enabled pool of the base pool
+    /// and record the managed ranges on the base pool
+    #[instrument(skip_all, fields(pool = update.pool))]
+    async fn write(
+        &self,
+        update: &PoolUpdate,
+        pools: &CalicoPools,
+        applied: &mut AppliedPools,
+    ) -> Result<(), K8sError> {
+        let cidr = pool_cidr(update)?;
+        let plan = plan(pools, cidr);
+        debug!(plan = ?plan);
+        let managed = update
+            .managed
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+
+        let base_name = pools.base.name_any();
+        let base_disabled = plan
+            .toggle
+            .iter()
+            .find(|(name, _)| *name == base_name)
+            .map(|(_, disabled)| *disabled);
+        // The base pool is written first, so that a concurrent update fails before anything else is changed
+        let base = self
+            .patch_pool(
+                &base_name,
+                base_patch(
+                    Some(managed.join(",")),
+                    base_disabled,
+                    pools.base.resource_version(),
+                ),
+            )
+            .await?;
+        applied.base_version = base.resource_version();
+
+        if plan.create {
+            let pool = created_pool(&pools.base, cidr);
+            let params = PostParams {
+                field_manager: Some(FIELD_MANAGER.to_string()),
+                ..Default::default()
+            };
+            let created = self
+                .pool_api
+                .create(&params, &pool)
+                .await
+                .map_err(|e| match e {
+                    kube::Error::Api(ae) if ae.code == 409 => conflict(&plan.target),
+                    e => K8sError::new(format!("Error creating pool {}: {}", plan.target, e)),
+                })?;
+            info!(msg = "Created pool", pool = plan.target, %cidr);
+            applied.created = Some((plan.target.clone(), created.resource_version()));
+        }
+
+        for (name, disabled) in plan.toggle.iter().filter(|(name, _)| *name != base_name) {
+            let Some(pool) = pools.created.iter().find(|p| p.name_any() == *name) else {
+                continue;
+            };
+            let patched = self
+                .patch_pool(
+                    name,
+                    disabled_patch(Some(*disabled), pool.resource_version()),
+                )
+                .await?;
+            info!(msg = "Changed pool state", pool = name, disabled);
+            applied
+                .toggled
+                .push((name.clone(), !disabled, patched.resource_version()));
+        }
+        applied.delete = plan.delete;
+        Ok(())
+    }
+
+    /// Undo the changes made to the pools of a base pool, in reverse order.
+    /// Returns whether the base pool was restored.
+    async fn revert(
+        &self,
+        original: &CalicoPools,
+        applied: &AppliedPools,
+    ) -> Result<bool, K8sError> {
+        for (name, disabled, version) in applied.toggled.iter().rev() {
+            let reverted = self
+                .patch_pool(name, disabled_patch(Some(*disabled), version.clone()))
+                .await;
+            skip_conflict(reverted, name)?;
+        }
+        if let Some((name, version)) = &applied.created {
+            let params = DeleteParams {
+                preconditions: Some(Preconditions {
+                    resource_version: version.clone(),
+                    uid: None,
+                }),
+                ..Default::default()
+            };
+            let deleted = self
+                .pool_api
+                .delete(name, &params)
+                .await
+                .map_err(|e| match e {
+                    kube::Error::Api(ae) if ae.code == 409 => conflict(name),
+                    e => K8sError::new(format!("Error deleting pool {}: {}", name, e)),
+                });
+            skip_conflict(deleted, name)?;
+        }
+        if applied.base_version.is_none() {
+            return Ok(false);
+        }
+        let base = &original.base;
+        let original_managed = base.annotations().get(MANAGED_RANGES_ANNOTATION).cloned();
+        let reverted = self
+            .patch_pool(
+                &base.name_any(),
+                base_patch(
+                    original_managed,
+                    Some(is_disabled(base)),
+                    applied.base_version.clone(),
+                ),
+            )
+            .await;
+        skip_conflict(reverted, &base.name_any())
+    }
+
+    fn rollback_target(original: &CalicoPools) -> (ObjectReference, String) {
+        (
+            original.base.object_ref(&()),
+            format!("Restored CIDR {}", original.active().spec.cidr),
+        )
+    }
+
+    /// Delete the pools that stayed disabled.
+    /// Failures are only logged, as the updates themselves succeeded.
+    async fn finish(&self, applied: &[(CalicoPools, AppliedPools)]) {
+        for name in applied.iter().flat_map(|(_, changes)| &changes.delete) {
+            match self.pool_api.delete(name, &DeleteParams::default()).await {
+                Ok(_) => info!(msg = "Deleted disabled pool", pool = name),
+                Err(e) => warn!(
+                    msg = "Could not delete disabled pool",
+                    pool = name,
+                    error = e.to_string()
+                ),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PoolBackend for CalicoUpdater {
+    /// List all Calico pools that are annotated for management by metallb-dyn6
+    #[instrument(skip(self))]
+    async fn discover_pools(&self) -> Result<Vec<AnnotatedPool>, K8sError> {
+        let pools = self
+            .pool_api
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing pools: {}", e)))?;
+        let annotated = pools.iter().filter_map(annotated_pool).collect::<Vec<_>>();
+        debug!(pools = ?annotated);
+        Ok(annotated)
+    }
+
+    /// The CIDR of the enabled pool of a base pool, along with the managed ranges and version of the base pool
+    async fn get_addresses(&self, pool: &str) -> Result<PoolAddresses, K8sError> {
+        let pools = self.get_pools(pool).await?;
+        Ok(PoolAddresses {
+            ranges: vec![cidr_range(&pools.active().spec.cidr)?],
+            managed: managed_ranges(&pools.base)?,
+            resource_version: pools.base.metadata.resource_version,
+        })
+    }
+
+    /// Move one or more base pools to a new CIDR and record whether it is managed by metallb-dyn6.
+    /// If any pool can not be updated, all pools are reverted to their original state.
+    /// Calico has no advertisements to render, so `advertisements` is always empty.
+    async fn set_addresses(
+        &self,
+        updates: Vec<PoolUpdate>,
+        advertisements: Vec<AdvertisementUpdate>,
+    ) -> Result<(), K8sError> {
+        if !advertisements.is_empty() {
+            warn!(msg = "Calico pools have no advertisements, ignoring rendered templates");
+        }
+        update_pools(self, updates).await
+    }
+}
+
+fn is_disabled(pool: &IPPool) -> bool {
+    pool.spec.disabled.unwrap_or(false)
+}
+
+/// Plan the changes that make the pool with `cidr` the only enabled pool of a base pool.
+/// An existing pool with the CIDR is reused, which may be the base pool itself.
+fn plan(pools: &CalicoPools, cidr: IpNet) -> PoolPlan {
+    let all = std::iter::once(&pools.base).chain(&pools.created);
+    let existing = all
+        .clone()
+        .find(|p| p.spec.cidr.parse::<IpNet>().ok() == Some(cidr));
+    let target = existing.map_or_else(
+        || created_pool_name(&pools.base.name_any(), cidr),
+        |p| p.name_any(),
+    );
+    let toggle = all
+        .filter_map(|p| {
+            let disabled = p.name_any() != target;
+            (is_disabled(p) != disabled).then(|| (p.name_any(), disabled))
+        })
+        .collect();
+    let delete = pools
+        .created
+        .iter()
+        .filter(|p| is_disabled(p) && p.name_any() != target)
+        .map(|p| p.name_any())
+        .collect();
+    PoolPlan {
+        target,
+        create: existing.is_none(),
+        toggle,
+        delete,
+    }
+}
+
+/// Name of the pool created for a CIDR, such as `public-2001-db8--1000-116`
+fn created_pool_name(base: &str, cidr: IpNet) -> String {
+    format!(
+        "{}-{}",
+        base,
+        cidr.to_string().replace([':', '/', '.'], "-")
+    )
+}
+
+/// A copy of the base pool with `cidr`, labelled with the name of the base pool
+fn created_pool(base: &IPPool, cidr: IpNet) -> IPPool {
+    let mut labels = base.labels().clone();
+    labels.insert(BASE_POOL_LABEL.to_string(), base.name_any());
+    IPPool {
+        metadata: ObjectMeta {
+            name: Some(created_pool_name(&base.name_any(), cidr)),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        spec: IPPoolSpec {
+            cidr: cidr.to_string(),
+            disabled: Some(false),
+            ..base.spec.clone()
+        },
+    }
+}
+
+/// Merge patch for a base pool that sets the managed ranges annotation, removing it if `managed` is None,
+/// and the `disabled` flag if given, see [disabled_patch]
+fn base_patch(
+    managed: Option<String>,
+    disabled: Option<bool>,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let mut patch = disabled_patch(disabled, resource_version);
+    patch["metadata"]["annotations"] = json!({ MANAGED_RANGES_ANNOTATION: managed });
+    patch
+}
+
+/// Merge patch that sets the `disabled` flag of a pool if given, see [with_resource_version]
+fn disabled_patch(disabled: Option<bool>, resource_version: Option<String>) -> serde_json::Value {
+    let mut patch = json!({});
+    if let Some(disabled) = disabled {
+        patch["spec"]["disabled"] = json!(disabled);
+    }
+    with_resource_version(patch, resource_version)
+}
+
+/// The address range of a pool CIDR. IPv6 networks are returned as ranges, like the ranges built from host ranges.
+fn cidr_range(cidr: &str) -> Result<MetalLbAddressRange, K8sError> {
+    match cidr.parse::<IpNet>() {
+        Ok(IpNet::V6(net)) => Ok(MetalLbAddressRange::V6Range(V6Range::from(net))),
+        Ok(IpNet::V4(net)) => Ok(MetalLbAddressRange::V4Cidr(net)),
+        Err(e) => Err(K8sError::new(format!(
+            "Error while parsing pool CIDR {}: {}",
+            cidr, e
+        ))),
+    }
+}
+
+/// The single CIDR of an updated pool
+fn pool_cidr(update: &PoolUpdate) -> Result<IpNet, K8sError> {
+    let [range] = update.addresses.as_slice() else {
+        return Err(K8sError::new(format!(
+            "Calico pool {} can only hold a single CIDR, but {} ranges were requested",
+            update.pool,
+            update.addresses.len()
+        )));
+    };
+    let cidr = match range {
+        MetalLbAddressRange::V6Range(r) => r.to_cidr().map(IpNet::V6),
+        MetalLbAddressRange::V6Cidr(n) => Some(IpNet::V6(*n)),
+        MetalLbAddressRange::V4Cidr(n) => Some(IpNet::V4(*n)),
+        MetalLbAddressRange::V4Range(_) => None,
+    };
+    cidr.ok_or_else(|| {
+        K8sError::new(format!(
+            "Range {} of pool {} is not a CIDR, use a host range that is aligned to a prefix length such as ::1000-::1fff",
+            range, update.pool
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(addresses: &[&str]) -> PoolUpdate {
+        PoolUpdate {
+            pool: "public".to_string(),
+            addresses: addresses.iter().map(|a| a.parse().unwrap()).collect(),
+            managed: vec![],
+            resource_version: None,
+        }
+    }
+
+    fn pool(name: &str, cidr: &str, disabled: bool) -> IPPool {
+        IPPool {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            spec: IPPoolSpec {
+                allowed_uses: Some(vec!["LoadBalancer".to_string()]),
+                assignment_mode: None,
+                block_size: Some(122),
+                cidr: cidr.to_string(),
+                disable_bgp_export: None,
+                disabled: disabled.then_some(true),
+                ipip_mode: None,
+                nat_outgoing: None,
+                node_selector: None,
+                vxlan_mode: None,
+            },
+        }
+    }
+
+    #[test]
+    fn cidrs_convert_to_ranges() {
+        assert_eq!(
+            cidr_range("2001:db8::1000/116").unwrap(),
+            "2001:db8::1000-2001:db8::1fff".parse().unwrap()
+        );
+        assert_eq!(
+            cidr_range("192.0.2.0/24").unwrap(),
+            "192.0.2.0/24".parse().unwrap()
+        );
+        assert!(cidr_range("2001:db8::1000-2001:db8::1fff").is_err());
+    }
+
+    #[test]
+    fn pools_hold_a_single_aligned_range() {
+        assert_eq!(
+            pool_cidr(&update(&["2001:db8::1000-2001:db8::1fff"]))
+                .unwrap()
+                .to_string(),
+            "2001:db8::1000/116"
+        );
+        assert!(pool_cidr(&update(&["2001:db8::1000-2001:db8::1999"])).is_err());
+        assert!(pool_cidr(&update(&["2001:db8::/64", "192.0.2.0/24"])).is_err());
+    }
+
+    #[test]
+    fn first_update_creates_pool_and_disables_base_pool() {
+        let pools = CalicoPools {
+            base: pool("public", "2001:db8:aaaa::1000/116", false),
+            created: vec![],
+        };
+        let cidr = "2001:db8:bbbb::1000/116".parse().unwrap();
+        assert_eq!(
+            plan(&pools, cidr),
+            PoolPlan {
+                target: "public-2001-db8-bbbb--1000-116".to_string(),
+                create: true,
+                toggle: vec![("public".to_string(), true)],
+                delete: vec![],
+            }
+        );
+        let created = created_pool(&pools.base, cidr);
+        assert_eq!(created.name_any(), "public-2001-db8-bbbb--1000-116");
+        assert_eq!(
+            created.labels().get(BASE_POOL_LABEL).map(String::as_str),
+            Some("public")
+        );
+        assert_eq!(created.spec.cidr, "2001:db8:bbbb::1000/116");
+        assert_eq!(created.spec.disabled, Some(false));
+        assert_eq!(created.spec.block_size, Some(122));
+    }
+
+    #[test]
+    fn later_updates_disable_previous_pool_and_delete_older_ones() {
+        let pools = CalicoPools {
+            base: pool("public", "2001:db8:aaaa::1000/116", true),
+            created: vec![
+                pool(
+                    "public-2001-db8-bbbb--1000-116",
+                    "2001:db8:bbbb::1000/116",
+                    true,
+                ),
+                pool(
+                    "public-2001-db8-cccc--1000-116",
+                    "2001:db8:cccc::1000/116",
+                    false,
+                ),
+            ],
+        };
+        assert_eq!(
+            plan(&pools, "2001:db8:dddd::1000/116".parse().unwrap()),
+            PoolPlan {
+                target: "public-2001-db8-dddd--1000-116".to_string(),
+                create: true,
+                toggle: vec![("public-2001-db8-cccc--1000-116".to_string(), true)],
+                delete: vec!["public-2001-db8-bbbb--1000-116".to_string()],
+            }
+        );
+        assert_eq!(pools.active().spec.cidr, "2001:db8:cccc::1000/116");
+    }
+
+    #[test]
+    fn existing_pools_are_enabled_again() {
+        let pools = CalicoPools {
+            base: pool("public", "2001:db8:aaaa::1000/116", true),
+            created: vec![pool(
+                "public-2001-db8-bbbb--1000-116",
+                "2001:db8:bbbb::1000/116",
+                false,
+            )],
+        };
+        assert_eq!(
+            plan(&pools, "2001:db8:aaaa::1000/116".parse().unwrap()),
+            PoolPlan {
+                target: "public".to_string(),
+                create: false,
+                toggle: vec![
+                    ("public".to_string(), false),
+                    ("public-2001-db8-bbbb--1000-116".to_string(), true),
+                ],
+                delete: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn base_patch_removes_missing_annotation() {
+        assert_eq!(
+            base_patch(None, Some(true), Some("42".to_string())),
+            json!({
+                "metadata": {
+                    "annotations": { MANAGED_RANGES_ANNOTATION: null },
+                    "resourceVersion": "42",
+                },
+                "spec": { "disabled": true },
+            })
+        );
+        assert_eq!(
+            disabled_patch(Some(false), None),
+            json!({ "spec": { "disabled": false } })
+        );
+    }
+}
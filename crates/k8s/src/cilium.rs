@@ -0,0 +1,332 @@
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    runtime::events::Recorder,
+    Api, Client, Resource, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    backend::{apply_error, get_pool, skip_conflict, update_pools, EventRecorder, PoolStore},
+    patch::with_resource_version,
+    ranges::MetalLbAddressRange,
+    updater::{annotated_pool, managed_ranges, parse_ranges, takes_ownership},
+    v2alpha1::ciliumloadbalancerippool::{
+        CiliumLoadBalancerIPPool, CiliumLoadBalancerIPPoolBlocks,
+    },
+    AdvertisementUpdate, AnnotatedPool, K8sError, PoolAddresses, PoolBackend, PoolUpdate,
+    FIELD_MANAGER, MANAGED_RANGES_ANNOTATION,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CiliumUpdaterConfig {
+    /// Names of the CiliumLoadBalancerIPPools to manage
+    pub ip_pools: Vec<String>,
+    /// Take ownership of the pool blocks even if they are managed by another field manager
+    pub force_conflicts: bool,
+}
+
+/// Manages the `spec.blocks` of Cilium LB-IPAM pools.
+/// Cilium reconciles pool changes live, so no pods have to be restarted.
+#[derive(Debug)]
+pub struct CiliumUpdater {
+    config: CiliumUpdaterConfig,
+    pool_api: Api<CiliumLoadBalancerIPPool>,
+    recorder: EventRecorder,
+}
+
+impl CiliumUpdater {
+    #[instrument]
+    pub async fn new(config: CiliumUpdaterConfig) -> Result<Self, K8sError> {
+        debug!(
+            msg = "Creating k8s Client for Cilium access",
+            pools = ?config.ip_pools
+        );
+        let client = Client::try_default().await?;
+
+        let updater = CiliumUpdater {
+            config: config.clone(),
+            pool_api: Api::all(client.clone()),
+            recorder: EventRecorder::new(client),
+        };
+        info!(
+            msg = "Created k8s Client for Cilium Pools",
+            pool_names = ?config.ip_pools
+        );
+        for name in &config.ip_pools {
+            let pool = get_pool(&updater.pool_api, name).await?;
+            debug!(?pool);
+        }
+        Ok(updater)
+    }
+
+    /// Apply the pool blocks and the managed ranges annotation with server-side apply.
+    /// If `managed` is None, the annotation is removed.
+    /// If `resource_version` is set, the pool is only updated if it still has this version.
+    /// With `force`, the blocks are taken over from other field managers.
+    #[instrument(skip(self))]
+    async fn patch_pool(
+        &self,
+        name: &str,
+        blocks: Vec<serde_json::Value>,
+        managed: Option<String>,
+        resource_version: Option<String>,
+        force: bool,
+    ) -> Result<CiliumLoadBalancerIPPool, K8sError> {
+        let patch = apply_patch(name, blocks, managed, resource_version);
+        debug!(patch = ?patch);
+
+        let mut params = PatchParams::apply(FIELD_MANAGER);
+        params.force = force;
+        self.pool_api
+            .patch(name, &params, &Patch::Apply(&patch))
+            .await
+            .map(|p| {
+                debug!(pool = ?p);
+                p
+            })
+            .map_err(|e| apply_error(e, name, "blocks"))
+    }
+}
+
+#[async_trait]
+impl PoolStore for CiliumUpdater {
+    type Original = CiliumLoadBalancerIPPool;
+    /// Version written by us, None if the pool was not written
+    type Applied = Option<String>;
+
+    fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    async fn read(&self, pool: &str) -> Result<CiliumLoadBalancerIPPool, K8sError> {
+        get_pool(&self.pool_api, pool).await
+    }
+
+    fn resource_version(original: &CiliumLoadBalancerIPPool) -> Option<String> {
+        original.resource_version()
+    }
+
+    /// Replace the blocks of a pool and record which of them are managed by metallb-dyn6
+    async fn write(
+        &self,
+        update: &PoolUpdate,
+        original: &CiliumLoadBalancerIPPool,
+        applied: &mut Option<String>,
+    ) -> Result<(), K8sError> {
+        let blocks = update.addresses.iter().map(range_block).collect();
+        let managed = update
+            .managed
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>();
+        let patched = self
+            .patch_pool(
+                &update.pool,
+                blocks,
+                Some(managed.join(",")),
+                original.resource_version(),
+                takes_ownership(original, self.config.force_conflicts),
+            )
+            .await?;
+        *applied = patched.resource_version();
+        Ok(())
+    }
+
+    /// Restore the blocks and managed ranges annotation of a pool
+    async fn revert(
+        &self,
+        original: &CiliumLoadBalancerIPPool,
+        applied: &Option<String>,
+    ) -> Result<bool, K8sError> {
+        if applied.is_none() {
+            return Ok(false);
+        }
+        let name = original.name_any();
+        let original_managed = original
+            .annotations()
+            .get(MANAGED_RANGES_ANNOTATION)
+            .cloned();
+        let blocks = original.spec.blocks.iter().map(|b| json!(b)).collect();
+        let reverted = self
+            .patch_pool(
+                &name,
+                blocks,
+                original_managed,
+                applied.clone(),
+                self.config.force_conflicts,
+            )
+            .await;
+        skip_conflict(reverted, &name)
+    }
+
+    fn rollback_target(original: &CiliumLoadBalancerIPPool) -> (ObjectReference, String) {
+        (original.object_ref(&()), "Restored blocks".to_string())
+    }
+}
+
+#[async_trait]
+impl PoolBackend for CiliumUpdater {
+    /// List all Cilium pools that are annotated for management by metallb-dyn6
+    #[instrument(skip(self))]
+    async fn discover_pools(&self) -> Result<Vec<AnnotatedPool>, K8sError> {
+        let pools = self
+            .pool_api
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing pools: {}", e)))?;
+        let annotated = pools.iter().filter_map(annotated_pool).collect::<Vec<_>>();
+        debug!(pools = ?annotated);
+        Ok(annotated)
+    }
+
+    async fn get_addresses(&self, pool: &str) -> Result<PoolAddresses, K8sError> {
+        let pool = get_pool(&self.pool_api, pool).await?;
+        let ranges = pool
+            .spec
+            .blocks
+            .iter()
+            .map(block_range)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PoolAddresses {
+            ranges,
+            managed: managed_ranges(&pool)?,
+            resource_version: pool.metadata.resource_version,
+        })
+    }
+
+    /// Replace the blocks of one or more pools and record which of them are managed by metallb-dyn6.
+    /// If any pool can not be updated, all pools are reverted to their original state.
+    /// Cilium has no advertisements to render, so `advertisements` is always empty.
+    async fn set_addresses(
+        &self,
+        updates: Vec<PoolUpdate>,
+        advertisements: Vec<AdvertisementUpdate>,
+    ) -> Result<(), K8sError> {
+        if !advertisements.is_empty() {
+            warn!(msg = "Cilium pools have no advertisements, ignoring rendered templates");
+        }
+        update_pools(self, updates).await
+    }
+}
+
+/// Build the server-side apply patch for a pool, containing only the fields owned by metallb-dyn6
+fn apply_patch(
+    name: &str,
+    blocks: Vec<serde_json::Value>,
+    managed: Option<String>,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let mut metadata = json!({ "name": name });
+    if let Some(managed) = managed {
+        metadata["annotations"] = json!({ MANAGED_RANGES_ANNOTATION: managed });
+    }
+    with_resource_version(
+        json!({
+            "apiVersion": CiliumLoadBalancerIPPool::api_version(&()),
+            "kind": CiliumLoadBalancerIPPool::kind(&()),
+            "metadata": metadata,
+            "spec": {
+                "blocks": blocks,
+            },
+        }),
+        resource_version,
+    )
+}
+
+/// The address range of a block, which is either a CIDR or a start and optional stop address
+fn block_range(block: &CiliumLoadBalancerIPPoolBlocks) -> Result<MetalLbAddressRange, K8sError> {
+    let range = match (&block.cidr, &block.start, &block.stop) {
+        (Some(cidr), None, None) => cidr.clone(),
+        (None, Some(start), Some(stop)) => format!("{}-{}", start, stop),
+        (None, Some(start), None) => format!("{}-{}", start, start),
+        _ => {
+            return Err(K8sError::new(format!(
+                "Invalid pool block {:?}, expected either cidr or start and stop",
+                block
+            )))
+        }
+    };
+    parse_ranges([range].iter()).map(|mut r| r.remove(0))
+}
+
+fn range_block(range: &MetalLbAddressRange) -> serde_json::Value {
+    match range {
+        MetalLbAddressRange::V4Cidr(_) | MetalLbAddressRange::V6Cidr(_) => {
+            json!({ "cidr": range.to_string() })
+        }
+        MetalLbAddressRange::V4Range(_) | MetalLbAddressRange::V6Range(_) => {
+            json!({ "start": range.start().to_string(), "stop": range.end().to_string() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(
+        cidr: Option<&str>,
+        start: Option<&str>,
+        stop: Option<&str>,
+    ) -> CiliumLoadBalancerIPPoolBlocks {
+        CiliumLoadBalancerIPPoolBlocks {
+            cidr: cidr.map(str::to_string),
+            start: start.map(str::to_string),
+            stop: stop.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn blocks_convert_to_ranges() {
+        assert_eq!(
+            block_range(&block(Some("2001:db8::/64"), None, None)).unwrap(),
+            "2001:db8::/64".parse().unwrap()
+        );
+        assert_eq!(
+            block_range(&block(None, Some("2001:db8::1000"), Some("2001:db8::1999"))).unwrap(),
+            "2001:db8::1000-2001:db8::1999".parse().unwrap()
+        );
+        assert_eq!(
+            block_range(&block(None, Some("2001:db8::1000"), None)).unwrap(),
+            "2001:db8::1000-2001:db8::1000".parse().unwrap()
+        );
+        assert!(block_range(&block(Some("2001:db8::/64"), Some("2001:db8::1"), None)).is_err());
+    }
+
+    #[test]
+    fn apply_patch_sets_blocks() {
+        let blocks = ["2001:db8::1000-2001:db8::1999", "192.0.2.0/24"]
+            .iter()
+            .map(|r| range_block(&r.parse().unwrap()))
+            .collect();
+        let patch = apply_patch(
+            "public",
+            blocks,
+            Some("2001:db8::1000-2001:db8::1999".to_string()),
+            Some("42".to_string()),
+        );
+        assert_eq!(
+            patch,
+            json!({
+                "apiVersion": "cilium.io/v2alpha1",
+                "kind": "CiliumLoadBalancerIPPool",
+                "metadata": {
+                    "name": "public",
+                    "resourceVersion": "42",
+                    "annotations": {
+                        MANAGED_RANGES_ANNOTATION: "2001:db8::1000-2001:db8::1999",
+                    },
+                },
+                "spec": {
+                    "blocks": [
+                        { "start": "2001:db8::1000", "stop": "2001:db8::1999" },
+                        { "cidr": "192.0.2.0/24" },
+                    ],
+                },
+            })
+        );
+    }
+}
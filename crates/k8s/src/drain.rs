@@ -0,0 +1,368 @@
+use std::time::Duration;
+
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::{
+    api::{DeleteParams, ListParams, Patch, PatchParams},
+    Api, Resource, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    ranges::MetalLbAddressRange, v1beta1::ipaddresspool::IPAddressPool, K8sError, FIELD_MANAGER,
+};
+
+/// Label on a draining pool with the name of the pool whose previous ranges it holds
+pub const DRAINING_FROM_LABEL: &str = "dyn6.spacebird.dev/draining-from";
+/// Annotation on a draining pool with the time (RFC 3339) after which it is deleted
+pub const DRAIN_UNTIL_ANNOTATION: &str = "dyn6.spacebird.dev/drain-until";
+
+/// Keeps the previous ranges of a pool in a separate pool with `autoAssign: false` for a grace period,
+/// so that Services can keep their previous addresses while clients move to the new ones
+#[derive(Debug)]
+pub(crate) struct DrainingPools {
+    pool_api: Api<IPAddressPool>,
+    period: Duration,
+}
+
+impl DrainingPools {
+    pub(crate) fn new(pool_api: Api<IPAddressPool>, period: Duration) -> Self {
+        DrainingPools { pool_api, period }
+    }
+
+    /// Move `ranges`, removed from the `source` pool, into its draining pool and restart the grace period.
+    /// Returns all ranges of the draining pool.
+    #[instrument(skip(self, source), fields(pool = source.name_any()))]
+    pub(crate) async fn drain(
+        &self,
+        source: &IPAddressPool,
+        ranges: &[MetalLbAddressRange],
+    ) -> Result<Vec<MetalLbAddressRange>, K8sError> {
+        let name = draining_pool_name(&source.name_any());
+        let mut addresses = self.draining_addresses(&name).await?;
+        for range in ranges {
+            if !addresses.contains(range) {
+                addresses.push(*range);
+            }
+        }
+
+        let until = Utc::now()
+            + k8s_openapi::chrono::Duration::from_std(self.period)
+                .map_err(|e| K8sError::new(format!("Invalid drain period: {}", e)))?;
+        let patch = draining_patch(source, &name, &addresses, until);
+        debug!(patch = ?patch);
+        self.pool_api
+            .patch(
+                &name,
+                &PatchParams::apply(FIELD_MANAGER),
+                &Patch::Apply(&patch),
+            )
+            .await
+            .map_err(|e| K8sError::new(format!("Error updating draining pool {}: {}", name, e)))?;
+        info!(
+            msg = "Draining previous ranges",
+            draining_pool = name,
+            ranges = ?addresses,
+            until = until.to_rfc3339()
+        );
+        Ok(addresses)
+    }
+
+    /// Remove `ranges` from the draining pool of `pool`, for example because they are assigned to the pool again.
+    /// MetalLB does not allow overlapping pools, so this has to happen before they are added back.
+    /// The draining pool is deleted once it is empty.
+    /// Returns the draining pool as it was before, if any ranges were removed from it, so that it can be restored.
+    #[instrument(skip(self))]
+    pub(crate) async fn release(
+        &self,
+        pool: &str,
+        ranges: &[MetalLbAddressRange],
+    ) -> Result<Option<IPAddressPool>, K8sError> {
+        let name = draining_pool_name(pool);
+        let Some(draining) = self.get_draining_pool(&name).await? else {
+            return Ok(None);
+        };
+        let addresses = parse_addresses(&draining);
+        let remaining = addresses
+            .iter()
+            .filter(|a| !ranges.contains(a))
+            .copied()
+            .collect::<Vec<_>>();
+        if remaining.len() == addresses.len() {
+            return Ok(None);
+        }
+        if remaining.is_empty() {
+            self.delete(&name).await?;
+            return Ok(Some(draining));
+        }
+
+        // Keep the deadline and the copied labels, which are owned by us as well
+        let mut released = draining.clone();
+        released.spec.addresses = remaining.iter().map(|a| a.to_string()).collect();
+        self.apply(&released).await?;
+        info!(
+            msg = "Released ranges from draining pool",
+            draining_pool = name,
+            ranges = ?ranges
+        );
+        Ok(Some(draining))
+    }
+
+    /// Restore a draining pool returned by [DrainingPools::release], recreating it if it was deleted
+    #[instrument(skip(self, previous), fields(draining_pool = previous.name_any()))]
+    pub(crate) async fn restore(&self, previous: &IPAddressPool) -> Result<(), K8sError> {
+        self.apply(previous).await?;
+        info!(
+            msg = "Restored draining pool",
+            draining_pool = previous.name_any(),
+            ranges = ?previous.spec.addresses
+        );
+        Ok(())
+    }
+
+    /// Delete all draining pools whose grace period is over
+    #[instrument(skip(self))]
+    pub(crate) async fn remove_expired(&self) -> Result<(), K8sError> {
+        let pools = self
+            .pool_api
+            .list(&ListParams::default().labels(DRAINING_FROM_LABEL))
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing draining pools: {}", e)))?;
+        let now = Utc::now();
+        for pool in pools {
+            if drain_expired(&pool, now) {
+                self.delete(&pool.name_any()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply(&self, pool: &IPAddressPool) -> Result<(), K8sError> {
+        let name = pool.name_any();
+        let patch = apply_patch(pool);
+        debug!(patch = ?patch);
+        self.pool_api
+            .patch(
+                &name,
+                &PatchParams::apply(FIELD_MANAGER),
+                &Patch::Apply(&patch),
+            )
+            .await
+            .map_err(|e| K8sError::new(format!("Error updating draining pool {}: {}", name, e)))?;
+        Ok(())
+    }
+
+    async fn draining_addresses(&self, name: &str) -> Result<Vec<MetalLbAddressRange>, K8sError> {
+        Ok(self
+            .get_draining_pool(name)
+            .await?
+            .map(|p| parse_addresses(&p))
+            .unwrap_or_default())
+    }
+
+    async fn get_draining_pool(&self, name: &str) -> Result<Option<IPAddressPool>, K8sError> {
+        self.pool_api
+            .get_opt(name)
+            .await
+            .map_err(|e| K8sError::new(format!("Error reading draining pool {}: {}", name, e)))
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), K8sError> {
+        self.pool_api
+            .delete(name, &DeleteParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error deleting draining pool {}: {}", name, e)))?;
+        info!(msg = "Deleted draining pool", draining_pool = name);
+        Ok(())
+    }
+}
+
+fn draining_pool_name(pool: &str) -> String {
+    format!("{}-draining", pool)
+}
+
+/// Ranges of a draining pool, skipping any that can not be parsed
+fn parse_addresses(pool: &IPAddressPool) -> Vec<MetalLbAddressRange> {
+    pool.spec
+        .addresses
+        .iter()
+        .filter_map(|a| match a.parse() {
+            Ok(r) => Some(r),
+            Err(e) => {
+                warn!(msg = "Skipping invalid range in draining pool", range = a, error = ?e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Build the server-side apply patch for a draining pool.
+/// The labels of the source pool are copied, so that advertisements selecting it by label also announce the draining pool.
+fn draining_patch(
+    source: &IPAddressPool,
+    name: &str,
+    addresses: &[MetalLbAddressRange],
+    until: DateTime<Utc>,
+) -> serde_json::Value {
+    let mut labels = source.labels().clone();
+    labels.insert(DRAINING_FROM_LABEL.to_string(), source.name_any());
+    json!({
+        "apiVersion": IPAddressPool::api_version(&()),
+        "kind": IPAddressPool::kind(&()),
+        "metadata": {
+            "name": name,
+            "labels": labels,
+            "annotations": { DRAIN_UNTIL_ANNOTATION: until.to_rfc3339() },
+        },
+        "spec": {
+            "addresses": addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+            "autoAssign": false,
+        },
+    })
+}
+
+/// Build the server-side apply patch that sets the addresses, labels and deadline of an existing draining pool
+fn apply_patch(pool: &IPAddressPool) -> serde_json::Value {
+    let mut patch = json!({
+        "apiVersion": IPAddressPool::api_version(&()),
+        "kind": IPAddressPool::kind(&()),
+        "metadata": {
+            "name": pool.name_any(),
+            "labels": pool.labels(),
+        },
+        "spec": {
+            "addresses": pool.spec.addresses,
+            "autoAssign": false,
+        },
+    });
+    if let Some(until) = pool.annotations().get(DRAIN_UNTIL_ANNOTATION) {
+        patch["metadata"]["annotations"] = json!({ DRAIN_UNTIL_ANNOTATION: until });
+    }
+    patch
+}
+
+/// Whether the grace period of a draining pool is over. Pools without a valid deadline are kept.
+fn drain_expired(pool: &IPAddressPool, now: DateTime<Utc>) -> bool {
+    let Some(until) = pool.annotations().get(DRAIN_UNTIL_ANNOTATION) else {
+        return false;
+    };
+    match DateTime::parse_from_rfc3339(until) {
+        Ok(until) => until < now,
+        Err(e) => {
+            warn!(
+                msg = "Invalid drain deadline, keeping draining pool",
+                pool = pool.name_any(),
+                error = e.to_string()
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::api::ObjectMeta;
+
+    use crate::v1beta1::ipaddresspool::IPAddressPoolSpec;
+
+    use super::*;
+
+    fn pool(labels: &[(&str, &str)], annotations: &[(&str, &str)]) -> IPAddressPool {
+        IPAddressPool {
+            metadata: ObjectMeta {
+                name: Some("public".to_string()),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                annotations: Some(
+                    annotations
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            spec: IPAddressPoolSpec {
+                addresses: vec![],
+                auto_assign: None,
+                avoid_buggy_i_ps: None,
+                service_allocation: None,
+            },
+            status: None,
+        }
+    }
+
+    #[test]
+    fn draining_patch_disables_auto_assign_and_copies_labels() {
+        let until = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let patch = draining_patch(
+            &pool(&[("zone", "a")], &[]),
+            "public-draining",
+            &["2001:db8:aaaa::1000-2001:db8:aaaa::1999".parse().unwrap()],
+            until,
+        );
+        assert_eq!(
+            patch,
+            json!({
+                "apiVersion": "metallb.io/v1beta1",
+                "kind": "IPAddressPool",
+                "metadata": {
+                    "name": "public-draining",
+                    "labels": { "zone": "a", DRAINING_FROM_LABEL: "public" },
+                    "annotations": { DRAIN_UNTIL_ANNOTATION: "2024-01-01T00:00:00+00:00" },
+                },
+                "spec": {
+                    "addresses": ["2001:db8:aaaa::1000-2001:db8:aaaa::1999"],
+                    "autoAssign": false,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn apply_patch_keeps_labels_and_deadline() {
+        let mut draining = pool(
+            &[("zone", "a"), (DRAINING_FROM_LABEL, "public")],
+            &[
+                (DRAIN_UNTIL_ANNOTATION, "2024-01-01T00:00:00Z"),
+                ("example.com/other", "x"),
+            ],
+        );
+        draining.metadata.name = Some("public-draining".to_string());
+        draining.spec.addresses = vec!["2001:db8:aaaa::1000-2001:db8:aaaa::1999".to_string()];
+        assert_eq!(
+            apply_patch(&draining),
+            json!({
+                "apiVersion": "metallb.io/v1beta1",
+                "kind": "IPAddressPool",
+                "metadata": {
+                    "name": "public-draining",
+                    "labels": { "zone": "a", DRAINING_FROM_LABEL: "public" },
+                    "annotations": { DRAIN_UNTIL_ANNOTATION: "2024-01-01T00:00:00Z" },
+                },
+                "spec": {
+                    "addresses": ["2001:db8:aaaa::1000-2001:db8:aaaa::1999"],
+                    "autoAssign": false,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn drain_expires_after_deadline() {
+        let now = DateTime::parse_from_rfc3339("2024-01-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let expired = pool(&[], &[(DRAIN_UNTIL_ANNOTATION, "2024-01-01T11:00:00Z")]);
+        let draining = pool(&[], &[(DRAIN_UNTIL_ANNOTATION, "2024-01-01T13:00:00Z")]);
+        let invalid = pool(&[], &[(DRAIN_UNTIL_ANNOTATION, "tomorrow")]);
+        assert!(drain_expired(&expired, now));
+        assert!(!drain_expired(&draining, now));
+        assert!(!drain_expired(&invalid, now));
+    }
+}
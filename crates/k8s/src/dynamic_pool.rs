@@ -0,0 +1,154 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Client, CustomResource,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, info, instrument};
+
+use crate::{K8sError, FIELD_MANAGER};
+
+/// Type of the condition that reports whether the range of a [DynamicIPv6Pool] is in place
+pub const READY_CONDITION: &str = "Ready";
+
+/// A dynamic IPv6 range that metallb-dyn6 maintains in a MetalLB IPAddressPool
+#[derive(CustomResource, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[kube(
+    group = "dyn6.spacebird.dev",
+    version = "v1alpha1",
+    kind = "DynamicIPv6Pool",
+    plural = "dynamicipv6pools",
+    shortname = "dyn6pool"
+)]
+#[kube(namespaced)]
+#[kube(status = "DynamicIPv6PoolStatus")]
+#[kube(
+    printcolumn = r#"{"name":"Pool","type":"string","jsonPath":".spec.pool"}"#,
+    printcolumn = r#"{"name":"Range","type":"string","jsonPath":".status.range"}"#,
+    printcolumn = r#"{"name":"Changed","type":"date","jsonPath":".status.lastChangeTime"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicIPv6PoolSpec {
+    /// Name of the uplink whose source provides the prefix.
+    /// Defaults to the uplink configured through the top-level --source argument.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uplink: Option<String>,
+    /// Network source that provides the prefix, such as my-ip, stun or mqtt.
+    /// Selects the single uplink that uses this source. If `uplink` is set as well, it has to use this source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Range of host addresses, such as ::1000-::1999
+    pub host_range: String,
+    /// Subnet override in the form <override>/<prefix length>, such as 0:0:0:cd::/56
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subnet_override: Option<String>,
+    /// Name of the IPAddressPool in the MetalLB namespace that receives the range
+    pub pool: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicIPv6PoolStatus {
+    /// Prefix last returned by the source, before any subnet override
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Address range currently in the pool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+    /// Time at which the range last changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_change_time: Option<Time>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    /// Generation of the spec that the status refers to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+}
+
+/// Access to the DynamicIPv6Pool resources reconciled in controller mode
+#[derive(Debug, Clone)]
+pub struct DynamicPoolClient {
+    api: Api<DynamicIPv6Pool>,
+}
+
+impl DynamicPoolClient {
+    #[instrument]
+    pub async fn new(namespace: &str) -> Result<Self, K8sError> {
+        debug!(msg = "Creating k8s Client for DynamicIPv6Pools");
+        let client = Client::try_default().await?;
+        info!(msg = "Created k8s Client for DynamicIPv6Pools", namespace);
+        Ok(DynamicPoolClient {
+            api: Api::namespaced(client, namespace),
+        })
+    }
+
+    /// API for the DynamicIPv6Pool resources in the namespace
+    pub fn api(&self) -> Api<DynamicIPv6Pool> {
+        self.api.clone()
+    }
+
+    /// Replace the status of a DynamicIPv6Pool
+    pub async fn set_status(
+        &self,
+        name: &str,
+        status: DynamicIPv6PoolStatus,
+    ) -> Result<(), K8sError> {
+        let patch = json!({ "status": status });
+        let params = PatchParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+        self.api
+            .patch_status(name, &params, &Patch::Merge(&patch))
+            .await
+            .map(|p| {
+                debug!(dynamic_pool = ?p);
+            })
+            .map_err(|e| K8sError::new(format!("Error updating status of {}: {}", name, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::{CustomResourceExt, Resource};
+
+    use super::*;
+
+    #[test]
+    fn crd_is_namespaced_and_has_status() {
+        let crd = DynamicIPv6Pool::crd();
+        assert_eq!(
+            crd.metadata.name.as_deref(),
+            Some("dynamicipv6pools.dyn6.spacebird.dev")
+        );
+        assert_eq!(crd.spec.scope, "Namespaced");
+        assert!(crd.spec.versions[0]
+            .subresources
+            .as_ref()
+            .and_then(|s| s.status.as_ref())
+            .is_some());
+        assert_eq!(DynamicIPv6Pool::kind(&()), "DynamicIPv6Pool");
+    }
+
+    #[test]
+    fn spec_uses_camel_case() {
+        let spec: DynamicIPv6PoolSpec = serde_json::from_value(serde_json::json!({
+            "hostRange": "::1000-::1999",
+            "subnetOverride": "0:0:0:cd::/56",
+            "pool": "public",
+        }))
+        .unwrap();
+        assert_eq!(
+            spec,
+            DynamicIPv6PoolSpec {
+                uplink: None,
+                source: None,
+                host_range: "::1000-::1999".to_string(),
+                subnet_override: Some("0:0:0:cd::/56".to_string()),
+                pool: "public".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::net::Ipv6Addr;
+
+use k8s_openapi::api::core::v1::Service;
+use kube::{api::ListParams, Api, Client, ResourceExt};
+use serde_json::json;
+use tracing::{debug, info, instrument};
+
+use crate::{
+    patch::{merge_patch, with_resource_version},
+    K8sError, HOST_ID_ANNOTATION, SUBNET_OVERRIDE_ANNOTATION, UPLINK_ANNOTATION,
+};
+
+/// Annotation on a Service with the external IP last set by metallb-dyn6, so that all other externalIPs are kept
+pub const MANAGED_EXTERNAL_IP_ANNOTATION: &str = "dyn6.spacebird.dev/managed-external-ip";
+
+/// A Service whose `spec.externalIPs` follow the dynamic prefix, along with its unparsed dyn6 annotations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIpService {
+    pub namespace: String,
+    pub name: String,
+    /// Value of the [HOST_ID_ANNOTATION], such as `::10`
+    pub host_id: Option<String>,
+    /// Value of the [SUBNET_OVERRIDE_ANNOTATION]
+    pub subnet_override: Option<String>,
+    /// Value of the [UPLINK_ANNOTATION]
+    pub uplink: Option<String>,
+    pub external_ips: Vec<String>,
+    /// Value of the [MANAGED_EXTERNAL_IP_ANNOTATION]
+    pub managed: Option<String>,
+    pub resource_version: Option<String>,
+}
+
+/// Keeps the externalIPs of Services selected by a label selector in sync with the dynamic prefix,
+/// for clusters without a LoadBalancer implementation
+#[derive(Debug)]
+pub struct ExternalIpUpdater {
+    service_api: Api<Service>,
+    selector: String,
+}
+
+impl ExternalIpUpdater {
+    #[instrument]
+    pub async fn new(selector: String) -> Result<Self, K8sError> {
+        debug!(msg = "Creating k8s Client for Service externalIPs");
+        let client = Client::try_default().await?;
+        info!(
+            msg = "Created k8s Client for Service externalIPs",
+            selector = selector
+        );
+        Ok(ExternalIpUpdater {
+            service_api: Api::all(client),
+            selector,
+        })
+    }
+
+    /// List all Services matching the selector
+    #[instrument(skip(self))]
+    pub async fn discover_services(&self) -> Result<Vec<ExternalIpService>, K8sError> {
+        let services = self
+            .service_api
+            .list(&ListParams::default().labels(&self.selector))
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing Services: {}", e)))?;
+        Ok(services
+            .into_iter()
+            .map(|service| {
+                let annotations = service.annotations();
+                let annotation = |key: &str| annotations.get(key).cloned();
+                ExternalIpService {
+                    namespace: service.namespace().unwrap_or_default(),
+                    name: service.name_any(),
+                    host_id: annotation(HOST_ID_ANNOTATION),
+                    subnet_override: annotation(SUBNET_OVERRIDE_ANNOTATION),
+                    uplink: annotation(UPLINK_ANNOTATION),
+                    external_ips: service
+                        .spec
+                        .as_ref()
+                        .and_then(|s| s.external_ips.clone())
+                        .unwrap_or_default(),
+                    managed: annotation(MANAGED_EXTERNAL_IP_ANNOTATION),
+                    resource_version: service.resource_version(),
+                }
+            })
+            .collect())
+    }
+
+    /// Replace the external IP previously set by metallb-dyn6 with `address`, keeping all other externalIPs.
+    /// Returns false if the Service already had the address.
+    #[instrument(skip(self, service), fields(service = format!("{}/{}", service.namespace, service.name)))]
+    pub async fn set_external_ip(
+        &self,
+        service: &ExternalIpService,
+        address: Ipv6Addr,
+    ) -> Result<bool, K8sError> {
+        let address = address.to_string();
+        let external_ips =
+            external_ips(&service.external_ips, service.managed.as_deref(), &address);
+        if external_ips == service.external_ips && service.managed.as_ref() == Some(&address) {
+            debug!(
+                msg = "Service already has the external IP",
+                address = address
+            );
+            return Ok(false);
+        }
+
+        let patch = external_ips_patch(&external_ips, &address, service.resource_version.clone());
+        merge_patch(&self.service_api, &service.namespace, &service.name, &patch).await?;
+        info!(
+            msg = "Updated Service externalIPs",
+            external_ips = ?external_ips
+        );
+        Ok(true)
+    }
+}
+
+/// The externalIPs with the previously managed address replaced by `address`, or `address` appended if it was not set
+fn external_ips(current: &[String], managed: Option<&str>, address: &str) -> Vec<String> {
+    let mut external_ips = current
+        .iter()
+        .filter(|ip| Some(ip.as_str()) != managed && *ip != address)
+        .cloned()
+        .collect::<Vec<_>>();
+    let position = managed
+        .and_then(|m| current.iter().position(|ip| ip == m))
+        .unwrap_or(external_ips.len())
+        .min(external_ips.len());
+    external_ips.insert(position, address.to_string());
+    external_ips
+}
+
+/// Merge patch that sets the externalIPs and the managed address, see [with_resource_version]
+fn external_ips_patch(
+    external_ips: &[String],
+    managed: &str,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let patch = json!({
+        "metadata": {
+            "annotations": { MANAGED_EXTERNAL_IP_ANNOTATION: managed },
+        },
+        "spec": { "externalIPs": external_ips },
+    });
+    with_resource_version(patch, resource_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(ips: &[&str]) -> Vec<String> {
+        ips.iter().map(|ip| ip.to_string()).collect()
+    }
+
+    #[test]
+    fn replaces_managed_address_in_place() {
+        assert_eq!(
+            external_ips(
+                &ips(&["192.0.2.1", "2001:db8:aaaa::10", "2001:db8:ffff::1"]),
+                Some("2001:db8:aaaa::10"),
+                "2001:db8:bbbb::10"
+            ),
+            ips(&["192.0.2.1", "2001:db8:bbbb::10", "2001:db8:ffff::1"])
+        );
+    }
+
+    #[test]
+    fn appends_address_without_managed_address() {
+        assert_eq!(
+            external_ips(&ips(&["192.0.2.1"]), None, "2001:db8:bbbb::10"),
+            ips(&["192.0.2.1", "2001:db8:bbbb::10"])
+        );
+        // An address that is already present is not added twice
+        assert_eq!(
+            external_ips(
+                &ips(&["2001:db8:bbbb::10"]),
+                Some("2001:db8:aaaa::10"),
+                "2001:db8:bbbb::10"
+            ),
+            ips(&["2001:db8:bbbb::10"])
+        );
+    }
+
+    #[test]
+    fn external_ips_patch_sets_managed_annotation() {
+        assert_eq!(
+            external_ips_patch(
+                &ips(&["2001:db8:bbbb::10"]),
+                "2001:db8:bbbb::10",
+                Some("42".to_string())
+            ),
+            json!({
+                "metadata": {
+                    "annotations": {
+                        "dyn6.spacebird.dev/managed-external-ip": "2001:db8:bbbb::10",
+                    },
+                    "resourceVersion": "42",
+                },
+                "spec": { "externalIPs": ["2001:db8:bbbb::10"] },
+            })
+        );
+    }
+}
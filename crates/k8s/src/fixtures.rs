@@ -0,0 +1,75 @@
+use k8s_openapi::{
+    api::core::v1::{LoadBalancerIngress, LoadBalancerStatus, Service, ServiceSpec, ServiceStatus},
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+
+use crate::verify::RangeChange;
+
+/// A LoadBalancer Service with the given ingress addresses
+pub(crate) fn load_balancer(namespace: &str, name: &str, ips: &[&str]) -> Service {
+    Service {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            type_: Some("LoadBalancer".to_string()),
+            ..Default::default()
+        }),
+        status: Some(ServiceStatus {
+            load_balancer: Some(LoadBalancerStatus {
+                ingress: Some(
+                    ips.iter()
+                        .map(|ip| LoadBalancerIngress {
+                            ip: Some(ip.to_string()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+            }),
+            ..Default::default()
+        }),
+    }
+}
+
+/// The Service with an additional annotation
+pub(crate) fn annotated(mut service: Service, key: &str, value: &str) -> Service {
+    service
+        .metadata
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(key.to_string(), value.to_string());
+    service
+}
+
+/// The pool `public` moving from the managed range 2001:db8:aaaa::1000-2001:db8:aaaa::1999
+/// to 2001:db8:bbbb::1000-2001:db8:bbbb::1999
+pub(crate) fn range_change() -> RangeChange {
+    let previous = vec!["2001:db8:aaaa::1000-2001:db8:aaaa::1999".parse().unwrap()];
+    let current = vec!["2001:db8:bbbb::1000-2001:db8:bbbb::1999".parse().unwrap()];
+    RangeChange {
+        pool: "public".to_string(),
+        previous: previous.clone(),
+        current: current.clone(),
+        previous_managed: previous,
+        current_managed: current,
+    }
+}
+
+/// The pool `public` with two managed ranges from different uplinks, of which only
+/// 2001:db8:aaaa::1000-2001:db8:aaaa::1999 moves to 2001:db8:bbbb::1000-2001:db8:bbbb::1999,
+/// while 2001:db8:cccc::1000-2001:db8:cccc::1999 stays in place
+pub(crate) fn two_range_change() -> RangeChange {
+    let unchanged = "2001:db8:cccc::1000-2001:db8:cccc::1999".parse().unwrap();
+    let mut change = range_change();
+    for ranges in [
+        &mut change.previous,
+        &mut change.current,
+        &mut change.previous_managed,
+        &mut change.current_managed,
+    ] {
+        ranges.push(unchanged);
+    }
+    change
+}
@@ -0,0 +1,340 @@
+use std::net::Ipv6Addr;
+
+use ipnet::Ipv6Net;
+use kube::{
+    api::ListParams,
+    runtime::events::{Event, EventType},
+    Api, Client, Resource, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    backend::EventRecorder,
+    patch::{merge_patch, with_resource_version},
+    ranges::V6Range,
+    v1::gateway::{Gateway, GatewayAddresses},
+    K8sError, SUBNET_OVERRIDE_ANNOTATION, UPLINK_ANNOTATION,
+};
+
+/// Annotation on a Gateway with the network its addresses were last moved to, so that addresses in other networks are kept
+pub const MANAGED_NETWORK_ANNOTATION: &str = "dyn6.spacebird.dev/managed-network";
+
+/// Address type of Gateway addresses that hold an IP address, which is also the default if no type is set
+const IP_ADDRESS_TYPE: &str = "IPAddress";
+
+/// A Gateway selected for address management, along with its unparsed dyn6 annotations
+#[derive(Debug, Clone)]
+pub struct AddressedGateway {
+    pub namespace: String,
+    pub name: String,
+    /// Value of the [SUBNET_OVERRIDE_ANNOTATION]
+    pub subnet_override: Option<String>,
+    /// Value of the [UPLINK_ANNOTATION]
+    pub uplink: Option<String>,
+    /// Value of the [MANAGED_NETWORK_ANNOTATION]
+    pub managed: Option<String>,
+    gateway: Gateway,
+}
+
+/// Moves the IPv6 `spec.addresses` of Gateway API Gateways selected by a label selector to the current prefix,
+/// keeping their host part. The outcome for each Gateway is recorded as an Event on it.
+#[derive(Debug)]
+pub struct GatewayUpdater {
+    gateway_api: Api<Gateway>,
+    selector: String,
+    recorder: EventRecorder,
+}
+
+impl GatewayUpdater {
+    #[instrument]
+    pub async fn new(selector: String) -> Result<Self, K8sError> {
+        debug!(msg = "Creating k8s Client for Gateway access");
+        let client = Client::try_default().await?;
+        info!(msg = "Created k8s Client for Gateways", selector = selector);
+        Ok(GatewayUpdater {
+            gateway_api: Api::all(client.clone()),
+            selector,
+            recorder: EventRecorder::new(client),
+        })
+    }
+
+    /// List all Gateways matching the selector
+    #[instrument(skip(self))]
+    pub async fn discover_gateways(&self) -> Result<Vec<AddressedGateway>, K8sError> {
+        let gateways = self
+            .gateway_api
+            .list(&ListParams::default().labels(&self.selector))
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing Gateways: {}", e)))?;
+        Ok(gateways
+            .into_iter()
+            .map(|gateway| {
+                let annotations = gateway.annotations();
+                AddressedGateway {
+                    namespace: gateway.namespace().unwrap_or_default(),
+                    name: gateway.name_any(),
+                    subnet_override: annotations.get(SUBNET_OVERRIDE_ANNOTATION).cloned(),
+                    uplink: annotations.get(UPLINK_ANNOTATION).cloned(),
+                    managed: annotations.get(MANAGED_NETWORK_ANNOTATION).cloned(),
+                    gateway,
+                }
+            })
+            .collect())
+    }
+
+    /// Move the IPv6 addresses of the Gateway from the network they were last moved to into `network`,
+    /// keeping their host part and all other addresses, and record `network` in the [MANAGED_NETWORK_ANNOTATION].
+    /// Without the annotation, no addresses are moved and only the network is recorded.
+    /// Returns false if the Gateway already had the network recorded.
+    /// Both moves and failures are recorded as an Event on the Gateway.
+    #[instrument(skip(self, gateway), fields(gateway = format!("{}/{}", gateway.namespace, gateway.name)))]
+    pub async fn set_network(
+        &self,
+        gateway: &AddressedGateway,
+        network: Ipv6Net,
+    ) -> Result<bool, K8sError> {
+        let previous = gateway
+            .managed
+            .as_deref()
+            .map(str::parse::<Ipv6Net>)
+            .transpose()
+            .map_err(|e| {
+                K8sError::new(format!(
+                    "Invalid {} annotation: {}",
+                    MANAGED_NETWORK_ANNOTATION, e
+                ))
+            })?;
+        if previous == Some(network) {
+            debug!(msg = "Gateway addresses are already in the network", %network);
+            return Ok(false);
+        }
+        let addresses = gateway.gateway.spec.addresses.clone().unwrap_or_default();
+        let renumbered =
+            previous.and_then(|previous| renumber_addresses(&addresses, previous, network));
+        let Some(renumbered) = renumbered else {
+            self.patch_gateway(gateway, None, network).await?;
+            info!(msg = "Recorded Gateway network", %network);
+            return Ok(true);
+        };
+        let values = renumbered
+            .iter()
+            .map(|a| a.value.as_str())
+            .collect::<Vec<_>>();
+        match self
+            .patch_gateway(gateway, Some(&renumbered), network)
+            .await
+        {
+            Ok(()) => {
+                info!(msg = "Updated Gateway addresses", addresses = ?values);
+                let note = format!("Moved addresses to {}: {}", network, values.join(", "));
+                self.record(gateway, EventType::Normal, "AddressesUpdated", note)
+                    .await;
+                Ok(true)
+            }
+            Err(e) => {
+                let note = format!("Could not move addresses to {}: {}", network, e);
+                self.record(gateway, EventType::Warning, "AddressUpdateFailed", note)
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn patch_gateway(
+        &self,
+        gateway: &AddressedGateway,
+        addresses: Option<&[GatewayAddresses]>,
+        network: Ipv6Net,
+    ) -> Result<(), K8sError> {
+        let patch = addresses_patch(addresses, network, gateway.gateway.resource_version());
+        merge_patch(&self.gateway_api, &gateway.namespace, &gateway.name, &patch).await?;
+        Ok(())
+    }
+
+    /// Publish an Event on the Gateway, so that the outcome shows up in `kubectl describe`
+    async fn record(
+        &self,
+        gateway: &AddressedGateway,
+        type_: EventType,
+        reason: &str,
+        note: String,
+    ) {
+        let event = Event {
+            type_,
+            reason: reason.to_string(),
+            note: Some(note),
+            action: "UpdateAddresses".to_string(),
+            secondary: None,
+        };
+        if let Err(e) = self
+            .recorder
+            .publish(&event, &gateway.gateway.object_ref(&()))
+            .await
+        {
+            warn!(
+                msg = "Could not record Gateway event",
+                error = e.to_string()
+            );
+        }
+    }
+}
+
+/// The addresses with all IPv6 IP addresses in `previous` moved to `network`, keeping their host part.
+/// Addresses in other networks, hostnames, IPv4 and implementation-specific addresses are kept as-is.
+/// None if no address had to be moved.
+fn renumber_addresses(
+    addresses: &[GatewayAddresses],
+    previous: Ipv6Net,
+    network: Ipv6Net,
+) -> Option<Vec<GatewayAddresses>> {
+    let range = V6Range::from(network);
+    let mut moved = false;
+    let renumbered = addresses
+        .iter()
+        .map(|address| {
+            let is_ip = address.r#type.as_deref().unwrap_or(IP_ADDRESS_TYPE) == IP_ADDRESS_TYPE;
+            let renumbered = address
+                .value
+                .parse::<Ipv6Addr>()
+                .ok()
+                .filter(|addr| is_ip && previous.contains(addr))
+                .and_then(|addr| range.with_host_id(addr).filter(|r| *r != addr));
+            match renumbered {
+                Some(addr) => {
+                    moved = true;
+                    GatewayAddresses {
+                        r#type: address.r#type.clone(),
+                        value: addr.to_string(),
+                    }
+                }
+                None => address.clone(),
+            }
+        })
+        .collect();
+    moved.then_some(renumbered)
+}
+
+/// Merge patch that records the managed network and sets the Gateway addresses if given, see [with_resource_version]
+fn addresses_patch(
+    addresses: Option<&[GatewayAddresses]>,
+    network: Ipv6Net,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let mut patch = json!({
+        "metadata": {
+            "annotations": { MANAGED_NETWORK_ANNOTATION: network.to_string() },
+        },
+    });
+    if let Some(addresses) = addresses {
+        patch["spec"]["addresses"] = json!(addresses);
+    }
+    with_resource_version(patch, resource_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(r#type: Option<&str>, value: &str) -> GatewayAddresses {
+        GatewayAddresses {
+            r#type: r#type.map(str::to_string),
+            value: value.to_string(),
+        }
+    }
+
+    fn values(addresses: &[GatewayAddresses]) -> Vec<&str> {
+        addresses.iter().map(|a| a.value.as_str()).collect()
+    }
+
+    #[test]
+    fn renumbers_ipv6_ip_addresses() {
+        let addresses = [
+            address(Some("IPAddress"), "2001:db8:aaaa::10"),
+            address(None, "2001:db8:aaaa::11"),
+            address(Some("IPAddress"), "192.0.2.1"),
+            address(Some("Hostname"), "gateway.example.com"),
+        ];
+        let renumbered = renumber_addresses(
+            &addresses,
+            "2001:db8:aaaa::/64".parse().unwrap(),
+            "2001:db8:bbbb::/64".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            values(&renumbered),
+            [
+                "2001:db8:bbbb::10",
+                "2001:db8:bbbb::11",
+                "192.0.2.1",
+                "gateway.example.com"
+            ]
+        );
+        assert_eq!(renumbered[1].r#type, None);
+    }
+
+    #[test]
+    fn keeps_addresses_in_network() {
+        let addresses = [
+            address(Some("IPAddress"), "2001:db8:bbbb::10"),
+            address(Some("example.com/custom"), "2001:db8:aaaa::10"),
+        ];
+        assert!(renumber_addresses(
+            &addresses,
+            "2001:db8:aaaa::/64".parse().unwrap(),
+            "2001:db8:bbbb::/64".parse().unwrap()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn keeps_addresses_outside_previous_network() {
+        let addresses = [
+            address(Some("IPAddress"), "2001:db8:aaaa::10"),
+            address(Some("IPAddress"), "fd00::10"),
+            address(Some("IPAddress"), "2001:db8:cccc::10"),
+        ];
+        let renumbered = renumber_addresses(
+            &addresses,
+            "2001:db8:aaaa::/64".parse().unwrap(),
+            "2001:db8:bbbb::/64".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            values(&renumbered),
+            ["2001:db8:bbbb::10", "fd00::10", "2001:db8:cccc::10"]
+        );
+    }
+
+    #[test]
+    fn addresses_patch_replaces_addresses() {
+        assert_eq!(
+            addresses_patch(
+                Some(&[address(Some("IPAddress"), "2001:db8:bbbb::10")]),
+                "2001:db8:bbbb::/64".parse().unwrap(),
+                Some("42".to_string())
+            ),
+            json!({
+                "metadata": {
+                    "annotations": { MANAGED_NETWORK_ANNOTATION: "2001:db8:bbbb::/64" },
+                    "resourceVersion": "42",
+                },
+                "spec": {
+                    "addresses": [{ "type": "IPAddress", "value": "2001:db8:bbbb::10" }],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn addresses_patch_only_records_network() {
+        assert_eq!(
+            addresses_patch(None, "2001:db8:bbbb::/64".parse().unwrap(), None),
+            json!({
+                "metadata": {
+                    "annotations": { MANAGED_NETWORK_ANNOTATION: "2001:db8:bbbb::/64" },
+                },
+            })
+        );
+    }
+}
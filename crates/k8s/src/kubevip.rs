@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Client, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    patch::with_resource_version, ranges::MetalLbAddressRange, updater::parse_ranges,
+    AdvertisementUpdate, AnnotatedPool, K8sError, PoolAddresses, PoolBackend, PoolUpdate,
+    FIELD_MANAGER, MANAGED_RANGES_ANNOTATION,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KubeVipUpdaterConfig {
+    /// Namespace of the ConfigMap read by the kube-vip cloud provider
+    pub config_map_namespace: String,
+    /// Name of the ConfigMap read by the kube-vip cloud provider
+    pub config_map: String,
+    /// Names of the pools to manage, such as `global` or the name of a namespace
+    pub ip_pools: Vec<String>,
+}
+
+/// Manages the `range-<pool>` and `cidr-<pool>` keys of the kube-vip cloud provider ConfigMap.
+/// All pools share the ConfigMap, so they are updated with a single write.
+/// The cloud provider watches the ConfigMap, so no pods have to be restarted.
+#[derive(Debug)]
+pub struct KubeVipUpdater {
+    config: KubeVipUpdaterConfig,
+    config_map_api: Api<ConfigMap>,
+}
+
+impl KubeVipUpdater {
+    #[instrument]
+    pub async fn new(config: KubeVipUpdaterConfig) -> Result<Self, K8sError> {
+        debug!(
+            msg = "Creating k8s Client for kube-vip access",
+            pools = ?config.ip_pools
+        );
+        let client = Client::try_default().await?;
+
+        let updater = KubeVipUpdater {
+            config: config.clone(),
+            config_map_api: Api::namespaced(client, &config.config_map_namespace),
+        };
+        info!(
+            msg = "Created k8s Client for kube-vip Pools",
+            config_map = config.config_map,
+            pool_names = ?config.ip_pools
+        );
+        let config_map = updater.get_config_map().await?;
+        debug!(?config_map);
+        Ok(updater)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_config_map(&self) -> Result<ConfigMap, K8sError> {
+        let name = &self.config.config_map;
+        self.config_map_api
+            .get(name)
+            .await
+            .map_err(|e| K8sError::new(format!("Error reading ConfigMap {}: {}", name, e)))
+    }
+}
+
+#[async_trait]
+impl PoolBackend for KubeVipUpdater {
+    /// kube-vip pools are keys of a ConfigMap and can not carry annotations, so they can not be discovered
+    async fn discover_pools(&self) -> Result<Vec<AnnotatedPool>, K8sError> {
+        Err(K8sError::new(
+            "Pool discovery is not supported for kube-vip, configure the pools on the command line"
+                .to_string(),
+        ))
+    }
+
+    async fn get_addresses(&self, pool: &str) -> Result<PoolAddresses, K8sError> {
+        let config_map = self.get_config_map().await?;
+        let data = config_map.data.clone().unwrap_or_default();
+        let entries = |key: String| {
+            data.get(&key)
+                .map(|v| {
+                    v.split(',')
+                        .filter(|r| !r.trim().is_empty())
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        let mut ranges = parse_ranges(entries(range_key(pool)).iter())?;
+        ranges.extend(parse_ranges(entries(cidr_key(pool)).iter())?);
+        let managed = managed_by_pool(&config_map)?
+            .and_then(|m| m.get(pool).cloned())
+            .map(|a| parse_ranges(a.split(',').filter(|r| !r.is_empty())))
+            .transpose()?;
+        Ok(PoolAddresses {
+            ranges,
+            managed,
+            resource_version: config_map.metadata.resource_version,
+        })
+    }
+
+    /// Replace the ranges of one or more pools and record which of them are managed by metallb-dyn6.
+    /// All pools are written at once, so a failed update leaves all of them unchanged.
+    /// kube-vip has no advertisements to render, so `advertisements` is always empty.
+    async fn set_addresses(
+        &self,
+        updates: Vec<PoolUpdate>,
+        advertisements: Vec<AdvertisementUpdate>,
+    ) -> Result<(), K8sError> {
+        if !advertisements.is_empty() {
+            warn!(msg = "kube-vip pools have no advertisements, ignoring rendered templates");
+        }
+        let name = &self.config.config_map;
+        let config_map = self.get_config_map().await?;
+        let managed = managed_by_pool(&config_map)?.unwrap_or_default();
+        let version = config_map.metadata.resource_version;
+        if updates
+            .iter()
+            .any(|u| u.resource_version.is_some() && u.resource_version != version)
+        {
+            return Err(conflict(name));
+        }
+
+        let patch = config_map_patch(&updates, managed, version);
+        debug!(patch = ?patch);
+        let params = PatchParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+        self.config_map_api
+            .patch(name, &params, &Patch::Merge(&patch))
+            .await
+            .map_err(|e| match e {
+                kube::Error::Api(ae) if ae.code == 409 => conflict(name),
+                e => K8sError::new(format!("Error updating ConfigMap {}: {}", name, e)),
+            })?;
+        for update in updates {
+            info!(msg = "Pool updated", pool = update.pool);
+        }
+        Ok(())
+    }
+}
+
+fn range_key(pool: &str) -> String {
+    format!("range-{}", pool)
+}
+
+fn cidr_key(pool: &str) -> String {
+    format!("cidr-{}", pool)
+}
+
+/// The managed ranges of all pools in the ConfigMap, by pool name.
+/// The ConfigMap holds several pools, so the [MANAGED_RANGES_ANNOTATION] is a JSON object such as
+/// `{"global": "2001:db8::1000-2001:db8::1999"}`, which keeps the annotation name within the length limit for any pool name.
+fn managed_by_pool(config_map: &ConfigMap) -> Result<Option<BTreeMap<String, String>>, K8sError> {
+    config_map
+        .annotations()
+        .get(MANAGED_RANGES_ANNOTATION)
+        .map(|a| {
+            serde_json::from_str(a).map_err(|e| {
+                K8sError::new(format!(
+                    "Invalid {} annotation on ConfigMap {}: {}",
+                    MANAGED_RANGES_ANNOTATION,
+                    config_map.name_any(),
+                    e
+                ))
+            })
+        })
+        .transpose()
+}
+
+fn conflict(config_map: &str) -> K8sError {
+    K8sError::conflict(format!(
+        "ConfigMap {} was modified concurrently",
+        config_map
+    ))
+}
+
+/// Merge patch that sets the range and CIDR keys of the updated pools, see [with_resource_version].
+/// Keys without any ranges are removed. The managed ranges of the updated pools are merged into `managed`,
+/// the current managed ranges of all pools.
+fn config_map_patch(
+    updates: &[PoolUpdate],
+    mut managed: BTreeMap<String, String>,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let join = |ranges: Vec<String>| (!ranges.is_empty()).then(|| ranges.join(","));
+    let mut data = BTreeMap::new();
+    for update in updates {
+        let (cidrs, ranges): (Vec<&MetalLbAddressRange>, Vec<_>) =
+            update.addresses.iter().partition(|r| {
+                matches!(
+                    r,
+                    MetalLbAddressRange::V4Cidr(_) | MetalLbAddressRange::V6Cidr(_)
+                )
+            });
+        data.insert(
+            range_key(&update.pool),
+            join(ranges.iter().map(|r| r.to_string()).collect()),
+        );
+        data.insert(
+            cidr_key(&update.pool),
+            join(cidrs.iter().map(|r| r.to_string()).collect()),
+        );
+        let ranges = update
+            .managed
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+        managed.insert(update.pool.clone(), ranges.join(","));
+    }
+    let patch = json!({
+        "metadata": {
+            "annotations": { MANAGED_RANGES_ANNOTATION: json!(managed).to_string() },
+        },
+        "data": data,
+    });
+    with_resource_version(patch, resource_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_map_patch_splits_ranges_and_cidrs() {
+        let updates = [PoolUpdate {
+            pool: "global".to_string(),
+            addresses: vec![
+                "192.0.2.0/24".parse().unwrap(),
+                "2001:db8::1000-2001:db8::1999".parse().unwrap(),
+            ],
+            managed: vec!["2001:db8::1000-2001:db8::1999".parse().unwrap()],
+            resource_version: None,
+        }];
+        let managed = [(
+            "other".to_string(),
+            "2001:db8:1::1-2001:db8:1::9".to_string(),
+        )]
+        .into();
+        assert_eq!(
+            config_map_patch(&updates, managed, Some("42".to_string())),
+            json!({
+                "metadata": {
+                    "annotations": {
+                        MANAGED_RANGES_ANNOTATION: r#"{"global":"2001:db8::1000-2001:db8::1999","other":"2001:db8:1::1-2001:db8:1::9"}"#,
+                    },
+                    "resourceVersion": "42",
+                },
+                "data": {
+                    "cidr-global": "192.0.2.0/24",
+                    "range-global": "2001:db8::1000-2001:db8::1999",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn reads_managed_ranges_by_pool() {
+        let mut config_map = ConfigMap::default();
+        assert_eq!(managed_by_pool(&config_map).unwrap(), None);
+
+        let pool = "a".repeat(63);
+        config_map.annotations_mut().insert(
+            MANAGED_RANGES_ANNOTATION.to_string(),
+            format!(r#"{{"{}":"2001:db8::1000-2001:db8::1999"}}"#, pool),
+        );
+        assert_eq!(
+            managed_by_pool(&config_map).unwrap().unwrap()[&pool],
+            "2001:db8::1000-2001:db8::1999"
+        );
+
+        config_map.annotations_mut().insert(
+            MANAGED_RANGES_ANNOTATION.to_string(),
+            "2001:db8::/64".to_string(),
+        );
+        managed_by_pool(&config_map).unwrap_err();
+    }
+
+    #[test]
+    fn config_map_patch_removes_empty_keys() {
+        let updates = [PoolUpdate {
+            pool: "default".to_string(),
+            addresses: vec!["2001:db8::1000-2001:db8::1999".parse().unwrap()],
+            managed: vec![],
+            resource_version: None,
+        }];
+        let patch = config_map_patch(&updates, BTreeMap::new(), None);
+        assert_eq!(
+            patch["data"].get("cidr-default"),
+            Some(&serde_json::Value::Null)
+        );
+        assert_eq!(
+            patch["data"]["range-default"],
+            "2001:db8::1000-2001:db8::1999"
+        );
+    }
+}
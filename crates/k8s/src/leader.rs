@@ -0,0 +1,399 @@
+use std::{sync::Mutex, time::Duration};
+
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta},
+    chrono::{DateTime, Utc},
+};
+use kube::{
+    api::{PostParams, ResourceExt},
+    Api, Client,
+};
+use tokio::{sync::watch, time::Instant};
+use tracing::{debug, info, instrument, warn};
+
+use crate::K8sError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LeaderElectionConfig {
+    pub namespace: String,
+    /// Name of the coordination.k8s.io/v1 Lease used as the lock
+    pub lease_name: String,
+    /// Unique name of this replica, usually the pod name
+    pub identity: String,
+    /// Time after which a lease that was not renewed can be taken over by another replica
+    pub lease_duration: Duration,
+}
+
+/// Elects a single leader among several replicas through a Lease
+#[derive(Debug)]
+pub struct LeaderElector {
+    config: LeaderElectionConfig,
+    api: Api<Lease>,
+    leading: watch::Sender<bool>,
+    /// Lease record of the current holder, as last seen by this replica
+    observed: Mutex<Option<ObservedLease>>,
+}
+
+/// A lease record along with the local time at which this replica first saw it.
+/// Expiry is measured from that time on the local monotonic clock instead of comparing the `renewTime`
+/// written by another replica with our own wall clock, so that clock skew between nodes cannot cause an early takeover.
+#[derive(Debug, Clone, PartialEq)]
+struct ObservedLease {
+    holder_identity: Option<String>,
+    renew_time: Option<MicroTime>,
+    observed_at: Instant,
+}
+
+/// Whether this replica holds the lease, as last seen by its [LeaderElector].
+/// Work that acts as the leader checks it before making changes, so that a replica that lost the lease
+/// finishes its current update and then stops, instead of being interrupted halfway.
+#[derive(Debug, Clone)]
+pub struct Leadership(watch::Receiver<bool>);
+
+impl Leadership {
+    pub fn is_leading(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once leadership is lost, or right away if it is not held
+    pub async fn lost(mut self) {
+        // An error means that the elector is gone, which ends leadership as well
+        let _ = self.0.wait_for(|leading| !leading).await;
+    }
+}
+
+impl LeaderElector {
+    #[instrument]
+    pub async fn new(config: LeaderElectionConfig) -> Result<Self, K8sError> {
+        let client = Client::try_default().await?;
+        Ok(LeaderElector {
+            api: Api::namespaced(client, &config.namespace),
+            config,
+            leading: watch::Sender::new(false),
+            observed: Mutex::new(None),
+        })
+    }
+
+    /// Track whether this replica holds the lease
+    pub fn leadership(&self) -> Leadership {
+        Leadership(self.leading.subscribe())
+    }
+
+    /// Interval between attempts to acquire or renew the lease
+    fn retry_period(&self) -> Duration {
+        self.config.lease_duration / 3
+    }
+
+    /// Wait until this replica holds the lease
+    pub async fn acquire(&self) {
+        info!(
+            msg = "Waiting for leadership",
+            lease = self.config.lease_name,
+            identity = self.config.identity
+        );
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => {
+                    info!(msg = "Acquired leadership", lease = self.config.lease_name);
+                    self.leading.send_replace(true);
+                    return;
+                }
+                Ok(false) => debug!(msg = "Lease is held by another replica"),
+                Err(e) => warn!(msg = "Could not acquire lease", error = e.to_string()),
+            }
+            tokio::time::sleep(self.retry_period()).await;
+        }
+    }
+
+    /// Renew the lease until it is lost, either to another replica or because it could not be renewed in time.
+    /// Only returns once leadership is lost, which is reported to every [Leadership] first.
+    pub async fn keep_leading(&self) -> K8sError {
+        let e = self.renew_until_lost().await;
+        self.leading.send_replace(false);
+        e
+    }
+
+    async fn renew_until_lost(&self) -> K8sError {
+        let mut last_renewal = Instant::now();
+        loop {
+            tokio::time::sleep(self.retry_period()).await;
+            match self.try_acquire_or_renew().await {
+                Ok(true) => last_renewal = Instant::now(),
+                Ok(false) => {
+                    return K8sError::new(format!(
+                        "Lease {} was taken over by another replica",
+                        self.config.lease_name
+                    ))
+                }
+                Err(e) if last_renewal.elapsed() >= self.config.lease_duration => {
+                    return K8sError::new(format!(
+                        "Could not renew lease {} in time: {}",
+                        self.config.lease_name, e
+                    ))
+                }
+                Err(e) => warn!(msg = "Could not renew lease", error = e.to_string()),
+            }
+        }
+    }
+
+    /// Give up the lease, so that a standby replica can take over without waiting for it to expire
+    #[instrument(skip(self))]
+    pub async fn release(&self) -> Result<(), K8sError> {
+        self.leading.send_replace(false);
+        let Some(mut lease) = self.api.get_opt(&self.config.lease_name).await? else {
+            return Ok(());
+        };
+        let spec = lease.spec.get_or_insert_with(Default::default);
+        if spec.holder_identity.as_deref() != Some(self.config.identity.as_str()) {
+            return Ok(());
+        }
+        spec.holder_identity = None;
+        spec.renew_time = Some(MicroTime(Utc::now()));
+        self.api
+            .replace(&lease.name_any(), &PostParams::default(), &lease)
+            .await?;
+        info!(msg = "Released leadership", lease = self.config.lease_name);
+        Ok(())
+    }
+
+    /// Acquire the lease if it is free or expired, or renew it if we already hold it.
+    /// Returns whether this replica holds the lease afterwards.
+    async fn try_acquire_or_renew(&self) -> Result<bool, K8sError> {
+        let now = Utc::now();
+        let Some(mut lease) = self.api.get_opt(&self.config.lease_name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.config.lease_name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(self.acquired_spec(&LeaseSpec::default(), now)),
+            };
+            return conflict_as_false(self.api.create(&PostParams::default(), &lease).await);
+        };
+
+        let spec = lease.spec.clone().unwrap_or_default();
+        let new_spec = if spec.holder_identity.as_deref() == Some(self.config.identity.as_str()) {
+            LeaseSpec {
+                renew_time: Some(MicroTime(now)),
+                lease_duration_seconds: Some(self.lease_duration_seconds()),
+                ..spec
+            }
+        } else if lease_available(
+            &spec,
+            &mut self.observed.lock().expect("observed lease lock poisoned"),
+            Instant::now(),
+        ) {
+            info!(
+                msg = "Taking over lease",
+                previous_holder = spec.holder_identity
+            );
+            self.acquired_spec(&spec, now)
+        } else {
+            return Ok(false);
+        };
+
+        // The replace is conditional on the resourceVersion we read, so only one replica can win
+        lease.spec = Some(new_spec);
+        conflict_as_false(
+            self.api
+                .replace(&self.config.lease_name, &PostParams::default(), &lease)
+                .await,
+        )
+    }
+
+    fn acquired_spec(&self, previous: &LeaseSpec, now: DateTime<Utc>) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: Some(self.config.identity.clone()),
+            lease_duration_seconds: Some(self.lease_duration_seconds()),
+            acquire_time: Some(MicroTime(now)),
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: Some(previous.lease_transitions.unwrap_or(0) + 1),
+        }
+    }
+
+    fn lease_duration_seconds(&self) -> i32 {
+        self.config
+            .lease_duration
+            .as_secs()
+            .try_into()
+            .unwrap_or(i32::MAX)
+    }
+}
+
+/// Whether a lease held by someone else can be taken over: it has no holder, or its holder did not renew it in time.
+/// The holder renewed the lease if its record changed since it was last observed, see [ObservedLease].
+fn lease_available(spec: &LeaseSpec, observed: &mut Option<ObservedLease>, now: Instant) -> bool {
+    if spec.holder_identity.as_deref().is_none_or(str::is_empty) {
+        return true;
+    }
+    let (Some(_), Some(duration)) = (&spec.renew_time, spec.lease_duration_seconds) else {
+        return true;
+    };
+    let observed = match observed {
+        Some(o) if o.holder_identity == spec.holder_identity && o.renew_time == spec.renew_time => {
+            o
+        }
+        _ => observed.insert(ObservedLease {
+            holder_identity: spec.holder_identity.clone(),
+            renew_time: spec.renew_time.clone(),
+            observed_at: now,
+        }),
+    };
+    now.duration_since(observed.observed_at)
+        > Duration::from_secs(u64::try_from(duration).unwrap_or(0))
+}
+
+fn conflict_as_false(result: Result<Lease, kube::Error>) -> Result<bool, K8sError> {
+    match result {
+        Ok(_) => Ok(true),
+        // Another replica was faster
+        Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(holder: Option<&str>, renew_secs: i64) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: holder.map(str::to_string),
+            renew_time: Some(MicroTime(DateTime::from_timestamp(renew_secs, 0).unwrap())),
+            lease_duration_seconds: Some(15),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn held_lease_is_not_available() {
+        let now = Instant::now();
+        let mut observed = None;
+        assert!(!lease_available(
+            &spec(Some("other"), 100),
+            &mut observed,
+            now
+        ));
+        assert!(!lease_available(
+            &spec(Some("other"), 100),
+            &mut observed,
+            now + Duration::from_secs(15)
+        ));
+    }
+
+    #[test]
+    fn lease_expires_after_duration_without_renewal() {
+        let now = Instant::now();
+        let mut observed = None;
+        assert!(!lease_available(
+            &spec(Some("other"), 100),
+            &mut observed,
+            now
+        ));
+        assert!(lease_available(
+            &spec(Some("other"), 100),
+            &mut observed,
+            now + Duration::from_secs(16)
+        ));
+    }
+
+    #[test]
+    fn expiry_ignores_renew_time_of_other_clock() {
+        // A renewTime far in the past, such as from a holder whose clock is behind, does not expire the lease
+        let now = Instant::now();
+        let mut observed = None;
+        assert!(!lease_available(
+            &spec(Some("other"), 0),
+            &mut observed,
+            now
+        ));
+        assert_eq!(observed.as_ref().map(|o| o.observed_at), Some(now));
+    }
+
+    #[test]
+    fn renewal_restarts_expiry() {
+        let now = Instant::now();
+        let mut observed = None;
+        assert!(!lease_available(
+            &spec(Some("other"), 100),
+            &mut observed,
+            now
+        ));
+        let renewed = now + Duration::from_secs(10);
+        assert!(!lease_available(
+            &spec(Some("other"), 110),
+            &mut observed,
+            renewed
+        ));
+        assert!(!lease_available(
+            &spec(Some("other"), 110),
+            &mut observed,
+            now + Duration::from_secs(20)
+        ));
+        assert!(lease_available(
+            &spec(Some("other"), 110),
+            &mut observed,
+            renewed + Duration::from_secs(16)
+        ));
+    }
+
+    #[test]
+    fn new_holder_restarts_expiry() {
+        let now = Instant::now();
+        let mut observed = None;
+        assert!(!lease_available(
+            &spec(Some("other"), 100),
+            &mut observed,
+            now
+        ));
+        let taken_over = now + Duration::from_secs(16);
+        assert!(!lease_available(
+            &spec(Some("third"), 100),
+            &mut observed,
+            taken_over
+        ));
+        assert!(lease_available(
+            &spec(Some("third"), 100),
+            &mut observed,
+            taken_over + Duration::from_secs(16)
+        ));
+    }
+
+    #[test]
+    fn released_lease_is_available() {
+        let mut observed = None;
+        assert!(lease_available(
+            &spec(None, 100),
+            &mut observed,
+            Instant::now()
+        ));
+        assert!(lease_available(
+            &spec(Some(""), 100),
+            &mut observed,
+            Instant::now()
+        ));
+    }
+
+    #[tokio::test]
+    async fn leadership_follows_elector() {
+        let leading = watch::Sender::new(true);
+        let leadership = Leadership(leading.subscribe());
+        assert!(leadership.is_leading());
+        let lost = tokio::spawn(leadership.clone().lost());
+        tokio::task::yield_now().await;
+        assert!(!lost.is_finished());
+
+        leading.send_replace(false);
+        lost.await.unwrap();
+        assert!(!leadership.is_leading());
+    }
+
+    #[tokio::test]
+    async fn leadership_is_lost_without_elector() {
+        let leading = watch::Sender::new(true);
+        let leadership = Leadership(leading.subscribe());
+        drop(leading);
+        leadership.lost().await;
+    }
+}
@@ -1,6 +1,45 @@
+pub(crate) mod v1;
 pub(crate) mod v1beta1;
+pub(crate) mod v1beta2;
+pub(crate) mod v2alpha1;
+pub(crate) mod v3;
 
+mod advertisement;
+mod backend;
+mod calico;
+mod cilium;
+mod drain;
+pub mod dynamic_pool;
+mod external_ips;
+#[cfg(test)]
+mod fixtures;
+mod gateway;
+mod kubevip;
+mod leader;
+mod patch;
+mod peers;
+mod pinned;
 pub mod ranges;
+mod reip;
+mod reload;
 mod updater;
+mod verify;
 
-pub use updater::{K8sError, MetalLbUpdater, MetalLbUpdaterConfig};
+pub use advertisement::{
+    AdvertisementKind, AdvertisementTemplate, AdvertisementUpdate, SPEC_TEMPLATE_ANNOTATION,
+};
+pub use backend::PoolBackend;
+pub use calico::{CalicoUpdater, CalicoUpdaterConfig, BASE_POOL_LABEL};
+pub use cilium::{CiliumUpdater, CiliumUpdaterConfig};
+pub use drain::{DRAINING_FROM_LABEL, DRAIN_UNTIL_ANNOTATION};
+pub use external_ips::{ExternalIpService, ExternalIpUpdater, MANAGED_EXTERNAL_IP_ANNOTATION};
+pub use gateway::{AddressedGateway, GatewayUpdater, MANAGED_NETWORK_ANNOTATION};
+pub use kubevip::{KubeVipUpdater, KubeVipUpdaterConfig};
+pub use leader::{LeaderElectionConfig, LeaderElector, Leadership};
+pub use pinned::{HOST_ID_ANNOTATION, REWRITE_PINNED_LABEL};
+pub use reload::ReloadStrategy;
+pub use updater::{
+    AnnotatedPool, K8sError, MetalLbUpdater, MetalLbUpdaterConfig, PoolAddresses, PoolUpdate,
+    FIELD_MANAGER, HOST_RANGE_ANNOTATION, MANAGED_RANGES_ANNOTATION, SUBNET_OVERRIDE_ANNOTATION,
+    UPLINK_ANNOTATION,
+};
@@ -0,0 +1,79 @@
+use std::fmt::Debug;
+
+use k8s_openapi::NamespaceResourceScope;
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Resource,
+};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tracing::debug;
+
+use crate::{K8sError, FIELD_MANAGER};
+
+/// Make a patch conditional on the resourceVersion, if set.
+/// The API server then rejects the patch with a conflict if the object was modified since that version was read.
+pub(crate) fn with_resource_version(
+    mut patch: serde_json::Value,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    if let Some(resource_version) = resource_version {
+        patch["metadata"]["resourceVersion"] = json!(resource_version);
+    }
+    patch
+}
+
+/// Apply a merge patch to a namespaced object as metallb-dyn6, through the client of `api`.
+/// If the patch carries a resourceVersion that the object no longer has, the error is a conflict
+/// (see [K8sError::is_conflict]).
+pub(crate) async fn merge_patch<K>(
+    api: &Api<K>,
+    namespace: &str,
+    name: &str,
+    patch: &serde_json::Value,
+) -> Result<K, K8sError>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + DeserializeOwned
+        + Debug,
+{
+    debug!(patch = ?patch);
+    let params = PatchParams {
+        field_manager: Some(FIELD_MANAGER.to_string()),
+        ..Default::default()
+    };
+    let kind = K::kind(&());
+    Api::<K>::namespaced(api.clone().into_client(), namespace)
+        .patch(name, &params, &Patch::Merge(patch))
+        .await
+        .map_err(|e| match e {
+            kube::Error::Api(ae) if ae.code == 409 => K8sError::conflict(format!(
+                "{} {}/{} was modified concurrently",
+                kind, namespace, name
+            )),
+            e => K8sError::new(format!(
+                "Error updating {} {}/{}: {}",
+                kind, namespace, name, e
+            )),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_resource_version_to_metadata() {
+        let patch = json!({ "metadata": { "name": "public" }, "spec": {} });
+        assert_eq!(
+            with_resource_version(patch.clone(), Some("42".to_string())),
+            json!({ "metadata": { "name": "public", "resourceVersion": "42" }, "spec": {} })
+        );
+        assert_eq!(with_resource_version(patch.clone(), None), patch);
+        assert_eq!(
+            with_resource_version(json!({}), Some("42".to_string())),
+            json!({ "metadata": { "resourceVersion": "42" } })
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::net::Ipv6Addr;
+
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, Resource, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    patch::with_resource_version, ranges::MetalLbAddressRange, v1beta2::bgppeer::BGPPeer,
+    verify::RangeChange, K8sError, FIELD_MANAGER,
+};
+
+/// The addresses of a BGP session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PeerAddresses {
+    peer_address: Option<String>,
+    source_address: Option<String>,
+}
+
+/// A BGPPeer whose addresses were rewritten, along with what is needed to restore them
+#[derive(Debug, Clone)]
+pub(crate) struct RewrittenPeer {
+    name: String,
+    original: PeerAddresses,
+    /// Version written by the rewrite, so that the peer is only restored if nobody changed it since
+    resource_version: Option<String>,
+}
+
+/// Moves the peer and source addresses of BGPPeers from the network of a previous managed range to the new one
+#[derive(Debug)]
+pub(crate) struct BgpPeerRewriter {
+    peer_api: Api<BGPPeer>,
+}
+
+impl BgpPeerRewriter {
+    pub(crate) fn new(peer_api: Api<BGPPeer>) -> Self {
+        BgpPeerRewriter { peer_api }
+    }
+
+    /// Rewrite the network part of the `peerAddress` and `sourceAddress` of all BGPPeers in the namespace
+    /// that lie in the network of a previous managed range, keeping their host part.
+    /// Each rewritten peer is added to `rewritten`, also if a later one fails.
+    #[instrument(skip_all)]
+    pub(crate) async fn rewrite(
+        &self,
+        changes: &[RangeChange],
+        rewritten: &mut Vec<RewrittenPeer>,
+    ) -> Result<(), K8sError> {
+        let peers = self
+            .peer_api
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing BGPPeers: {}", e)))?;
+
+        for peer in peers {
+            let original = PeerAddresses {
+                peer_address: peer.spec.peer_address.clone(),
+                source_address: peer.spec.source_address.clone(),
+            };
+            let Some(renumbered) = renumber_peer(&original, changes) else {
+                continue;
+            };
+            let name = peer.name_any();
+            let patched = self
+                .patch_peer(&name, &renumbered, peer.resource_version())
+                .await?;
+            info!(
+                msg = "Rewrote BGPPeer addresses",
+                peer = name,
+                addresses = ?renumbered
+            );
+            rewritten.push(RewrittenPeer {
+                name,
+                original,
+                resource_version: patched.resource_version(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Restore the addresses of rewritten peers that were not modified since
+    pub(crate) async fn revert(&self, rewritten: &[RewrittenPeer]) -> Result<(), K8sError> {
+        for peer in rewritten {
+            let reverted = self
+                .patch_peer(&peer.name, &peer.original, peer.resource_version.clone())
+                .await;
+            match reverted {
+                Ok(_) => {}
+                Err(e) if e.is_conflict() => warn!(
+                    msg = "BGPPeer was modified concurrently, not reverting it",
+                    peer = peer.name
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn patch_peer(
+        &self,
+        name: &str,
+        addresses: &PeerAddresses,
+        resource_version: Option<String>,
+    ) -> Result<BGPPeer, K8sError> {
+        let patch = peer_patch(name, addresses, resource_version);
+        debug!(patch = ?patch);
+        // Enabling the rewrite hands the addresses of all peers to us
+        let mut params = PatchParams::apply(FIELD_MANAGER);
+        params.force = true;
+        self.peer_api
+            .patch(name, &params, &Patch::Apply(&patch))
+            .await
+            .map_err(|e| match e {
+                kube::Error::Api(ae) if ae.code == 409 => {
+                    K8sError::conflict(format!("BGPPeer {} was modified concurrently", name))
+                }
+                e => K8sError::new(format!("Error updating BGPPeer {}: {}", name, e)),
+            })
+    }
+}
+
+/// The peer addresses with all addresses from the network of a previous managed range moved to the new one.
+/// None if no address had to be moved.
+fn renumber_peer(addresses: &PeerAddresses, changes: &[RangeChange]) -> Option<PeerAddresses> {
+    let mut moved = false;
+    let mut renumber = |ip: &String| match renumber_network(ip, changes) {
+        Some(addr) => {
+            moved = true;
+            addr.to_string()
+        }
+        None => ip.clone(),
+    };
+    let renumbered = PeerAddresses {
+        peer_address: addresses.peer_address.as_ref().map(&mut renumber),
+        source_address: addresses.source_address.as_ref().map(&mut renumber),
+    };
+    moved.then_some(renumbered)
+}
+
+/// The address in the network of the new managed range that corresponds to an address in the network of a previous one
+fn renumber_network(ip: &str, changes: &[RangeChange]) -> Option<Ipv6Addr> {
+    let addr = ip.parse::<Ipv6Addr>().ok()?;
+    changes
+        .iter()
+        .flat_map(|c| {
+            c.previous_managed
+                .iter()
+                .flat_map(|p| c.current_managed.iter().map(move |n| (p, n)))
+        })
+        .find_map(|ranges| match ranges {
+            (MetalLbAddressRange::V6Range(p), MetalLbAddressRange::V6Range(n)) => {
+                p.renumber_network(addr, n)
+            }
+            _ => None,
+        })
+        .filter(|renumbered| *renumbered != addr)
+}
+
+/// Build the server-side apply patch that sets the peer addresses, see [with_resource_version].
+/// Missing addresses are left out, which removes them as they are owned by us.
+fn peer_patch(
+    name: &str,
+    addresses: &PeerAddresses,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let mut spec = json!({});
+    if let Some(peer_address) = &addresses.peer_address {
+        spec["peerAddress"] = json!(peer_address);
+    }
+    if let Some(source_address) = &addresses.source_address {
+        spec["sourceAddress"] = json!(source_address);
+    }
+    with_resource_version(
+        json!({
+            "apiVersion": BGPPeer::api_version(&()),
+            "kind": BGPPeer::kind(&()),
+            "metadata": { "name": name },
+            "spec": spec,
+        }),
+        resource_version,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::range_change as change;
+
+    #[test]
+    fn renumbers_addresses_in_previous_network() {
+        let addresses = PeerAddresses {
+            peer_address: Some("2001:db8:aaaa::1".to_string()),
+            source_address: Some("2001:db8:aaaa::2".to_string()),
+        };
+        assert_eq!(
+            renumber_peer(&addresses, &[change()]),
+            Some(PeerAddresses {
+                peer_address: Some("2001:db8:bbbb::1".to_string()),
+                source_address: Some("2001:db8:bbbb::2".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_addresses_outside_previous_network() {
+        let addresses = PeerAddresses {
+            peer_address: Some("fe80::1".to_string()),
+            source_address: None,
+        };
+        assert_eq!(renumber_peer(&addresses, &[change()]), None);
+    }
+
+    #[test]
+    fn peer_patch_removes_missing_source_address() {
+        let addresses = PeerAddresses {
+            peer_address: Some("2001:db8:bbbb::1".to_string()),
+            source_address: None,
+        };
+        assert_eq!(
+            peer_patch("upstream", &addresses, Some("42".to_string())),
+            json!({
+                "apiVersion": "metallb.io/v1beta2",
+                "kind": "BGPPeer",
+                "metadata": { "name": "upstream", "resourceVersion": "42" },
+                "spec": { "peerAddress": "2001:db8:bbbb::1" },
+            })
+        );
+    }
+}
@@ -0,0 +1,637 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use k8s_openapi::api::core::v1::Service;
+use kube::{api::ListParams, Api, Client, ResourceExt};
+use serde_json::json;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    patch::{merge_patch, with_resource_version},
+    ranges::{MetalLbAddressRange, V6Range},
+    verify::{ingress_addresses, uses_pool, RangeChange},
+    K8sError,
+};
+
+/// Label that opts a Service into having its pinned addresses rewritten after a prefix change,
+/// and with stable host IDs into being pinned to its host part
+pub const REWRITE_PINNED_LABEL: &str = "dyn6.spacebird.dev/rewrite-pinned-ips";
+/// Annotation that records the host part of the IPv6 address of a Service, such as `::1005`,
+/// so that it keeps it across prefix changes
+pub const HOST_ID_ANNOTATION: &str = "dyn6.spacebird.dev/host-id";
+
+/// Annotations through which a Service requests specific addresses from MetalLB, current and deprecated
+const LOAD_BALANCER_IPS_ANNOTATIONS: [&str; 2] = [
+    "metallb.io/loadBalancerIPs",
+    "metallb.universe.tf/loadBalancerIPs",
+];
+
+/// The addresses requested by a Service
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PinnedIps {
+    /// Values of the [LOAD_BALANCER_IPS_ANNOTATIONS] present on the Service
+    annotations: BTreeMap<String, String>,
+    /// The deprecated `spec.loadBalancerIP`
+    load_balancer_ip: Option<String>,
+    /// Value of the [HOST_ID_ANNOTATION]
+    host_id: Option<String>,
+}
+
+/// A Service whose pinned addresses were rewritten, along with what is needed to restore them
+#[derive(Debug, Clone)]
+pub(crate) struct RewrittenService {
+    namespace: String,
+    name: String,
+    original: PinnedIps,
+    rewritten: PinnedIps,
+    /// Version written by the rewrite, so that the Service is only restored if nobody changed it since
+    resource_version: Option<String>,
+}
+
+/// Moves the addresses pinned by opted-in Services from the previous managed ranges to the new ones
+#[derive(Debug)]
+pub(crate) struct PinnedIpRewriter {
+    service_api: Api<Service>,
+    /// Pin the Services with the [REWRITE_PINNED_LABEL] in changed pools to their previous host part
+    stable_host_ids: bool,
+}
+
+impl PinnedIpRewriter {
+    pub(crate) fn new(client: Client, stable_host_ids: bool) -> Self {
+        PinnedIpRewriter {
+            service_api: Api::all(client),
+            stable_host_ids,
+        }
+    }
+
+    /// Rewrite the pinned addresses of all Services labelled with [REWRITE_PINNED_LABEL]=true
+    /// that lie in a previous managed range, keeping their host part.
+    /// With stable host IDs, labelled Services of the changed pools that pin no address yet are pinned
+    /// to their host part under the new prefix, which is recorded in the [HOST_ID_ANNOTATION].
+    /// Each rewritten Service is added to `rewritten`, also if a later one fails.
+    #[instrument(skip_all)]
+    pub(crate) async fn rewrite(
+        &self,
+        changes: &[RangeChange],
+        rewritten: &mut Vec<RewrittenService>,
+    ) -> Result<(), K8sError> {
+        let params = ListParams::default().labels(&format!("{}=true", REWRITE_PINNED_LABEL));
+        let services = self
+            .service_api
+            .list(&params)
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing Services: {}", e)))?;
+
+        for service in services {
+            let original = pinned_ips(&service);
+            let renumbered = rewritten_pins(&service, &original, changes, self.stable_host_ids);
+            let Some(renumbered) = renumbered else {
+                continue;
+            };
+            let namespace = service.namespace().unwrap_or_default();
+            let name = service.name_any();
+            let patched = self
+                .patch_service(
+                    &namespace,
+                    &name,
+                    &renumbered,
+                    &original,
+                    service.resource_version(),
+                )
+                .await?;
+            info!(
+                msg = "Rewrote pinned Service addresses",
+                service = format!("{}/{}", namespace, name),
+                addresses = ?renumbered
+            );
+            rewritten.push(RewrittenService {
+                namespace,
+                name,
+                original,
+                rewritten: renumbered,
+                resource_version: patched.resource_version(),
+            });
+        }
+        Ok(())
+    }
+
+    /// With stable host IDs, record the host part of every Service labelled with [REWRITE_PINNED_LABEL]=true
+    /// that has an ingress address in the `managed` ranges of a pool in its [HOST_ID_ANNOTATION].
+    /// This has to happen before the pool is patched, as MetalLB may hand out new addresses right after.
+    /// The host parts stay recorded if the update is rolled back, as they are still accurate.
+    #[instrument(skip(self, managed))]
+    pub(crate) async fn record_host_ids(
+        &self,
+        pool: &str,
+        managed: &[MetalLbAddressRange],
+    ) -> Result<(), K8sError> {
+        if !self.stable_host_ids {
+            return Ok(());
+        }
+        let params = ListParams::default().labels(&format!("{}=true", REWRITE_PINNED_LABEL));
+        let services = self
+            .service_api
+            .list(&params)
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing Services: {}", e)))?;
+
+        for service in services {
+            let original = pinned_ips(&service);
+            let Some(host_id) = recorded_host_id(&service, &original, managed) else {
+                continue;
+            };
+            let recorded = PinnedIps {
+                host_id: Some(host_id.to_string()),
+                ..original.clone()
+            };
+            let namespace = service.namespace().unwrap_or_default();
+            let name = service.name_any();
+            let patched = self
+                .patch_service(
+                    &namespace,
+                    &name,
+                    &recorded,
+                    &original,
+                    service.resource_version(),
+                )
+                .await;
+            match patched {
+                Ok(_) => info!(
+                    msg = "Recorded host part of Service",
+                    service = format!("{}/{}", namespace, name),
+                    host_id = host_id.to_string()
+                ),
+                Err(e) if e.is_conflict() => warn!(
+                    msg = "Service was modified concurrently, not recording its host part",
+                    service = format!("{}/{}", namespace, name)
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore the pinned addresses of rewritten Services that were not modified since
+    pub(crate) async fn revert(&self, rewritten: &[RewrittenService]) -> Result<(), K8sError> {
+        for service in rewritten {
+            let reverted = self
+                .patch_service(
+                    &service.namespace,
+                    &service.name,
+                    &service.original,
+                    &service.rewritten,
+                    service.resource_version.clone(),
+                )
+                .await;
+            match reverted {
+                Ok(_) => {}
+                Err(e) if e.is_conflict() => warn!(
+                    msg = "Service was modified concurrently, not reverting it",
+                    service = format!("{}/{}", service.namespace, service.name)
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn patch_service(
+        &self,
+        namespace: &str,
+        name: &str,
+        pinned: &PinnedIps,
+        replaced: &PinnedIps,
+        resource_version: Option<String>,
+    ) -> Result<Service, K8sError> {
+        let patch = pinned_patch(pinned, replaced, resource_version);
+        merge_patch(&self.service_api, namespace, name, &patch).await
+    }
+}
+
+fn pinned_ips(service: &Service) -> PinnedIps {
+    let annotations = service.annotations();
+    PinnedIps {
+        annotations: LOAD_BALANCER_IPS_ANNOTATIONS
+            .iter()
+            .filter_map(|a| Some((a.to_string(), annotations.get(*a)?.clone())))
+            .collect(),
+        load_balancer_ip: service
+            .spec
+            .as_ref()
+            .and_then(|s| s.load_balancer_ip.clone()),
+        host_id: annotations.get(HOST_ID_ANNOTATION).cloned(),
+    }
+}
+
+/// The addresses a Service requests from MetalLB, through annotations or `spec.loadBalancerIP`
+pub(crate) fn requested_addresses(service: &Service) -> Vec<IpAddr> {
+    let pinned = pinned_ips(service);
+    pinned
+        .annotations
+        .values()
+        .flat_map(|ips| ips.split(','))
+        .chain(pinned.load_balancer_ip.as_deref())
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect()
+}
+
+/// The new pinned addresses of an opted-in Service: its pinned addresses moved to the new managed ranges,
+/// or with stable host IDs its host part pinned under the new prefix. None if nothing has to change.
+fn rewritten_pins(
+    service: &Service,
+    pinned: &PinnedIps,
+    changes: &[RangeChange],
+    stable_host_ids: bool,
+) -> Option<PinnedIps> {
+    renumber_pinned(pinned, changes).or_else(|| {
+        stable_host_ids
+            .then(|| stable_pin(service, pinned, changes))
+            .flatten()
+    })
+}
+
+/// The pinned addresses with all addresses from previous managed ranges moved to the new ones.
+/// None if no address had to be moved.
+fn renumber_pinned(pinned: &PinnedIps, changes: &[RangeChange]) -> Option<PinnedIps> {
+    let mut moved = false;
+    let mut renumber = |ip: &str| match renumber_ip(ip.trim(), changes) {
+        Some(addr) => {
+            moved = true;
+            addr.to_string()
+        }
+        None => ip.trim().to_string(),
+    };
+    let renumbered = PinnedIps {
+        annotations: pinned
+            .annotations
+            .iter()
+            .map(|(k, v)| {
+                let ips = v.split(',').map(&mut renumber).collect::<Vec<_>>();
+                (k.clone(), ips.join(","))
+            })
+            .collect(),
+        load_balancer_ip: pinned.load_balancer_ip.as_deref().map(&mut renumber),
+        host_id: pinned.host_id.clone(),
+    };
+    moved.then_some(renumbered)
+}
+
+/// Pin a Service of a changed pool to its host part under the new prefix, through `metallb.io/loadBalancerIPs`.
+/// The host part is taken from the [HOST_ID_ANNOTATION], or from an ingress address in a previous managed range.
+/// Services pinned by the user without a recorded host part are left alone.
+/// None if nothing has to change.
+fn stable_pin(service: &Service, pinned: &PinnedIps, changes: &[RangeChange]) -> Option<PinnedIps> {
+    let user_pinned = pinned.load_balancer_ip.is_some() || !pinned.annotations.is_empty();
+    if pinned.host_id.is_none() && user_pinned {
+        return None;
+    }
+    let ingress = ingress_addresses(service);
+    changes
+        .iter()
+        .filter(|c| uses_pool(service, c))
+        .find_map(|change| {
+            let (previous, current) = moved_ranges(change);
+            // Services in a range that is kept do not move
+            let kept = v6_ranges(&change.current_managed)
+                .into_iter()
+                .filter(|r| !current.contains(r))
+                .collect::<Vec<_>>();
+            if ingress.iter().any(|a| match a {
+                IpAddr::V6(a) => kept.iter().any(|r| r.host_id(*a).is_some()),
+                IpAddr::V4(_) => false,
+            }) {
+                return None;
+            }
+            let host_id = match &pinned.host_id {
+                Some(h) => h.parse::<Ipv6Addr>().ok()?,
+                None => ingress.iter().find_map(|a| match a {
+                    IpAddr::V6(a) => previous.iter().find_map(|r| r.host_id(*a)),
+                    IpAddr::V4(_) => None,
+                })?,
+            };
+            let target = current.iter().find_map(|r| r.with_host_id(host_id))?;
+
+            // Keep all other requested addresses, such as IPv4 ones, and replace the managed IPv6 one
+            let requested = match pinned.annotations.get(LOAD_BALANCER_IPS_ANNOTATIONS[0]) {
+                Some(ips) => ips.split(',').map(|ip| ip.trim().to_string()).collect(),
+                None => ingress.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+            };
+            let mut requested = requested
+                .into_iter()
+                .filter(|ip| {
+                    ip.parse::<Ipv6Addr>().map_or(true, |a| {
+                        !previous
+                            .iter()
+                            .chain(&current)
+                            .any(|r| r.host_id(a).is_some())
+                    })
+                })
+                .collect::<Vec<_>>();
+            requested.push(target.to_string());
+
+            let mut annotations = pinned.annotations.clone();
+            annotations.insert(
+                LOAD_BALANCER_IPS_ANNOTATIONS[0].to_string(),
+                requested.join(","),
+            );
+            let stable = PinnedIps {
+                annotations,
+                load_balancer_ip: pinned.load_balancer_ip.clone(),
+                host_id: Some(host_id.to_string()),
+            };
+            (stable != *pinned).then_some(stable)
+        })
+}
+
+/// The host part to record for a Service that has none recorded yet, taken from its ingress address in a `managed` range.
+/// Services pinned by the user are left alone, like in [stable_pin].
+fn recorded_host_id(
+    service: &Service,
+    pinned: &PinnedIps,
+    managed: &[MetalLbAddressRange],
+) -> Option<Ipv6Addr> {
+    if pinned.host_id.is_some()
+        || pinned.load_balancer_ip.is_some()
+        || !pinned.annotations.is_empty()
+    {
+        return None;
+    }
+    let managed = v6_ranges(managed);
+    ingress_addresses(service).iter().find_map(|a| match a {
+        IpAddr::V6(a) => managed.iter().find_map(|r| r.host_id(*a)),
+        IpAddr::V4(_) => None,
+    })
+}
+
+fn v6_ranges(ranges: &[MetalLbAddressRange]) -> Vec<V6Range> {
+    ranges
+        .iter()
+        .filter_map(|r| match r {
+            MetalLbAddressRange::V6Range(r) => Some(*r),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The address in the new managed range that corresponds to an address in a previous one.
+/// None if the address stays the same, for example because its range did not change.
+fn renumber_ip(ip: &str, changes: &[RangeChange]) -> Option<Ipv6Addr> {
+    let addr = ip.parse::<Ipv6Addr>().ok()?;
+    changes
+        .iter()
+        .find_map(|c| {
+            let (removed, added) = moved_ranges(c);
+            removed
+                .iter()
+                .find_map(|p| added.iter().find_map(|n| p.renumber(addr, n)))
+        })
+        .filter(|r| *r != addr)
+}
+
+/// The managed IPv6 ranges removed from and added to a pool.
+/// Ranges that are kept, such as the one of another uplink, are left out, as their addresses do not move.
+fn moved_ranges(change: &RangeChange) -> (Vec<V6Range>, Vec<V6Range>) {
+    let previous = v6_ranges(&change.previous_managed);
+    let current = v6_ranges(&change.current_managed);
+    let removed = previous
+        .iter()
+        .filter(|r| !current.contains(r))
+        .copied()
+        .collect();
+    let added = current
+        .iter()
+        .filter(|r| !previous.contains(r))
+        .copied()
+        .collect();
+    (removed, added)
+}
+
+/// Merge patch that sets the pinned addresses, see [with_resource_version].
+/// Fields that are set in `replaced` but not in `pinned` are removed.
+fn pinned_patch(
+    pinned: &PinnedIps,
+    replaced: &PinnedIps,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let mut patch = json!({});
+    let mut annotations = pinned
+        .annotations
+        .iter()
+        .map(|(k, v)| (k.clone(), json!(v)))
+        .collect::<serde_json::Map<_, _>>();
+    for key in replaced.annotations.keys() {
+        annotations.entry(key.clone()).or_insert(json!(null));
+    }
+    if pinned.host_id.is_some() || replaced.host_id.is_some() {
+        annotations.insert(HOST_ID_ANNOTATION.to_string(), json!(pinned.host_id));
+    }
+    if !annotations.is_empty() {
+        patch["metadata"]["annotations"] = json!(annotations);
+    }
+    if pinned.load_balancer_ip.is_some() || replaced.load_balancer_ip.is_some() {
+        patch["spec"]["loadBalancerIP"] = json!(pinned.load_balancer_ip);
+    }
+    with_resource_version(patch, resource_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{load_balancer, range_change as change, two_range_change};
+
+    fn service(ip: &str) -> Service {
+        load_balancer("default", "web", &[ip])
+    }
+
+    #[test]
+    fn renumbers_pinned_addresses_in_managed_range() {
+        let pinned = PinnedIps {
+            annotations: [(
+                LOAD_BALANCER_IPS_ANNOTATIONS[1].to_string(),
+                "192.0.2.10, 2001:db8:aaaa::1234".to_string(),
+            )]
+            .into(),
+            load_balancer_ip: Some("2001:db8:aaaa::1001".to_string()),
+            host_id: None,
+        };
+        assert_eq!(
+            renumber_pinned(&pinned, &[change()]),
+            Some(PinnedIps {
+                annotations: [(
+                    LOAD_BALANCER_IPS_ANNOTATIONS[1].to_string(),
+                    "192.0.2.10,2001:db8:bbbb::1234".to_string(),
+                )]
+                .into(),
+                load_balancer_ip: Some("2001:db8:bbbb::1001".to_string()),
+                host_id: None,
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_addresses_outside_of_managed_range() {
+        let pinned = PinnedIps {
+            annotations: BTreeMap::new(),
+            load_balancer_ip: Some("2001:db8:aaaa::2000".to_string()),
+            host_id: None,
+        };
+        assert_eq!(renumber_pinned(&pinned, &[change()]), None);
+    }
+
+    #[test]
+    fn keeps_addresses_in_unchanged_range() {
+        let pinned = PinnedIps {
+            annotations: BTreeMap::new(),
+            load_balancer_ip: Some("2001:db8:cccc::1005".to_string()),
+            host_id: None,
+        };
+        assert_eq!(renumber_pinned(&pinned, &[two_range_change()]), None);
+
+        let moved = PinnedIps {
+            load_balancer_ip: Some("2001:db8:aaaa::1005".to_string()),
+            ..pinned
+        };
+        assert_eq!(
+            renumber_pinned(&moved, &[two_range_change()])
+                .unwrap()
+                .load_balancer_ip
+                .as_deref(),
+            Some("2001:db8:bbbb::1005")
+        );
+    }
+
+    #[test]
+    fn stable_pin_keeps_services_in_unchanged_range() {
+        let service = service("2001:db8:cccc::1005");
+        let pinned = pinned_ips(&service);
+        assert_eq!(stable_pin(&service, &pinned, &[two_range_change()]), None);
+
+        let recorded = PinnedIps {
+            host_id: Some("::1005".to_string()),
+            ..pinned
+        };
+        assert_eq!(stable_pin(&service, &recorded, &[two_range_change()]), None);
+    }
+
+    #[test]
+    fn pinned_patch_has_version_precondition() {
+        let pinned = PinnedIps {
+            annotations: BTreeMap::new(),
+            load_balancer_ip: Some("2001:db8:bbbb::1001".to_string()),
+            host_id: None,
+        };
+        assert_eq!(
+            pinned_patch(&pinned, &pinned, Some("42".to_string())),
+            json!({
+                "metadata": { "resourceVersion": "42" },
+                "spec": { "loadBalancerIP": "2001:db8:bbbb::1001" },
+            })
+        );
+    }
+
+    #[test]
+    fn stable_pin_keeps_host_part() {
+        let service = service("2001:db8:aaaa::1005");
+        let pinned = pinned_ips(&service);
+        let stable = stable_pin(&service, &pinned, &[change()]).unwrap();
+        assert_eq!(
+            stable,
+            PinnedIps {
+                annotations: [(
+                    LOAD_BALANCER_IPS_ANNOTATIONS[0].to_string(),
+                    "2001:db8:bbbb::1005".to_string(),
+                )]
+                .into(),
+                load_balancer_ip: None,
+                host_id: Some("::1005".to_string()),
+            }
+        );
+
+        // Reverting removes the annotations that were added
+        assert_eq!(
+            pinned_patch(&pinned, &stable, None),
+            json!({
+                "metadata": {
+                    "annotations": {
+                        LOAD_BALANCER_IPS_ANNOTATIONS[0]: null,
+                        HOST_ID_ANNOTATION: null,
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn stable_pin_uses_recorded_host_id() {
+        let service = service("2001:db8:aaaa::1005");
+        let pinned = PinnedIps {
+            annotations: [(
+                LOAD_BALANCER_IPS_ANNOTATIONS[0].to_string(),
+                "192.0.2.10,2001:db8:aaaa::1005".to_string(),
+            )]
+            .into(),
+            load_balancer_ip: None,
+            host_id: Some("::1005".to_string()),
+        };
+        assert_eq!(
+            stable_pin(&service, &pinned, &[change()])
+                .unwrap()
+                .annotations[LOAD_BALANCER_IPS_ANNOTATIONS[0]],
+            "192.0.2.10,2001:db8:bbbb::1005"
+        );
+    }
+
+    #[test]
+    fn only_pins_host_part_with_stable_host_ids() {
+        let service = service("2001:db8:aaaa::1005");
+        let pinned = pinned_ips(&service);
+        assert_eq!(rewritten_pins(&service, &pinned, &[change()], false), None);
+        assert_eq!(
+            rewritten_pins(&service, &pinned, &[change()], true)
+                .unwrap()
+                .host_id
+                .as_deref(),
+            Some("::1005")
+        );
+    }
+
+    #[test]
+    fn records_host_id_of_managed_ingress() {
+        let managed = change().previous_managed;
+        let outside = service("2001:db8:aaaa::2000");
+        let service = service("2001:db8:aaaa::1005");
+        let pinned = pinned_ips(&service);
+        assert_eq!(
+            recorded_host_id(&service, &pinned, &managed),
+            Some("::1005".parse().unwrap())
+        );
+
+        let recorded = PinnedIps {
+            host_id: Some("::1005".to_string()),
+            ..pinned.clone()
+        };
+        assert_eq!(recorded_host_id(&service, &recorded, &managed), None);
+        let user_pinned = PinnedIps {
+            load_balancer_ip: Some("2001:db8:aaaa::1005".to_string()),
+            ..pinned.clone()
+        };
+        assert_eq!(recorded_host_id(&service, &user_pinned, &managed), None);
+        assert_eq!(
+            recorded_host_id(&outside, &pinned_ips(&outside), &managed),
+            None
+        );
+    }
+
+    #[test]
+    fn stable_pin_leaves_user_pinned_services_alone() {
+        let service = service("2001:db8:aaaa::1005");
+        let pinned = PinnedIps {
+            annotations: BTreeMap::new(),
+            load_balancer_ip: Some("2001:db8:aaaa::1005".to_string()),
+            host_id: None,
+        };
+        assert_eq!(stable_pin(&service, &pinned, &[change()]), None);
+    }
+}
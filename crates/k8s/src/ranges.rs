@@ -89,6 +89,11 @@ impl MetalLbAddressRange {
             Self::V6Range(v6r) => IpAddr::V6(v6r.end),
         }
     }
+
+    /// Check whether the address lies within the range
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.start() <= addr && addr <= self.end()
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
@@ -115,6 +120,15 @@ impl Display for V6Range {
     }
 }
 
+impl From<Ipv6Net> for V6Range {
+    fn from(net: Ipv6Net) -> Self {
+        V6Range {
+            start: net.network(),
+            end: net.broadcast(),
+        }
+    }
+}
+
 impl V6Range {
     /// Create a dash-separated V6 range from a prefix and a host-address range
     pub fn from_host_range(prefix: Ipv6Net, host_range: V6HostRange) -> Self {
@@ -123,6 +137,71 @@ impl V6Range {
             end: Ipv6Addr::from(u128::from(prefix.network()) | u128::from(host_range.end)),
         }
     }
+
+    /// Check whether this range was created from the given host range, regardless of its prefix
+    pub fn has_host_range(&self, host_range: V6HostRange) -> bool {
+        let (start, end) = (u128::from(self.start), u128::from(self.end));
+        start & PREFIX_MASK == end & PREFIX_MASK
+            && start & !PREFIX_MASK == u128::from(host_range.start)
+            && end & !PREFIX_MASK == u128::from(host_range.end)
+    }
+
+    /// The host part of an address in this range, such as ::1005
+    pub fn host_id(&self, addr: Ipv6Addr) -> Option<Ipv6Addr> {
+        (self.start <= addr && addr <= self.end)
+            .then(|| Ipv6Addr::from(u128::from(addr) & !PREFIX_MASK))
+    }
+
+    /// The address in this range with the given host part
+    pub fn with_host_id(&self, host_id: Ipv6Addr) -> Option<Ipv6Addr> {
+        let addr = Ipv6Addr::from(
+            (u128::from(self.start) & PREFIX_MASK) | (u128::from(host_id) & !PREFIX_MASK),
+        );
+        (self.start <= addr && addr <= self.end).then_some(addr)
+    }
+
+    /// Move an address of this range to the same host address in `to`, which must have been created
+    /// from the same host range (such as the range for a new prefix).
+    /// Returns None if the address is not in this range or the host ranges differ.
+    pub fn renumber(&self, addr: Ipv6Addr, to: &V6Range) -> Option<Ipv6Addr> {
+        let host = |a: Ipv6Addr| u128::from(a) & !PREFIX_MASK;
+        if addr < self.start
+            || addr > self.end
+            || host(self.start) != host(to.start)
+            || host(self.end) != host(to.end)
+        {
+            return None;
+        }
+        Some(Ipv6Addr::from(
+            (u128::from(to.start) & PREFIX_MASK) | host(addr),
+        ))
+    }
+
+    /// The network that covers exactly this range, if the range is aligned to a prefix length
+    pub fn to_cidr(&self) -> Option<Ipv6Net> {
+        let (start, end) = (u128::from(self.start), u128::from(self.end));
+        let size = (end - start).checked_add(1)?;
+        if !size.is_power_of_two() || start % size != 0 {
+            return None;
+        }
+        Ipv6Net::new(self.start, 128 - size.trailing_zeros() as u8).ok()
+    }
+
+    /// Move an address from the network of this range to the network of `to`, keeping its host part.
+    /// Unlike [V6Range::renumber], the address does not have to be part of the range,
+    /// which covers other hosts in the same network such as routers.
+    /// Returns None if the address is in another network or the host ranges differ.
+    pub fn renumber_network(&self, addr: Ipv6Addr, to: &V6Range) -> Option<Ipv6Addr> {
+        let network = |a: Ipv6Addr| u128::from(a) & PREFIX_MASK;
+        let host = |a: Ipv6Addr| u128::from(a) & !PREFIX_MASK;
+        if network(addr) != network(self.start)
+            || host(self.start) != host(to.start)
+            || host(self.end) != host(to.end)
+        {
+            return None;
+        }
+        Some(Ipv6Addr::from(network(to.start) | host(addr)))
+    }
 }
 
 /// A range of Ipv6 host address parts for insertion into a MetalLB Ipv6 address range.
@@ -269,6 +348,17 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_address_range_contains() {
+        let range = "2001:db8::1000-2001:db8::1999"
+            .parse::<MetalLbAddressRange>()
+            .unwrap();
+        assert!(range.contains("2001:db8::1000".parse().unwrap()));
+        assert!(range.contains("2001:db8::1999".parse().unwrap()));
+        assert!(!range.contains("2001:db8::2000".parse().unwrap()));
+        assert!(!range.contains("192.0.2.1".parse().unwrap()));
+    }
+
     #[test]
     fn test_address_range_from_host_range() {
         let range = V6Range::from_host_range(
@@ -286,6 +376,98 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_address_range_has_host_range() {
+        let host_range = "::1000-::1999".parse::<V6HostRange>().unwrap();
+        let range = V6Range::from_host_range(
+            Ipv6Net::new("2001:db8:dead:beef::".parse().unwrap(), 64).unwrap(),
+            host_range,
+        );
+
+        assert!(range.has_host_range(host_range));
+        assert!(!range.has_host_range("::1000-::2000".parse().unwrap()));
+        assert!(!V6Range {
+            start: "2001:db8:dead:beef::1000".parse().unwrap(),
+            end: "2001:db8:dead:ffff::1999".parse().unwrap()
+        }
+        .has_host_range(host_range));
+    }
+
+    #[test]
+    fn test_address_range_renumber() {
+        let host_range = "::1000-::1999".parse::<V6HostRange>().unwrap();
+        let old = V6Range::from_host_range("2001:db8:aaaa::/64".parse().unwrap(), host_range);
+        let new = V6Range::from_host_range("2001:db8:bbbb::/64".parse().unwrap(), host_range);
+
+        assert_eq!(
+            old.renumber("2001:db8:aaaa::1234".parse().unwrap(), &new),
+            Some("2001:db8:bbbb::1234".parse().unwrap())
+        );
+        assert_eq!(
+            old.renumber("2001:db8:aaaa::2000".parse().unwrap(), &new),
+            None
+        );
+        let other = V6Range::from_host_range(
+            "2001:db8:bbbb::/64".parse().unwrap(),
+            "::2000-::2999".parse().unwrap(),
+        );
+        assert_eq!(
+            old.renumber("2001:db8:aaaa::1234".parse().unwrap(), &other),
+            None
+        );
+    }
+
+    #[test]
+    fn test_address_range_renumber_network() {
+        let host_range = "::1000-::1999".parse::<V6HostRange>().unwrap();
+        let old = V6Range::from_host_range("2001:db8:aaaa::/64".parse().unwrap(), host_range);
+        let new = V6Range::from_host_range("2001:db8:bbbb::/64".parse().unwrap(), host_range);
+
+        assert_eq!(
+            old.renumber_network("2001:db8:aaaa::1".parse().unwrap(), &new),
+            Some("2001:db8:bbbb::1".parse().unwrap())
+        );
+        assert_eq!(
+            old.renumber_network("2001:db8:cccc::1".parse().unwrap(), &new),
+            None
+        );
+    }
+
+    #[test]
+    fn test_address_range_cidr() {
+        let range = V6Range::from_host_range(
+            "2001:db8:aaaa::/64".parse().unwrap(),
+            "::1000-::1fff".parse().unwrap(),
+        );
+        let cidr = "2001:db8:aaaa::1000/116".parse::<Ipv6Net>().unwrap();
+        assert_eq!(range.to_cidr(), Some(cidr));
+        assert_eq!(V6Range::from(cidr), range);
+
+        let unaligned = V6Range::from_host_range(
+            "2001:db8:aaaa::/64".parse().unwrap(),
+            "::1000-::1999".parse().unwrap(),
+        );
+        assert_eq!(unaligned.to_cidr(), None);
+    }
+
+    #[test]
+    fn test_address_range_host_id() {
+        let range = V6Range::from_host_range(
+            "2001:db8:aaaa::/64".parse().unwrap(),
+            "::1000-::1999".parse().unwrap(),
+        );
+        assert_eq!(
+            range.host_id("2001:db8:aaaa::1005".parse().unwrap()),
+            Some("::1005".parse().unwrap())
+        );
+        assert_eq!(range.host_id("2001:db8:aaaa::2000".parse().unwrap()), None);
+        assert_eq!(
+            range.with_host_id("::1005".parse().unwrap()),
+            Some("2001:db8:aaaa::1005".parse().unwrap())
+        );
+        assert_eq!(range.with_host_id("::2000".parse().unwrap()), None);
+    }
+
     #[test]
     fn test_address_range_to_string() {
         let range = V6Range::from_host_range(
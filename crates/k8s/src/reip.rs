@@ -0,0 +1,184 @@
+use std::net::IpAddr;
+
+use k8s_openapi::api::core::v1::Service;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    runtime::events::{Event, EventType, Recorder},
+    Api, Client, Resource, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    verify::{outdated_addresses, uses_pool, RangeChange},
+    FIELD_MANAGER,
+};
+
+/// Makes MetalLB allocate new addresses to Services that still hold an IPv6 address outside of the new pool ranges
+#[derive(Debug)]
+pub(crate) struct ServiceReallocator {
+    service_api: Api<Service>,
+    /// Only Services in these namespaces are reallocated
+    namespaces: Vec<String>,
+}
+
+/// A Service that has to be reallocated, along with the addresses it has to give up
+struct StaleService<'a> {
+    service: &'a Service,
+    pool: &'a str,
+    addresses: Vec<IpAddr>,
+}
+
+impl ServiceReallocator {
+    pub(crate) fn new(client: Client, namespaces: Vec<String>) -> Self {
+        ServiceReallocator {
+            service_api: Api::all(client),
+            namespaces,
+        }
+    }
+
+    /// Clear the ingress status of Services in the allowed namespaces that use a changed pool
+    /// but hold an IPv6 address outside of its new ranges, so that MetalLB allocates a new one.
+    /// Each reallocation is recorded as an Event on the Service. Failures are only logged.
+    #[instrument(skip_all)]
+    pub(crate) async fn reallocate(&self, changes: &[RangeChange], recorder: &Recorder) {
+        if self.namespaces.is_empty() {
+            return;
+        }
+        let services = match self.service_api.list(&ListParams::default()).await {
+            Ok(s) => s.items,
+            Err(e) => {
+                warn!(
+                    msg = "Could not list Services for reallocation",
+                    error = e.to_string()
+                );
+                return;
+            }
+        };
+        for stale in stale_services(&services, changes, &self.namespaces) {
+            let namespace = stale.service.namespace().unwrap_or_default();
+            let name = stale.service.name_any();
+            if let Err(e) = self.clear_ingress(&namespace, &name).await {
+                warn!(
+                    msg = "Could not reallocate Service",
+                    service = format!("{}/{}", namespace, name),
+                    error = e.to_string()
+                );
+                continue;
+            }
+            info!(
+                msg = "Requested new address for Service",
+                service = format!("{}/{}", namespace, name),
+                previous = ?stale.addresses
+            );
+
+            let addresses = stale
+                .addresses
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>();
+            let event = Event {
+                type_: EventType::Normal,
+                reason: "Reallocating".to_string(),
+                note: Some(format!(
+                    "Address {} is outside of the ranges of pool {}, requesting a new address",
+                    addresses.join(","),
+                    stale.pool
+                )),
+                action: "ReIP".to_string(),
+                secondary: None,
+            };
+            if let Err(e) = recorder
+                .publish(&event, &stale.service.object_ref(&()))
+                .await
+            {
+                warn!(msg = "Could not record reallocation", error = e.to_string());
+            }
+        }
+    }
+
+    async fn clear_ingress(&self, namespace: &str, name: &str) -> Result<(), kube::Error> {
+        let patch = json!({ "status": { "loadBalancer": { "ingress": null } } });
+        let params = PatchParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+        Api::<Service>::namespaced(self.service_api.clone().into_client(), namespace)
+            .patch_status(name, &params, &Patch::Merge(&patch))
+            .await
+            .map(|s| debug!(service = ?s))
+    }
+}
+
+/// Services in the allowed namespaces that use a changed pool and hold IPv6 addresses outside of its new ranges
+fn stale_services<'a>(
+    services: &'a [Service],
+    changes: &'a [RangeChange],
+    namespaces: &[String],
+) -> Vec<StaleService<'a>> {
+    services
+        .iter()
+        .filter(|s| s.namespace().is_some_and(|ns| namespaces.contains(&ns)))
+        .filter_map(|service| {
+            changes.iter().find_map(|change| {
+                if !uses_pool(service, change) {
+                    return None;
+                }
+                let addresses = outdated_addresses(service, change)
+                    .into_iter()
+                    .filter(IpAddr::is_ipv6)
+                    .collect::<Vec<_>>();
+                (!addresses.is_empty()).then_some(StaleService {
+                    service,
+                    pool: &change.pool,
+                    addresses,
+                })
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{load_balancer, range_change};
+
+    fn namespaces(namespaces: &[&str]) -> Vec<String> {
+        namespaces.iter().map(|ns| ns.to_string()).collect()
+    }
+
+    #[test]
+    fn only_reallocates_stale_services_in_allowed_namespaces() {
+        let changes = [range_change()];
+        let services = [
+            load_balancer("apps", "web", &["2001:db8:aaaa::1000"]),
+            load_balancer("kube-system", "dns", &["2001:db8:aaaa::1001"]),
+            load_balancer("monitoring", "grafana", &["2001:db8:aaaa::1002"]),
+        ];
+        let stale = stale_services(&services, &changes, &namespaces(&["apps", "monitoring"]));
+        assert_eq!(
+            stale
+                .iter()
+                .map(|s| s.service.name_any())
+                .collect::<Vec<_>>(),
+            ["web", "grafana"]
+        );
+        assert_eq!(stale[0].pool, "public");
+        assert_eq!(
+            stale[0].addresses,
+            vec!["2001:db8:aaaa::1000".parse::<IpAddr>().unwrap()]
+        );
+        assert!(stale_services(&services, &changes, &[]).is_empty());
+    }
+
+    #[test]
+    fn keeps_services_in_new_range() {
+        let changes = [range_change()];
+        let services = [
+            load_balancer("apps", "web", &["2001:db8:bbbb::1000"]),
+            // IPv4 addresses are never reallocated
+            load_balancer("apps", "dual-stack", &["192.0.2.1", "2001:db8:bbbb::1001"]),
+        ];
+        assert!(stale_services(&services, &changes, &namespaces(&["apps"])).is_empty());
+    }
+}
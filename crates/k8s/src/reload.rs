@@ -0,0 +1,444 @@
+use std::time::Duration;
+
+use either::Either::Left;
+use k8s_openapi::{
+    api::{
+        apps::v1::{DaemonSet, Deployment},
+        core::v1::Pod,
+    },
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+    chrono::Utc,
+};
+use kube::{
+    api::{DeleteParams, ListParams, Patch, PatchParams},
+    runtime::{conditions::is_deleted, wait::await_condition},
+    Api, Client, ResourceExt,
+};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{K8sError, FIELD_MANAGER};
+
+/// Annotation on the pod template that triggers a rollout, as set by `kubectl rollout restart`
+const RESTARTED_AT_ANNOTATION: &str = "kubectl.kubernetes.io/restartedAt";
+
+/// Interval between checks whether the replaced MetalLB pods are ready
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// First MetalLB release that reconciles IPAddressPool changes live, without a restart
+const LIVE_RELOAD_VERSION: (u64, u64, u64) = (0, 13, 0);
+
+/// How MetalLB is made to pick up changed pools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReloadStrategy {
+    /// Do not restart MetalLB, as it picks up pool changes on its own
+    None,
+    /// Delete all MetalLB pods at once and wait for their replacements to become ready
+    DeletePods,
+    /// Restart the MetalLB Deployments and DaemonSets like `kubectl rollout restart`
+    /// and wait for the rollouts to complete
+    RolloutRestart,
+    /// Detect the MetalLB version from the controller image before each reload.
+    /// Uses [ReloadStrategy::None] for versions that reload live and [ReloadStrategy::DeletePods] otherwise.
+    Auto,
+}
+
+/// Reloads the MetalLB controller and speakers selected by a label selector
+#[derive(Debug)]
+pub(crate) struct MetalLbReloader {
+    strategy: ReloadStrategy,
+    label_selector: String,
+    ready_timeout: Duration,
+    pod_api: Api<Pod>,
+    deployment_api: Api<Deployment>,
+    daemonset_api: Api<DaemonSet>,
+}
+
+impl MetalLbReloader {
+    pub(crate) fn new(
+        client: Client,
+        namespace: &str,
+        label_selector: String,
+        strategy: ReloadStrategy,
+        ready_timeout: Duration,
+    ) -> Self {
+        MetalLbReloader {
+            strategy,
+            label_selector,
+            ready_timeout,
+            pod_api: Api::namespaced(client.clone(), namespace),
+            deployment_api: Api::namespaced(client.clone(), namespace),
+            daemonset_api: Api::namespaced(client, namespace),
+        }
+    }
+
+    pub(crate) async fn reload(&self) -> Result<(), K8sError> {
+        let strategy = match self.strategy {
+            ReloadStrategy::Auto => self.detect_strategy().await,
+            s => s,
+        };
+        info!(msg = "Reloading MetalLB", strategy = ?strategy, configured = ?self.strategy);
+        match strategy {
+            ReloadStrategy::None => {
+                info!("MetalLB picks up pool changes on its own, skipping restart");
+                Ok(())
+            }
+            ReloadStrategy::DeletePods => self.delete_pods().await,
+            ReloadStrategy::RolloutRestart => self.rollout_restart().await,
+            ReloadStrategy::Auto => unreachable!("auto is resolved to a concrete strategy"),
+        }
+    }
+
+    /// Choose the reload strategy based on the version of the MetalLB controller image.
+    /// Falls back to deleting the pods if the version can not be determined.
+    async fn detect_strategy(&self) -> ReloadStrategy {
+        let deployments = match self.deployment_api.list(&self.list_params()).await {
+            Ok(d) => d,
+            Err(e) => {
+                warn!(
+                    msg = "Could not detect MetalLB version",
+                    error = e.to_string()
+                );
+                return ReloadStrategy::DeletePods;
+            }
+        };
+        let version = deployments
+            .iter()
+            .filter_map(|d| d.spec.as_ref()?.template.spec.as_ref())
+            .flat_map(|s| &s.containers)
+            .filter_map(|c| c.image.as_deref())
+            .filter(|i| i.contains("controller"))
+            .find_map(image_version);
+        info!(msg = "Detected MetalLB version", version = ?version);
+        strategy_for_version(version)
+    }
+
+    fn list_params(&self) -> ListParams {
+        ListParams {
+            label_selector: Some(self.label_selector.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Forcibly delete all pods within the MetalLB namespace and wait for their replacements to become ready.
+    /// This is required to get MetalLB to accept a new configuration, as documented here:
+    /// https://github.com/metallb/metallb/issues/308
+    #[instrument(skip(self))]
+    async fn delete_pods(&self) -> Result<(), K8sError> {
+        info!(
+            msg = "Forcibly deleting MetalLB pods to pick up new addresses",
+            label_selector = ?self.label_selector
+        );
+        let mut deleted = Vec::new();
+        if let Left(del) = self
+            .pod_api
+            .delete_collection(&DeleteParams::default(), &self.list_params())
+            .await?
+        {
+            deleted = del.into_iter().map(|l| l.metadata).collect();
+        }
+
+        // Pods can get stuck terminating, for example on a lost node, so their deletion is part of the timeout
+        tokio::time::timeout(self.ready_timeout, self.wait_for_replacement(deleted))
+            .await
+            .map_err(|_| {
+                K8sError::new(format!(
+                    "MetalLB pods were not replaced and ready within {}s",
+                    self.ready_timeout.as_secs()
+                ))
+            })?
+    }
+
+    /// Wait until the deleted pods are gone and their replacements are ready
+    async fn wait_for_replacement(&self, deleted: Vec<ObjectMeta>) -> Result<(), K8sError> {
+        let count = deleted.len();
+        for pod in deleted {
+            let (Some(name), Some(uid)) = (pod.name, pod.uid) else {
+                warn!(msg = "Could not wait for pod deletion, metadata incomplete");
+                continue;
+            };
+            debug!(msg = "Waiting for pod deletion", pod = name);
+            await_condition(self.pod_api.clone(), &name, is_deleted(&uid))
+                .await
+                .map_err(|e| K8sError::new(e.to_string()))?;
+        }
+
+        info!(
+            msg = "Waiting for MetalLB pods to become ready",
+            pods = count
+        );
+        self.wait_for_pods(count).await
+    }
+
+    /// Wait until at least `expected` MetalLB pods exist and all of them are ready
+    async fn wait_for_pods(&self, expected: usize) -> Result<(), K8sError> {
+        loop {
+            let pods = self.pod_api.list(&self.list_params()).await?;
+            if pods_ready(&pods.items, expected) {
+                return Ok(());
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Restart all MetalLB Deployments and DaemonSets and wait for the rollouts to complete.
+    /// Pods are replaced according to the update strategy of each workload, so speakers restart gradually.
+    #[instrument(skip(self))]
+    async fn rollout_restart(&self) -> Result<(), K8sError> {
+        info!(
+            msg = "Restarting MetalLB workloads to pick up new addresses",
+            label_selector = ?self.label_selector
+        );
+        let restarted_at = Utc::now().to_rfc3339();
+        let patch = json!({
+            "spec": {
+                "template": {
+                    "metadata": {
+                        "annotations": {
+                            RESTARTED_AT_ANNOTATION: restarted_at,
+                        },
+                    },
+                },
+            },
+        });
+        let params = PatchParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+
+        let deployments = self.deployment_api.list(&self.list_params()).await?;
+        let daemonsets = self.daemonset_api.list(&self.list_params()).await?;
+        if deployments.items.is_empty() && daemonsets.items.is_empty() {
+            return Err(K8sError::new(format!(
+                "No MetalLB Deployments or DaemonSets match the label selector {}",
+                self.label_selector
+            )));
+        }
+
+        let mut restarted_deployments = Vec::new();
+        for d in deployments {
+            let patched = self
+                .deployment_api
+                .patch(&d.name_any(), &params, &Patch::Merge(&patch))
+                .await?;
+            restarted_deployments.push((patched.name_any(), patched.metadata.generation));
+        }
+        let mut restarted_daemonsets = Vec::new();
+        for ds in daemonsets {
+            let patched = self
+                .daemonset_api
+                .patch(&ds.name_any(), &params, &Patch::Merge(&patch))
+                .await?;
+            restarted_daemonsets.push((patched.name_any(), patched.metadata.generation));
+        }
+
+        let wait = async {
+            for (name, generation) in restarted_deployments {
+                debug!(msg = "Waiting for Deployment rollout", deployment = name);
+                let rolled_out = move |d: Option<&Deployment>| {
+                    d.is_some_and(|d| deployment_rolled_out(d, generation))
+                };
+                await_condition(self.deployment_api.clone(), &name, rolled_out)
+                    .await
+                    .map_err(|e| K8sError::new(e.to_string()))?;
+            }
+            for (name, generation) in restarted_daemonsets {
+                debug!(msg = "Waiting for DaemonSet rollout", daemonset = name);
+                let rolled_out = move |ds: Option<&DaemonSet>| {
+                    ds.is_some_and(|ds| daemonset_rolled_out(ds, generation))
+                };
+                await_condition(self.daemonset_api.clone(), &name, rolled_out)
+                    .await
+                    .map_err(|e| K8sError::new(e.to_string()))?;
+            }
+            Ok(())
+        };
+        tokio::time::timeout(self.ready_timeout, wait)
+            .await
+            .map_err(|_| {
+                K8sError::new(format!(
+                    "MetalLB rollout did not complete within {}s",
+                    self.ready_timeout.as_secs()
+                ))
+            })?
+    }
+}
+
+/// Parse the version from the tag of an image reference such as `quay.io/metallb/controller:v0.14.8`
+fn image_version(image: &str) -> Option<(u64, u64, u64)> {
+    let image = image.split('@').next()?;
+    let (repository, tag) = image.rsplit_once(':')?;
+    // A colon before the last slash belongs to the registry port, not the tag
+    if tag.contains('/') || repository.is_empty() {
+        return None;
+    }
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    let tag = tag.split(['-', '+']).next()?;
+    let mut parts = tag.split('.').map(|p| p.parse::<u64>().ok());
+    Some((
+        parts.next()??,
+        parts.next()??,
+        parts.next().flatten().unwrap_or(0),
+    ))
+}
+
+fn strategy_for_version(version: Option<(u64, u64, u64)>) -> ReloadStrategy {
+    match version {
+        Some(v) if v >= LIVE_RELOAD_VERSION => ReloadStrategy::None,
+        _ => ReloadStrategy::DeletePods,
+    }
+}
+
+/// Whether at least `expected` pods are running, none of them are being deleted and all of them are ready
+fn pods_ready(pods: &[Pod], expected: usize) -> bool {
+    pods.len() >= expected
+        && pods.iter().all(|p| {
+            p.metadata.deletion_timestamp.is_none()
+                && p.status
+                    .as_ref()
+                    .and_then(|s| s.conditions.as_ref())
+                    .is_some_and(|c| c.iter().any(|c| c.type_ == "Ready" && c.status == "True"))
+        })
+}
+
+/// Whether all replicas of a Deployment run the template of the given generation and are available
+fn deployment_rolled_out(deployment: &Deployment, generation: Option<i64>) -> bool {
+    let Some(status) = &deployment.status else {
+        return false;
+    };
+    let replicas = deployment
+        .spec
+        .as_ref()
+        .and_then(|s| s.replicas)
+        .unwrap_or(1);
+    status.observed_generation >= generation
+        && status.updated_replicas.unwrap_or(0) >= replicas
+        && status.replicas.unwrap_or(0) <= replicas
+        && status.available_replicas.unwrap_or(0) >= replicas
+}
+
+/// Whether all pods of a DaemonSet run the template of the given generation and are available
+fn daemonset_rolled_out(daemonset: &DaemonSet, generation: Option<i64>) -> bool {
+    let Some(status) = &daemonset.status else {
+        return false;
+    };
+    status.observed_generation >= generation
+        && status.updated_number_scheduled.unwrap_or(0) >= status.desired_number_scheduled
+        && status.number_available.unwrap_or(0) >= status.desired_number_scheduled
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::{
+        apps::v1::{DaemonSetStatus, DeploymentSpec, DeploymentStatus},
+        core::v1::{PodCondition, PodStatus},
+    };
+
+    use super::*;
+
+    fn deployment(
+        observed_generation: i64,
+        updated: i32,
+        total: i32,
+        available: i32,
+    ) -> Deployment {
+        Deployment {
+            spec: Some(DeploymentSpec {
+                replicas: Some(2),
+                ..Default::default()
+            }),
+            status: Some(DeploymentStatus {
+                observed_generation: Some(observed_generation),
+                updated_replicas: Some(updated),
+                replicas: Some(total),
+                available_replicas: Some(available),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn daemonset(
+        observed_generation: i64,
+        desired: i32,
+        updated: i32,
+        available: i32,
+    ) -> DaemonSet {
+        DaemonSet {
+            status: Some(DaemonSetStatus {
+                observed_generation: Some(observed_generation),
+                desired_number_scheduled: desired,
+                updated_number_scheduled: Some(updated),
+                number_available: Some(available),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn pod(ready: &str) -> Pod {
+        Pod {
+            status: Some(PodStatus {
+                conditions: Some(vec![PodCondition {
+                    type_: "Ready".to_string(),
+                    status: ready.to_string(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_image_version() {
+        assert_eq!(
+            image_version("quay.io/metallb/controller:v0.14.8"),
+            Some((0, 14, 8))
+        );
+        assert_eq!(
+            image_version("registry:5000/metallb/controller:0.12.1@sha256:abcd"),
+            Some((0, 12, 1))
+        );
+        assert_eq!(
+            image_version("quay.io/metallb/controller:v0.15.0-rc1"),
+            Some((0, 15, 0))
+        );
+        assert_eq!(image_version("registry:5000/metallb/controller"), None);
+        assert_eq!(image_version("quay.io/metallb/controller:main"), None);
+    }
+
+    #[test]
+    fn chooses_strategy_by_version() {
+        assert_eq!(strategy_for_version(Some((0, 14, 8))), ReloadStrategy::None);
+        assert_eq!(
+            strategy_for_version(Some((0, 12, 1))),
+            ReloadStrategy::DeletePods
+        );
+        assert_eq!(strategy_for_version(None), ReloadStrategy::DeletePods);
+    }
+
+    #[test]
+    fn pods_become_ready() {
+        assert!(!pods_ready(&[pod("True")], 2));
+        assert!(!pods_ready(&[pod("True"), pod("False")], 2));
+        assert!(!pods_ready(&[pod("True"), Pod::default()], 2));
+        assert!(pods_ready(&[pod("True"), pod("True")], 2));
+    }
+
+    #[test]
+    fn deployment_rollout_progress() {
+        assert!(!deployment_rolled_out(&deployment(1, 2, 2, 2), Some(2)));
+        assert!(!deployment_rolled_out(&deployment(2, 1, 3, 2), Some(2)));
+        assert!(!deployment_rolled_out(&deployment(2, 2, 3, 2), Some(2)));
+        assert!(deployment_rolled_out(&deployment(2, 2, 2, 2), Some(2)));
+    }
+
+    #[test]
+    fn daemonset_rollout_progress() {
+        assert!(!daemonset_rolled_out(&daemonset(1, 3, 3, 3), Some(2)));
+        assert!(!daemonset_rolled_out(&daemonset(2, 3, 2, 3), Some(2)));
+        assert!(!daemonset_rolled_out(&daemonset(2, 3, 3, 2), Some(2)));
+        assert!(daemonset_rolled_out(&daemonset(2, 3, 3, 3), Some(2)));
+    }
+}
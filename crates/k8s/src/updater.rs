@@ -1,44 +1,165 @@
-use either::Either::Left;
-use k8s_openapi::api::core::v1::Pod;
+use std::{fmt, time::Duration};
+
+use async_trait::async_trait;
 use kube::{
-    api::{DeleteParams, ListParams, Patch, PatchParams},
-    core::ObjectMeta,
-    runtime::{conditions::is_deleted, wait::await_condition},
-    Api, Client,
+    api::{ListParams, Patch, PatchParams},
+    Api, Client, Resource, ResourceExt,
 };
+use serde::de::DeserializeOwned;
+use serde_json::json;
 use thiserror::Error;
 use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
+    advertisement::{
+        revert_patch, AdvertisementKind, AdvertisementTemplate, AdvertisementUpdate,
+        SPEC_TEMPLATE_ANNOTATION,
+    },
+    backend::{apply_error, get_pool, record_rollback, skip_conflict, EventRecorder, PoolBackend},
+    drain::DrainingPools,
+    patch::with_resource_version,
+    peers::{BgpPeerRewriter, RewrittenPeer},
+    pinned::{PinnedIpRewriter, RewrittenService},
     ranges::MetalLbAddressRange,
-    v1beta1::ipaddresspool::{IPAddressPool, IPAddressPoolSpec, IPAddressPoolStatus},
+    reip::ServiceReallocator,
+    reload::{MetalLbReloader, ReloadStrategy},
+    v1beta1::{
+        bgpadvertisement::BGPAdvertisement, ipaddresspool::IPAddressPool,
+        l2advertisement::L2Advertisement,
+    },
+    verify::{AdoptionVerifier, RangeChange},
 };
 
+/// Annotation on the IPAddressPool that lists the address ranges created by metallb-dyn6
+pub const MANAGED_RANGES_ANNOTATION: &str = "dyn6.spacebird.dev/managed-ranges";
+/// Field manager used for server-side apply, so that other tools can see which fields metallb-dyn6 owns
+pub const FIELD_MANAGER: &str = "metallb-dyn6";
+/// Annotation that marks an IPAddressPool for management by metallb-dyn6, with the host range to use
+pub const HOST_RANGE_ANNOTATION: &str = "dyn6.spacebird.dev/host-range";
+/// Optional annotation with the subnet override of a discovered pool, such as `0:0:0:cd::/56`
+pub const SUBNET_OVERRIDE_ANNOTATION: &str = "dyn6.spacebird.dev/subnet-override";
+/// Optional annotation with the name of the uplink whose prefix a discovered pool uses
+pub const UPLINK_ANNOTATION: &str = "dyn6.spacebird.dev/uplink";
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MetalLbUpdaterConfig {
     pub namespace: String,
-    pub ip_pool: String,
+    /// Names of the IPAddressPools to manage
+    pub ip_pools: Vec<String>,
     pub label_selector: String,
+    /// Take ownership of the pool addresses even if they are managed by another field manager
+    pub force_conflicts: bool,
+    pub reload_strategy: ReloadStrategy,
+    /// Maximum time to wait for MetalLB to become ready again after a reload
+    pub ready_timeout: Duration,
+    /// Maximum time to wait for the Services of updated pools to receive addresses from the new ranges.
+    /// None disables the verification.
+    pub verify_timeout: Option<Duration>,
+    /// Namespaces in which Services that keep an address outside of the new ranges are made to request a new one.
+    /// Empty disables the reallocation.
+    pub reip_namespaces: Vec<String>,
+    /// Pin the Services of changed pools to their previous host part under the new prefix
+    pub stable_host_ids: bool,
+    /// Grace period during which removed ranges are kept in a draining pool with `autoAssign: false`.
+    /// None removes them right away.
+    pub drain_period: Option<Duration>,
+    /// Move the peer and source addresses of BGPPeers in the network of a previous managed range to the new one
+    pub rewrite_bgp_peers: bool,
+}
+
+/// The address ranges of a pool, along with the ones owned by metallb-dyn6
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolAddresses {
+    pub ranges: Vec<MetalLbAddressRange>,
+    /// Ranges created by metallb-dyn6, as recorded in the [MANAGED_RANGES_ANNOTATION].
+    /// None if the pool has no such annotation, for example because it was last updated by an older version.
+    pub managed: Option<Vec<MetalLbAddressRange>>,
+    /// Version of the pool that the ranges were read from
+    pub resource_version: Option<String>,
+}
+
+/// New address ranges for a single pool
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolUpdate {
+    pub pool: String,
+    pub addresses: Vec<MetalLbAddressRange>,
+    /// The subset of addresses that is managed by metallb-dyn6
+    pub managed: Vec<MetalLbAddressRange>,
+    /// Only update the pool if it still has this version, as read through [MetalLbUpdater::get_addresses]
+    pub resource_version: Option<String>,
+}
+
+/// An IPAddressPool that carries the [HOST_RANGE_ANNOTATION], along with its unparsed dyn6 annotations
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnnotatedPool {
+    pub name: String,
+    pub host_range: String,
+    pub subnet_override: Option<String>,
+    pub uplink: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct MetalLbUpdater {
     config: MetalLbUpdaterConfig,
     pool_api: Api<IPAddressPool>,
-    pod_api: Api<Pod>,
+    reloader: MetalLbReloader,
+    verifier: Option<AdoptionVerifier>,
+    rewriter: PinnedIpRewriter,
+    reallocator: ServiceReallocator,
+    draining: Option<DrainingPools>,
+    peer_rewriter: Option<BgpPeerRewriter>,
+    recorder: EventRecorder,
+    bgp_api: Api<BGPAdvertisement>,
+    l2_api: Api<L2Advertisement>,
+}
+
+/// Everything written during [MetalLbUpdater::set_addresses], so that it can be undone
+#[derive(Default)]
+struct AppliedChanges {
+    /// Pool name, original pool and the version written by us
+    pools: Vec<(String, IPAddressPool, Option<String>)>,
+    /// Ranges moved into the draining pool of a pool
+    drained: Vec<(String, Vec<MetalLbAddressRange>)>,
+    /// Draining pools as they were before ranges were released from them
+    released: Vec<IPAddressPool>,
+    /// Advertisements with the patch that restores their previous spec and the version written by us
+    advertisements: Vec<(AdvertisementKind, String, serde_json::Value, Option<String>)>,
+    services: Vec<RewrittenService>,
+    peers: Vec<RewrittenPeer>,
 }
 
 #[derive(Error, Debug)]
 #[error("Error while accessing the k8s API: {msg}")]
 pub struct K8sError {
     msg: String,
+    /// The resource was modified concurrently and the write was rejected
+    conflict: bool,
 }
-impl From<kube::Error> for K8sError {
-    fn from(value: kube::Error) -> Self {
+impl K8sError {
+    pub(crate) fn new(msg: String) -> Self {
         K8sError {
-            msg: value.to_string(),
+            msg,
+            conflict: false,
         }
     }
+
+    pub(crate) fn conflict(msg: String) -> Self {
+        K8sError {
+            msg,
+            conflict: true,
+        }
+    }
+
+    /// Whether the error was caused by a concurrent modification.
+    /// Such writes can be retried after reading the resource again.
+    pub fn is_conflict(&self) -> bool {
+        self.conflict
+    }
+}
+impl From<kube::Error> for K8sError {
+    fn from(value: kube::Error) -> Self {
+        K8sError::new(value.to_string())
+    }
 }
 
 impl MetalLbUpdater {
@@ -46,127 +167,788 @@ impl MetalLbUpdater {
     pub async fn new(config: MetalLbUpdaterConfig) -> Result<Self, K8sError> {
         debug!(
             msg = "Creating k8s Client for MetalLB access",
-            pool = ?config.ip_pool
+            pools = ?config.ip_pools
         );
         let client = Client::try_default().await?;
 
         let updater = MetalLbUpdater {
             config: config.clone(),
             pool_api: Api::namespaced(client.clone(), &config.namespace),
-            pod_api: Api::namespaced(client, &config.namespace),
+            reloader: MetalLbReloader::new(
+                client.clone(),
+                &config.namespace,
+                config.label_selector.clone(),
+                config.reload_strategy,
+                config.ready_timeout,
+            ),
+            verifier: config
+                .verify_timeout
+                .map(|t| AdoptionVerifier::new(client.clone(), t)),
+            rewriter: PinnedIpRewriter::new(client.clone(), config.stable_host_ids),
+            reallocator: ServiceReallocator::new(client.clone(), config.reip_namespaces.clone()),
+            draining: config
+                .drain_period
+                .map(|p| DrainingPools::new(Api::namespaced(client.clone(), &config.namespace), p)),
+            peer_rewriter: config
+                .rewrite_bgp_peers
+                .then(|| BgpPeerRewriter::new(Api::namespaced(client.clone(), &config.namespace))),
+            recorder: EventRecorder::new(client.clone()),
+            bgp_api: Api::namespaced(client.clone(), &config.namespace),
+            l2_api: Api::namespaced(client, &config.namespace),
         };
         info!(
-            msg = "Created k8s Client for Pool",
-            pool_name = ?config.ip_pool
+            msg = "Created k8s Client for Pools",
+            pool_names = ?config.ip_pools
         );
-        let pool = updater.get_pool().await?;
-        debug!(?pool);
+        for name in &config.ip_pools {
+            let pool = updater.get_pool(name).await?;
+            debug!(?pool);
+        }
         Ok(updater)
     }
 
-    pub async fn get_addresses(&self) -> Result<Vec<MetalLbAddressRange>, K8sError> {
-        let raw_addresses = self.get_pool().await?.spec.addresses;
-        raw_addresses
-            .iter()
-            .map(|a| {
-                a.parse::<MetalLbAddressRange>().map_err(|e| K8sError {
-                    msg: format!("Error while parsing IP pool addresses: {}", e),
-                })
-            })
-            .collect::<Result<Vec<_>, K8sError>>()
+    /// Make the writes of [pool_writes] for a single pool, adding each one to `applied` as soon as it is made.
+    /// Returns the change of the pool ranges, if any.
+    async fn write_pool(
+        &self,
+        update: &PoolUpdate,
+        applied: &mut AppliedChanges,
+    ) -> Result<Option<RangeChange>, K8sError> {
+        let original = self.get_pool(&update.pool).await?;
+        if update.resource_version.is_some()
+            && original.metadata.resource_version != update.resource_version
+        {
+            return Err(conflict(&update.pool));
+        }
+
+        let mut draining_ranges = Vec::new();
+        for write in pool_writes(&original, update, self.draining.is_some()) {
+            match (write, &self.draining) {
+                (PoolWrite::Release(ranges), Some(draining)) => {
+                    applied
+                        .released
+                        .extend(draining.release(&update.pool, &ranges).await?);
+                }
+                (PoolWrite::Drain(ranges), Some(draining)) => {
+                    draining_ranges = draining.drain(&original, &ranges).await?;
+                    applied.drained.push((update.pool.clone(), ranges));
+                }
+                (PoolWrite::Patch, _) => {
+                    let previous = parse_ranges(original.spec.addresses.iter()).unwrap_or_default();
+                    self.rewriter
+                        .record_host_ids(&update.pool, &previous_managed(&original, &previous))
+                        .await?;
+                    let patched = self
+                        .patch_pool(
+                            &update.pool,
+                            update.addresses.iter().map(|a| a.to_string()).collect(),
+                            Some(
+                                update
+                                    .managed
+                                    .iter()
+                                    .map(|a| a.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(","),
+                            ),
+                            original.metadata.resource_version.clone(),
+                            takes_ownership(&original, self.config.force_conflicts),
+                        )
+                        .await?;
+                    applied.pools.push((
+                        update.pool.clone(),
+                        original.clone(),
+                        patched.metadata.resource_version,
+                    ));
+                }
+                (_, None) => {}
+            }
+        }
+
+        let previous = parse_ranges(original.spec.addresses.iter()).unwrap_or_default();
+        if previous == update.addresses {
+            return Ok(None);
+        }
+        let mut current = update.addresses.clone();
+        // Services may keep their addresses from the draining pool
+        current.extend(draining_ranges);
+        Ok(Some(RangeChange {
+            pool: update.pool.clone(),
+            previous_managed: previous_managed(&original, &previous),
+            previous,
+            current,
+            current_managed: update.managed.clone(),
+        }))
     }
 
-    pub async fn set_addresses(&self, addresses: Vec<MetalLbAddressRange>) -> Result<(), K8sError> {
-        let original_pool: IPAddressPool = self.get_pool().await?;
-        let mut new_spec = original_pool.clone().spec;
-        new_spec.addresses = addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    /// Undo the changes of a failed update.
+    /// Drained ranges are released first and released ranges are restored last, as they can not be part of two pools at once.
+    async fn roll_back(&self, applied: &AppliedChanges, cause: &K8sError) -> Result<(), K8sError> {
+        self.rewriter.revert(&applied.services).await?;
+        if let Some(peer_rewriter) = &self.peer_rewriter {
+            peer_rewriter.revert(&applied.peers).await?;
+        }
+        for (kind, name, revert, version) in &applied.advertisements {
+            let reverted = self
+                .apply_advertisement(*kind, name, revert, version.clone())
+                .await;
+            match reverted {
+                Ok(_) => {}
+                Err(e) if e.is_conflict() => warn!(
+                    msg = "Advertisement was modified concurrently, not reverting it",
+                    kind = kind.to_string(),
+                    name
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(draining) = &self.draining {
+            for (pool, ranges) in &applied.drained {
+                draining.release(pool, ranges).await?;
+            }
+        }
+        self.revert_pools(&applied.pools, cause).await?;
+        if let Some(draining) = &self.draining {
+            for previous in &applied.released {
+                draining.restore(previous).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the rendered fields of an advertisement.
+    /// Returns the spec fields that restore the previous values and the written version,
+    /// or None if the advertisement already has the rendered values.
+    async fn patch_advertisement(
+        &self,
+        update: &AdvertisementUpdate,
+    ) -> Result<Option<(serde_json::Value, Option<String>)>, K8sError> {
+        let current = match update.kind {
+            AdvertisementKind::Bgp => get_spec(&self.bgp_api, &update.name).await,
+            AdvertisementKind::L2 => get_spec(&self.l2_api, &update.name).await,
+        };
+        let (current, version) = current.map_err(|e| {
+            K8sError::new(format!(
+                "Error reading {} {}: {}",
+                update.kind, update.name, e
+            ))
+        })?;
+        let revert = revert_patch(&current, &update.spec);
+        if revert == update.spec {
+            return Ok(None);
+        }
+        let version = self
+            .apply_advertisement(update.kind, &update.name, &update.spec, version)
+            .await?;
+        Ok(Some((revert, version)))
+    }
 
-        self.patch_pool(&PatchParams::default(), new_spec).await?;
-        info!(msg = "Pool updated");
+    /// Apply `spec` to an advertisement with server-side apply, if it still has the given version.
+    /// Ownership of the fields is always taken, as the [SPEC_TEMPLATE_ANNOTATION] hands them to us.
+    /// Returns the version written.
+    async fn apply_advertisement(
+        &self,
+        kind: AdvertisementKind,
+        name: &str,
+        spec: &serde_json::Value,
+        resource_version: Option<String>,
+    ) -> Result<Option<String>, K8sError> {
+        let mut params = PatchParams::apply(FIELD_MANAGER);
+        params.force = true;
+        let patched = match kind {
+            AdvertisementKind::Bgp => {
+                let patch = advertisement_patch::<BGPAdvertisement>(name, spec, resource_version);
+                debug!(patch = ?patch);
+                self.bgp_api
+                    .patch(name, &params, &Patch::Apply(&patch))
+                    .await
+                    .map(|a| a.metadata.resource_version)
+            }
+            AdvertisementKind::L2 => {
+                let patch = advertisement_patch::<L2Advertisement>(name, spec, resource_version);
+                debug!(patch = ?patch);
+                self.l2_api
+                    .patch(name, &params, &Patch::Apply(&patch))
+                    .await
+                    .map(|a| a.metadata.resource_version)
+            }
+        };
+        patched.map_err(|e| match e {
+            kube::Error::Api(ae) if ae.code == 409 => {
+                K8sError::conflict(format!("{} {} was modified concurrently", kind, name))
+            }
+            e => K8sError::new(format!("Error updating {} {}: {}", kind, name, e)),
+        })
+    }
 
-        if let Err(e) = self.force_reset_metallb().await {
-            error!(
-                msg = "Error while restarting MetalLB, reverting Pool change...",
-                error = e.to_string()
-            );
-            self.patch_pool(&PatchParams::default(), original_pool.spec.clone())
-                .await?;
-            self.force_reset_metallb().await?;
-            info!(msg = "Pool change reverted");
+    /// Restore the addresses and managed ranges annotation of the given pools.
+    /// Each pool is only reverted if it still has the version written by us,
+    /// so that concurrent changes by others are never overwritten.
+    /// Every rollback is recorded as a Warning Event on the pool, with `cause` as the reason.
+    async fn revert_pools(
+        &self,
+        originals: &[(String, IPAddressPool, Option<String>)],
+        cause: &K8sError,
+    ) -> Result<(), K8sError> {
+        for (name, original_pool, patched_version) in originals {
+            let original_managed = original_pool
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get(MANAGED_RANGES_ANNOTATION))
+                .cloned();
+            let reverted = self
+                .patch_pool(
+                    name,
+                    original_pool.spec.addresses.clone(),
+                    original_managed,
+                    patched_version.clone(),
+                    self.config.force_conflicts,
+                )
+                .await;
+            if skip_conflict(reverted, name)? {
+                let restored = format!(
+                    "Restored addresses {}",
+                    original_pool.spec.addresses.join(",")
+                );
+                record_rollback(
+                    &self.recorder,
+                    &original_pool.object_ref(&()),
+                    restored,
+                    cause,
+                )
+                .await;
+            }
         }
         Ok(())
     }
 
     #[instrument(skip(self))]
-    async fn get_pool(&self) -> Result<IPAddressPool, K8sError> {
+    async fn get_pool(&self, name: &str) -> Result<IPAddressPool, K8sError> {
+        get_pool(&self.pool_api, name).await
+    }
+
+    /// Apply the pool addresses and the managed ranges annotation with server-side apply.
+    /// If `managed` is None, the annotation is removed.
+    /// If `resource_version` is set, the pool is only updated if it still has this version.
+    /// Fails if another field manager owns the addresses, unless `force` is set.
+    #[instrument(skip(self))]
+    async fn patch_pool(
+        &self,
+        name: &str,
+        addresses: Vec<String>,
+        managed: Option<String>,
+        resource_version: Option<String>,
+        force: bool,
+    ) -> Result<IPAddressPool, K8sError> {
+        let patch = apply_patch(name, addresses, managed, resource_version);
+        debug!(patch = ?patch);
+
+        let mut params = PatchParams::apply(FIELD_MANAGER);
+        params.force = force;
         self.pool_api
-            .get(&self.config.ip_pool)
+            .patch(name, &params, &Patch::Apply(&patch))
             .await
             .map(|p| {
                 debug!(pool = ?p);
                 p
             })
-            .map_err(|e| K8sError {
-                msg: format!("Error reading pool: {}", e),
-            })
+            .map_err(|e| apply_error(e, name, "addresses"))
     }
+}
 
+#[async_trait]
+impl PoolBackend for MetalLbUpdater {
+    /// List all pools in the namespace that are annotated for management by metallb-dyn6
     #[instrument(skip(self))]
-    async fn patch_pool(
+    async fn discover_pools(&self) -> Result<Vec<AnnotatedPool>, K8sError> {
+        let pools = self
+            .pool_api
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing pools: {}", e)))?;
+        let annotated = pools.iter().filter_map(annotated_pool).collect::<Vec<_>>();
+        debug!(pools = ?annotated);
+        Ok(annotated)
+    }
+
+    /// List all BGPAdvertisements and L2Advertisements in the namespace that carry a [SPEC_TEMPLATE_ANNOTATION]
+    #[instrument(skip(self))]
+    async fn discover_advertisements(&self) -> Result<Vec<AdvertisementTemplate>, K8sError> {
+        let mut templates = Vec::new();
+        let bgp = self
+            .bgp_api
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing BGPAdvertisements: {}", e)))?;
+        for advertisement in bgp {
+            templates.extend(advertisement_template(
+                AdvertisementKind::Bgp,
+                &advertisement,
+            ));
+        }
+        let l2 = self
+            .l2_api
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| K8sError::new(format!("Error listing L2Advertisements: {}", e)))?;
+        for advertisement in l2 {
+            templates.extend(advertisement_template(
+                AdvertisementKind::L2,
+                &advertisement,
+            ));
+        }
+        debug!(advertisements = ?templates);
+        Ok(templates)
+    }
+
+    async fn get_addresses(&self, pool: &str) -> Result<PoolAddresses, K8sError> {
+        let pool = self.get_pool(pool).await?;
+        let managed = managed_ranges(&pool)?;
+        Ok(PoolAddresses {
+            ranges: parse_ranges(pool.spec.addresses.iter())?,
+            managed,
+            resource_version: pool.metadata.resource_version,
+        })
+    }
+
+    /// Replace the address ranges of one or more pools and record which of them are managed by metallb-dyn6.
+    /// Services that pin addresses from a previous managed range and carry the [crate::REWRITE_PINNED_LABEL] are moved to
+    /// the new range. MetalLB is restarted once after all pools have been updated.
+    /// Services in the configured re-IP namespaces that still hold an address from a previous range
+    /// are then made to request a new one.
+    /// If a verify timeout is configured, the update only succeeds once the Services of the pools use the new ranges.
+    /// With a drain period, removed managed ranges are moved into a draining pool, whose addresses Services may keep.
+    /// The rendered `advertisements` are updated along with the pools and reverted with them.
+    /// If enabled, BGPPeers with addresses in the network of a previous managed range are moved to the new one.
+    /// If any step fails, all pools are reverted to their original state and the rollback is recorded as an Event.
+    /// If a pool was modified since its addresses were read, the error is a conflict (see [K8sError::is_conflict])
+    /// and the update should be recalculated.
+    async fn set_addresses(
         &self,
-        params: &PatchParams,
-        spec: IPAddressPoolSpec,
+        updates: Vec<PoolUpdate>,
+        advertisements: Vec<AdvertisementUpdate>,
     ) -> Result<(), K8sError> {
-        let patch = IPAddressPool {
-            metadata: ObjectMeta {
-                ..Default::default()
+        let mut applied = AppliedChanges::default();
+        let mut changes = Vec::new();
+        for update in updates {
+            match self.write_pool(&update, &mut applied).await {
+                Ok(change) => {
+                    info!(msg = "Pool updated", pool = update.pool);
+                    changes.extend(change);
+                }
+                Err(e) => {
+                    error!(
+                        msg = "Error while updating Pool, reverting previous Pool changes...",
+                        pool = update.pool,
+                        error = e.to_string()
+                    );
+                    self.roll_back(&applied, &e).await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        for update in &advertisements {
+            match self.patch_advertisement(update).await {
+                Ok(Some((revert, version))) => {
+                    info!(
+                        msg = "Advertisement updated",
+                        kind = update.kind.to_string(),
+                        name = update.name
+                    );
+                    applied.advertisements.push((
+                        update.kind,
+                        update.name.clone(),
+                        revert,
+                        version,
+                    ));
+                }
+                Ok(None) => debug!(
+                    msg = "Advertisement is up to date",
+                    kind = update.kind.to_string(),
+                    name = update.name
+                ),
+                Err(e) => {
+                    error!(
+                        msg = "Error while updating advertisement, reverting Pool changes...",
+                        name = update.name,
+                        error = e.to_string()
+                    );
+                    self.roll_back(&applied, &e).await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        if !changes.is_empty() {
+            if let Err(e) = self.rewriter.rewrite(&changes, &mut applied.services).await {
+                error!(
+                    msg =
+                        "Error while rewriting pinned Service addresses, reverting Pool changes...",
+                    error = e.to_string()
+                );
+                self.roll_back(&applied, &e).await?;
+                return Err(e);
+            }
+        }
+        if let (Some(peer_rewriter), false) = (&self.peer_rewriter, changes.is_empty()) {
+            if let Err(e) = peer_rewriter.rewrite(&changes, &mut applied.peers).await {
+                error!(
+                    msg = "Error while rewriting BGPPeer addresses, reverting Pool changes...",
+                    error = e.to_string()
+                );
+                self.roll_back(&applied, &e).await?;
+                return Err(e);
+            }
+        }
+
+        if let Err(e) = self.reloader.reload().await {
+            error!(
+                msg = "Error while restarting MetalLB, reverting Pool changes...",
+                error = e.to_string()
+            );
+            self.roll_back(&applied, &e).await?;
+            self.reloader.reload().await?;
+            info!(msg = "Pool changes reverted");
+            return Err(e);
+        }
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.reallocator.reallocate(&changes, &self.recorder).await;
+        let Some(verifier) = &self.verifier else {
+            return Ok(());
+        };
+        if let Err(e) = verifier.verify(&changes).await {
+            error!(
+                msg = "MetalLB did not adopt the new ranges, reverting Pool changes...",
+                error = e.to_string()
+            );
+            self.roll_back(&applied, &e).await?;
+            self.reloader.reload().await?;
+            info!(msg = "Pool changes reverted");
+            return Err(e);
+        }
+        info!(msg = "MetalLB adopted the new ranges");
+        Ok(())
+    }
+
+    /// Delete the draining pools whose grace period is over. Does nothing if draining is disabled.
+    async fn remove_expired_draining_pools(&self) -> Result<(), K8sError> {
+        match &self.draining {
+            Some(draining) => draining.remove_expired().await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether an update takes ownership of the pool addresses from other field managers.
+/// Pools without the managed ranges annotation have never been written by metallb-dyn6 with server-side apply,
+/// so their addresses are still owned by whoever created them (such as kubectl, Helm or Argo CD).
+/// Ownership is taken once on the first update, afterwards conflicts are only overridden with `force_conflicts`.
+pub(crate) fn takes_ownership(pool: &impl ResourceExt, force_conflicts: bool) -> bool {
+    force_conflicts || !pool.annotations().contains_key(MANAGED_RANGES_ANNOTATION)
+}
+
+/// Build the server-side apply patch for a pool, containing only the fields owned by metallb-dyn6
+fn apply_patch(
+    name: &str,
+    addresses: Vec<String>,
+    managed: Option<String>,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    let mut metadata = json!({ "name": name });
+    if let Some(managed) = managed {
+        metadata["annotations"] = json!({ MANAGED_RANGES_ANNOTATION: managed });
+    }
+    with_resource_version(
+        json!({
+            "apiVersion": IPAddressPool::api_version(&()),
+            "kind": IPAddressPool::kind(&()),
+            "metadata": metadata,
+            "spec": {
+                "addresses": addresses,
             },
-            spec,
-            status: Some(IPAddressPoolStatus {}),
+        }),
+        resource_version,
+    )
+}
+
+/// Build the server-side apply patch that sets the given spec fields of an advertisement, see [with_resource_version].
+/// Fields that are null in `spec` are left out, which removes them as they are owned by us.
+fn advertisement_patch<K: Resource<DynamicType = ()>>(
+    name: &str,
+    spec: &serde_json::Value,
+    resource_version: Option<String>,
+) -> serde_json::Value {
+    with_resource_version(
+        json!({
+            "apiVersion": K::api_version(&()),
+            "kind": K::kind(&()),
+            "metadata": { "name": name },
+            "spec": without_nulls(spec),
+        }),
+        resource_version,
+    )
+}
+
+fn without_nulls(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(fields) => serde_json::Value::Object(
+            fields
+                .iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// The dyn6 annotations of a pool, if it carries a [HOST_RANGE_ANNOTATION]
+pub(crate) fn annotated_pool(pool: &impl ResourceExt) -> Option<AnnotatedPool> {
+    let annotations = pool.annotations();
+    Some(AnnotatedPool {
+        name: pool.name_any(),
+        host_range: annotations.get(HOST_RANGE_ANNOTATION)?.clone(),
+        subnet_override: annotations.get(SUBNET_OVERRIDE_ANNOTATION).cloned(),
+        uplink: annotations.get(UPLINK_ANNOTATION).cloned(),
+    })
+}
+
+/// The template of an advertisement, if it carries a [SPEC_TEMPLATE_ANNOTATION]
+fn advertisement_template(
+    kind: AdvertisementKind,
+    advertisement: &impl ResourceExt,
+) -> Option<AdvertisementTemplate> {
+    let annotations = advertisement.annotations();
+    Some(AdvertisementTemplate {
+        kind,
+        name: advertisement.name_any(),
+        template: annotations.get(SPEC_TEMPLATE_ANNOTATION)?.clone(),
+        uplink: annotations.get(UPLINK_ANNOTATION).cloned(),
+    })
+}
+
+/// The spec of a resource as JSON, along with its version
+async fn get_spec<K>(
+    api: &Api<K>,
+    name: &str,
+) -> Result<(serde_json::Value, Option<String>), K8sError>
+where
+    K: Resource + Clone + DeserializeOwned + fmt::Debug + serde::Serialize,
+{
+    let resource = api.get(name).await?;
+    let version = resource.meta().resource_version.clone();
+    let value = serde_json::to_value(&resource)
+        .map_err(|e| K8sError::new(format!("Error serializing {}: {}", name, e)))?;
+    Ok((value["spec"].clone(), version))
+}
+
+/// A write made while updating a pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PoolWrite {
+    /// Take ranges that are assigned to the pool again out of its draining pool
+    Release(Vec<MetalLbAddressRange>),
+    /// Move removed managed ranges into the draining pool
+    Drain(Vec<MetalLbAddressRange>),
+    /// Set the addresses of the pool itself
+    Patch,
+}
+
+/// The writes that update the `original` pool, in the order in which they have to be made.
+/// MetalLB does not allow overlapping pools, so ranges are released before they are added back to the pool.
+/// Removed ranges are drained before the pool is patched, so that the draining pool exists by the time
+/// MetalLB reloads the pool and Services never lose their address in between.
+fn pool_writes(original: &IPAddressPool, update: &PoolUpdate, draining: bool) -> Vec<PoolWrite> {
+    if !draining {
+        return vec![PoolWrite::Patch];
+    }
+    let previous = parse_ranges(original.spec.addresses.iter()).unwrap_or_default();
+    let removed = previous_managed(original, &previous)
+        .into_iter()
+        .filter(|r| !update.addresses.contains(r))
+        .collect::<Vec<_>>();
+    let mut writes = vec![PoolWrite::Release(update.addresses.clone())];
+    if !removed.is_empty() {
+        writes.push(PoolWrite::Drain(removed));
+    }
+    writes.push(PoolWrite::Patch);
+    writes
+}
+
+/// The managed ranges of a pool with the `previous` ranges.
+/// Pools without the annotation were last updated by an older version that managed all ranges.
+fn previous_managed(
+    pool: &IPAddressPool,
+    previous: &[MetalLbAddressRange],
+) -> Vec<MetalLbAddressRange> {
+    managed_ranges(pool)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| previous.to_vec())
+}
+
+pub(crate) fn conflict(pool: &str) -> K8sError {
+    K8sError::conflict(format!("Pool {} was modified concurrently", pool))
+}
+
+/// The ranges recorded in the [MANAGED_RANGES_ANNOTATION] of a pool, if it has one
+pub(crate) fn managed_ranges(
+    pool: &impl ResourceExt,
+) -> Result<Option<Vec<MetalLbAddressRange>>, K8sError> {
+    pool.annotations()
+        .get(MANAGED_RANGES_ANNOTATION)
+        .map(|a| parse_ranges(a.split(',').filter(|r| !r.is_empty())))
+        .transpose()
+}
+
+pub(crate) fn parse_ranges<'a>(
+    ranges: impl Iterator<Item = impl AsRef<str> + 'a>,
+) -> Result<Vec<MetalLbAddressRange>, K8sError> {
+    ranges
+        .map(|a| {
+            a.as_ref()
+                .trim()
+                .parse::<MetalLbAddressRange>()
+                .map_err(|e| K8sError::new(format!("Error while parsing IP pool addresses: {}", e)))
+        })
+        .collect::<Result<Vec<_>, K8sError>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_ownership_of_unmanaged_pools() {
+        use crate::v1beta1::ipaddresspool::IPAddressPoolSpec;
+
+        let mut pool = IPAddressPool::new(
+            "public",
+            IPAddressPoolSpec {
+                addresses: vec!["2001:db8:aaaa::1000-2001:db8:aaaa::1999".to_string()],
+                auto_assign: None,
+                avoid_buggy_i_ps: None,
+                service_allocation: None,
+            },
+        );
+        assert!(takes_ownership(&pool, false));
+        pool.annotations_mut().insert(
+            MANAGED_RANGES_ANNOTATION.to_string(),
+            "2001:db8:aaaa::1000-2001:db8:aaaa::1999".to_string(),
+        );
+        assert!(!takes_ownership(&pool, false));
+        assert!(takes_ownership(&pool, true));
+    }
+
+    #[test]
+    fn drains_removed_ranges_before_patching_pool() {
+        use crate::v1beta1::ipaddresspool::IPAddressPoolSpec;
+
+        let old: MetalLbAddressRange = "2001:db8:aaaa::1000-2001:db8:aaaa::1999".parse().unwrap();
+        let static_range: MetalLbAddressRange =
+            "2001:db8:ffff::1-2001:db8:ffff::9".parse().unwrap();
+        let new = "2001:db8:bbbb::1000-2001:db8:bbbb::1999".parse().unwrap();
+        let mut pool = IPAddressPool::new(
+            "public",
+            IPAddressPoolSpec {
+                addresses: vec![old.to_string(), static_range.to_string()],
+                auto_assign: None,
+                avoid_buggy_i_ps: None,
+                service_allocation: None,
+            },
+        );
+        pool.annotations_mut()
+            .insert(MANAGED_RANGES_ANNOTATION.to_string(), old.to_string());
+        let update = PoolUpdate {
+            pool: "public".to_string(),
+            addresses: vec![new, static_range],
+            managed: vec![new],
+            resource_version: None,
         };
 
-        debug!(patch = ?patch);
+        assert_eq!(
+            pool_writes(&pool, &update, true),
+            vec![
+                PoolWrite::Release(vec![new, static_range]),
+                PoolWrite::Drain(vec![old]),
+                PoolWrite::Patch,
+            ]
+        );
+        assert_eq!(pool_writes(&pool, &update, false), vec![PoolWrite::Patch]);
 
-        self.pool_api
-            .patch(&self.config.ip_pool, params, &Patch::Merge(&patch))
-            .await
-            .map(|p| {
-                debug!(pool = ?p);
-            })
-            .map_err(Into::into)
+        let unchanged = PoolUpdate {
+            addresses: vec![old, static_range],
+            managed: vec![old],
+            ..update
+        };
+        assert_eq!(
+            pool_writes(&pool, &unchanged, true),
+            vec![
+                PoolWrite::Release(vec![old, static_range]),
+                PoolWrite::Patch
+            ]
+        );
     }
 
-    /// Forcibly delete all pods within the MetalLB namespace.
-    /// This is required to get MetalLB to accept a new configuration, as documented here:
-    /// https://github.com/metallb/metallb/issues/308
-    #[instrument(skip(self))]
-    async fn force_reset_metallb(&self) -> Result<(), K8sError> {
-        info!(
-            msg = "Forcibly deleting MetalLB pods to pick up new addresses",
-            label_selector = ?self.config.label_selector
+    #[test]
+    fn apply_patch_only_contains_owned_fields() {
+        let patch = apply_patch(
+            "public",
+            vec!["2001:db8::1000-2001:db8::1999".to_string()],
+            Some("2001:db8::1000-2001:db8::1999".to_string()),
+            None,
         );
-        if let Left(del) = self
-            .pod_api
-            .delete_collection(
-                &DeleteParams::default(),
-                &ListParams {
-                    label_selector: Some(self.config.label_selector.clone()),
-                    ..Default::default()
+        assert_eq!(
+            patch,
+            json!({
+                "apiVersion": "metallb.io/v1beta1",
+                "kind": "IPAddressPool",
+                "metadata": {
+                    "name": "public",
+                    "annotations": {
+                        MANAGED_RANGES_ANNOTATION: "2001:db8::1000-2001:db8::1999",
+                    },
                 },
-            )
-            .await?
-        {
-            for l in del {
-                let (Some(name), Some(uid)) = (l.metadata.name, l.metadata.uid) else {
-                    warn!(msg = "Could not wait for pod deletion, metadata incomplete");
-                    continue;
-                };
-                debug!(msg = "Waiting for pod deletion", pod = name);
-                await_condition(self.pod_api.clone(), &name, is_deleted(&uid))
-                    .await
-                    .map_err(|e| K8sError { msg: e.to_string() })?;
-            }
-        }
-        Ok(())
+                "spec": {
+                    "addresses": ["2001:db8::1000-2001:db8::1999"],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn apply_patch_drops_missing_annotation() {
+        let patch = apply_patch("public", vec![], None, None);
+        assert_eq!(patch["metadata"], json!({ "name": "public" }));
+    }
+
+    #[test]
+    fn advertisement_patch_leaves_out_removed_fields() {
+        let patch = advertisement_patch::<BGPAdvertisement>(
+            "public",
+            &json!({ "aggregationLengthV6": 64, "communities": null, "nodeSelectors": null }),
+            Some("42".to_string()),
+        );
+        assert_eq!(
+            patch,
+            json!({
+                "apiVersion": "metallb.io/v1beta1",
+                "kind": "BGPAdvertisement",
+                "metadata": { "name": "public", "resourceVersion": "42" },
+                "spec": { "aggregationLengthV6": 64 },
+            })
+        );
+    }
+
+    #[test]
+    fn apply_patch_has_version_precondition() {
+        let patch = apply_patch("public", vec![], None, Some("42".to_string()));
+        assert_eq!(
+            patch["metadata"],
+            json!({ "name": "public", "resourceVersion": "42" })
+        );
     }
 }
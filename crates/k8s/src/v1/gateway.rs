@@ -0,0 +1,349 @@
+// WARNING: generated by kopium - manual changes will be overwritten
+// kopium command: kopium gateways.gateway.networking.k8s.io -A
+// kopium version: 0.15.0
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Spec defines the desired state of Gateway.
+#[derive(CustomResource, Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    kind = "Gateway",
+    plural = "gateways"
+)]
+#[kube(namespaced)]
+#[kube(status = "GatewayStatus")]
+pub struct GatewaySpec {
+    /// Addresses requested for this Gateway. This is optional and behavior can depend on the implementation. If a value is set in the spec and the requested address is invalid or unavailable, the implementation MUST indicate this in the associated entry in GatewayStatus.Addresses.
+    ///
+    /// The Addresses field represents a request for the address(es) on the "outside of the Gateway", that traffic bound for this Gateway will use. This could be the IP address or hostname of an external load balancer or other networking infrastructure, or some other address that traffic will be sent to.
+    ///
+    /// If no Addresses are specified, the implementation MAY schedule the Gateway in an implementation-specific manner, assigning an appropriate set of Addresses.
+    ///
+    /// The implementation MUST bind all Listeners to every GatewayAddress that it assigns to the Gateway and add a corresponding entry in GatewayStatus.Addresses.
+    ///
+    /// Support: Extended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<Vec<GatewayAddresses>>,
+    /// GatewayClassName used for this Gateway. This is the name of a GatewayClass resource.
+    #[serde(rename = "gatewayClassName")]
+    pub gateway_class_name: String,
+    /// Infrastructure defines infrastructure level attributes about this Gateway instance.
+    ///
+    /// Support: Extended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub infrastructure: Option<GatewayInfrastructure>,
+    /// Listeners associated with this Gateway. Listeners define logical endpoints that are bound on this Gateway's addresses. At least one Listener MUST be specified.
+    pub listeners: Vec<GatewayListeners>,
+}
+
+/// GatewayAddress describes an address that can be bound to a Gateway.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayAddresses {
+    /// Type of the address.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "type")]
+    pub r#type: Option<String>,
+    /// Value of the address. The validity of the values will depend on the type and support by the controller.
+    ///
+    /// Examples: `1.2.3.4`, `128::1`, `my-ip-address`.
+    pub value: String,
+}
+
+/// Infrastructure defines infrastructure level attributes about this Gateway instance.
+///
+/// Support: Extended
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayInfrastructure {
+    /// Annotations that SHOULD be applied to any resources created in response to this Gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+    /// Labels that SHOULD be applied to any resources created in response to this Gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    /// ParametersRef is a reference to a resource that contains the configuration parameters corresponding to the Gateway. This is optional if the controller does not require any additional configuration.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "parametersRef"
+    )]
+    pub parameters_ref: Option<GatewayInfrastructureParametersRef>,
+}
+
+/// ParametersRef is a reference to a resource that contains the configuration parameters corresponding to the Gateway. This is optional if the controller does not require any additional configuration.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayInfrastructureParametersRef {
+    /// Group is the group of the referent.
+    pub group: String,
+    /// Kind is kind of the referent.
+    pub kind: String,
+    /// Name is the name of the referent.
+    pub name: String,
+}
+
+/// Listener embodies the concept of a logical endpoint where a Gateway accepts network connections.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListeners {
+    /// AllowedRoutes defines the types of routes that MAY be attached to a Listener and the trusted namespaces where those Route resources MAY be present.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "allowedRoutes"
+    )]
+    pub allowed_routes: Option<GatewayListenersAllowedRoutes>,
+    /// Hostname specifies the virtual hostname to match for protocol types that define this concept. When unspecified, all hostnames are matched. This field is ignored for protocols that don't require hostname based matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Name is the name of the Listener. This name MUST be unique within a Gateway.
+    pub name: String,
+    /// Port is the network port. Multiple listeners may use the same port, subject to the Listener compatibility rules.
+    pub port: i32,
+    /// Protocol specifies the network protocol this listener expects to receive.
+    pub protocol: String,
+    /// TLS is the TLS configuration for the Listener. This field is required if the Protocol field is "HTTPS" or "TLS". It is invalid to set this field if the Protocol field is "HTTP", "TCP", or "UDP".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<GatewayListenersTls>,
+}
+
+/// AllowedRoutes defines the types of routes that MAY be attached to a Listener and the trusted namespaces where those Route resources MAY be present.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersAllowedRoutes {
+    /// Kinds specifies the groups and kinds of Routes that are allowed to bind to this Gateway Listener. When unspecified or empty, the kinds of Routes selected are determined using the Listener protocol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<GatewayListenersAllowedRoutesKinds>>,
+    /// Namespaces indicates namespaces from which Routes may be attached to this Listener. This is restricted to the namespace of this Gateway by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaces: Option<GatewayListenersAllowedRoutesNamespaces>,
+}
+
+/// RouteGroupKind indicates the group and kind of a Route resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersAllowedRoutesKinds {
+    /// Group is the group of the Route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Kind is the kind of the Route.
+    pub kind: String,
+}
+
+/// Namespaces indicates namespaces from which Routes may be attached to this Listener. This is restricted to the namespace of this Gateway by default.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersAllowedRoutesNamespaces {
+    /// From indicates where Routes will be selected for this Gateway. Possible values are:
+    ///
+    /// * All: Routes in all namespaces may be used by this Gateway. * Selector: Routes in namespaces selected by the selector may be used by this Gateway. * Same: Only Routes in the same namespace may be used by this Gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<GatewayListenersAllowedRoutesNamespacesFrom>,
+    /// Selector must be specified when From is set to "Selector". In that case, only Routes in Namespaces matching this Selector will be selected by this Gateway. This field is ignored for other values of "From".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<GatewayListenersAllowedRoutesNamespacesSelector>,
+}
+
+/// Namespaces indicates namespaces from which Routes may be attached to this Listener. This is restricted to the namespace of this Gateway by default.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub enum GatewayListenersAllowedRoutesNamespacesFrom {
+    All,
+    Selector,
+    Same,
+}
+
+/// Selector must be specified when From is set to "Selector". In that case, only Routes in Namespaces matching this Selector will be selected by this Gateway. This field is ignored for other values of "From".
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersAllowedRoutesNamespacesSelector {
+    /// matchExpressions is a list of label selector requirements. The requirements are ANDed.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "matchExpressions"
+    )]
+    pub match_expressions:
+        Option<Vec<GatewayListenersAllowedRoutesNamespacesSelectorMatchExpressions>>,
+    /// matchLabels is a map of {key,value} pairs. A single {key,value} in the matchLabels map is equivalent to an element of matchExpressions, whose key field is "key", the operator is "In", and the values array contains only "value". The requirements are ANDed.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "matchLabels"
+    )]
+    pub match_labels: Option<BTreeMap<String, String>>,
+}
+
+/// A label selector requirement is a selector that contains values, a key, and an operator that relates the key and values.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersAllowedRoutesNamespacesSelectorMatchExpressions {
+    /// key is the label key that the selector applies to.
+    pub key: String,
+    /// operator represents a key's relationship to a set of values. Valid operators are In, NotIn, Exists and DoesNotExist.
+    pub operator: String,
+    /// values is an array of string values. If the operator is In or NotIn, the values array must be non-empty. If the operator is Exists or DoesNotExist, the values array must be empty. This array is replaced during a strategic merge patch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+}
+
+/// TLS is the TLS configuration for the Listener. This field is required if the Protocol field is "HTTPS" or "TLS". It is invalid to set this field if the Protocol field is "HTTP", "TCP", or "UDP".
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersTls {
+    /// CertificateRefs contains a series of references to Kubernetes objects that contains TLS certificates and private keys. These certificates are used to establish a TLS handshake for requests that match the hostname of the associated listener.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "certificateRefs"
+    )]
+    pub certificate_refs: Option<Vec<GatewayListenersTlsCertificateRefs>>,
+    /// Mode defines the TLS behavior for the TLS session initiated by the client. There are two possible modes:
+    ///
+    /// - Terminate: The TLS session between the downstream client and the Gateway is terminated at the Gateway. - Passthrough: The TLS session is NOT terminated by the Gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<GatewayListenersTlsMode>,
+    /// Options are a list of key/value pairs to enable extended TLS configuration for each implementation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<BTreeMap<String, String>>,
+}
+
+/// SecretObjectReference identifies an API object including its namespace, defaulting to Secret.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayListenersTlsCertificateRefs {
+    /// Group is the group of the referent. For example, "gateway.networking.k8s.io". When unspecified or empty string, core API group is inferred.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Kind is kind of the referent. For example "Secret".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Name is the name of the referent.
+    pub name: String,
+    /// Namespace is the namespace of the referenced object. When unspecified, the local namespace is inferred.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+/// TLS is the TLS configuration for the Listener. This field is required if the Protocol field is "HTTPS" or "TLS". It is invalid to set this field if the Protocol field is "HTTP", "TCP", or "UDP".
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub enum GatewayListenersTlsMode {
+    Terminate,
+    Passthrough,
+}
+
+/// Status defines the current state of Gateway.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayStatus {
+    /// Addresses lists the network addresses that have been bound to the Gateway.
+    ///
+    /// This list may differ from the addresses provided in the spec under some conditions:
+    ///
+    /// * no addresses are specified, all addresses are dynamically assigned * a combination of specified and dynamic addresses are assigned * a specified address was unusable (e.g. already in use)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<Vec<GatewayStatusAddresses>>,
+    /// Conditions describe the current conditions of the Gateway.
+    ///
+    /// Known condition types are:
+    ///
+    /// * "Accepted" * "Programmed"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<GatewayStatusConditions>>,
+    /// Listeners provide status for each unique listener port defined in the Spec.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listeners: Option<Vec<GatewayStatusListeners>>,
+}
+
+/// GatewayStatusAddress describes a network address that is bound to a Gateway.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayStatusAddresses {
+    /// Type of the address.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "type")]
+    pub r#type: Option<String>,
+    /// Value of the address. The validity of the values will depend on the type and support by the controller.
+    ///
+    /// Examples: `1.2.3.4`, `128::1`, `my-ip-address`.
+    pub value: String,
+}
+
+/// Condition contains details for one aspect of the current state of this API Resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayStatusConditions {
+    /// lastTransitionTime is the last time the condition transitioned from one status to another.
+    #[serde(rename = "lastTransitionTime")]
+    pub last_transition_time: String,
+    /// message is a human readable message indicating details about the transition.
+    pub message: String,
+    /// observedGeneration represents the .metadata.generation that the condition was set based upon.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "observedGeneration"
+    )]
+    pub observed_generation: Option<i64>,
+    /// reason contains a programmatic identifier indicating the reason for the condition's last transition.
+    pub reason: String,
+    /// status of the condition, one of True, False, Unknown.
+    pub status: GatewayStatusConditionsStatus,
+    /// type of condition in CamelCase or in foo.example.com/CamelCase.
+    #[serde(rename = "type")]
+    pub r#type: String,
+}
+
+/// Condition contains details for one aspect of the current state of this API Resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub enum GatewayStatusConditionsStatus {
+    True,
+    False,
+    Unknown,
+}
+
+/// ListenerStatus is the status associated with a Listener.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayStatusListeners {
+    /// AttachedRoutes represents the total number of Routes that have been successfully attached to this Listener.
+    #[serde(rename = "attachedRoutes")]
+    pub attached_routes: i32,
+    /// Conditions describe the current condition of this listener.
+    pub conditions: Vec<GatewayStatusListenersConditions>,
+    /// Name is the name of the Listener that this status corresponds to.
+    pub name: String,
+    /// SupportedKinds is the list indicating the Kinds supported by this listener.
+    #[serde(rename = "supportedKinds")]
+    pub supported_kinds: Vec<GatewayStatusListenersSupportedKinds>,
+}
+
+/// Condition contains details for one aspect of the current state of this API Resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayStatusListenersConditions {
+    /// lastTransitionTime is the last time the condition transitioned from one status to another.
+    #[serde(rename = "lastTransitionTime")]
+    pub last_transition_time: String,
+    /// message is a human readable message indicating details about the transition.
+    pub message: String,
+    /// observedGeneration represents the .metadata.generation that the condition was set based upon.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "observedGeneration"
+    )]
+    pub observed_generation: Option<i64>,
+    /// reason contains a programmatic identifier indicating the reason for the condition's last transition.
+    pub reason: String,
+    /// status of the condition, one of True, False, Unknown.
+    pub status: GatewayStatusListenersConditionsStatus,
+    /// type of condition in CamelCase or in foo.example.com/CamelCase.
+    #[serde(rename = "type")]
+    pub r#type: String,
+}
+
+/// Condition contains details for one aspect of the current state of this API Resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub enum GatewayStatusListenersConditionsStatus {
+    True,
+    False,
+    Unknown,
+}
+
+/// RouteGroupKind indicates the group and kind of a Route resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct GatewayStatusListenersSupportedKinds {
+    /// Group is the group of the Route.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Kind is the kind of the Route.
+    pub kind: String,
+}
@@ -1 +1,3 @@
+pub mod bgpadvertisement;
 pub mod ipaddresspool;
+pub mod l2advertisement;
@@ -0,0 +1,73 @@
+// Written by hand after the spec of the ippools.crd.projectcalico.org CRD.
+// The projectcalico.org/v3 API is served by the Calico API server rather than a CRD, so kopium can not read it.
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// IPPoolSpec contains the specification for an IPPool resource.
+#[derive(CustomResource, Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "projectcalico.org",
+    version = "v3",
+    kind = "IPPool",
+    plural = "ippools"
+)]
+pub struct IPPoolSpec {
+    /// AllowedUse controls what the IP pool will be used for.  If not specified or empty, defaults to ["Tunnel", "Workload"] for back-compatibility
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "allowedUses"
+    )]
+    pub allowed_uses: Option<Vec<String>>,
+    /// Determines the mode how IP addresses should be assigned from this pool
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "assignmentMode"
+    )]
+    pub assignment_mode: Option<IPPoolAssignmentMode>,
+    /// The block size to use for IP address assignments from this pool. Defaults to 26 for IPv4 and 122 for IPv6.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "blockSize")]
+    pub block_size: Option<i64>,
+    /// The pool CIDR.
+    pub cidr: String,
+    /// Disable exporting routes from this IP Pool's CIDR over BGP. [Default: false]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "disableBGPExport"
+    )]
+    pub disable_bgp_export: Option<bool>,
+    /// When disabled is true, Calico IPAM will not assign addresses from this pool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<bool>,
+    /// Contains configuration for IPIP tunneling for this pool. If not specified, then this is defaulted to "Never" (i.e. IPIP tunneling is disabled).
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "ipipMode")]
+    pub ipip_mode: Option<String>,
+    /// When natOutgoing is true, packets sent from Calico networked containers in this pool to destinations outside of this pool will be masqueraded.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "natOutgoing"
+    )]
+    pub nat_outgoing: Option<bool>,
+    /// Allows IPPool to allocate for a specific node by label selector.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        rename = "nodeSelector"
+    )]
+    pub node_selector: Option<String>,
+    /// Contains configuration for VXLAN tunneling for this pool. If not specified, then this is defaulted to "Never" (i.e. VXLAN tunneling is disabled).
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "vxlanMode")]
+    pub vxlan_mode: Option<String>,
+}
+
+/// IPPoolSpec contains the specification for an IPPool resource.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub enum IPPoolAssignmentMode {
+    Automatic,
+    Manual,
+}
@@ -0,0 +1,185 @@
+use std::{net::IpAddr, time::Duration};
+
+use k8s_openapi::api::core::v1::Service;
+use kube::{api::ListParams, Api, Client, ResourceExt};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{pinned::requested_addresses, ranges::MetalLbAddressRange, K8sError};
+
+/// Annotation set by MetalLB on each Service with the name of the pool its addresses were allocated from
+const ALLOCATED_FROM_POOL_ANNOTATION: &str = "metallb.io/ip-allocated-from-pool";
+
+/// Interval between checks whether the Services received addresses from the new ranges
+const VERIFY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The address ranges of a pool before and after an update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RangeChange {
+    pub(crate) pool: String,
+    pub(crate) previous: Vec<MetalLbAddressRange>,
+    pub(crate) current: Vec<MetalLbAddressRange>,
+    /// The subsets of the ranges that are managed by metallb-dyn6
+    pub(crate) previous_managed: Vec<MetalLbAddressRange>,
+    pub(crate) current_managed: Vec<MetalLbAddressRange>,
+}
+
+/// Checks that MetalLB adopted new pool ranges by looking at the ingress addresses of LoadBalancer Services
+#[derive(Debug)]
+pub(crate) struct AdoptionVerifier {
+    timeout: Duration,
+    service_api: Api<Service>,
+}
+
+impl AdoptionVerifier {
+    pub(crate) fn new(client: Client, timeout: Duration) -> Self {
+        AdoptionVerifier {
+            timeout,
+            service_api: Api::all(client),
+        }
+    }
+
+    /// Wait until every Service that uses one of the changed pools only has ingress addresses within the new ranges.
+    /// If the Services can not be listed, the update is assumed to be adopted.
+    #[instrument(skip_all)]
+    pub(crate) async fn verify(&self, changes: &[RangeChange]) -> Result<(), K8sError> {
+        info!(
+            msg = "Verifying that MetalLB adopted the new ranges",
+            timeout = self.timeout.as_secs()
+        );
+        let mut pending = Vec::new();
+        let verified = tokio::time::timeout(self.timeout, async {
+            loop {
+                let services = match self.service_api.list(&ListParams::default()).await {
+                    Ok(services) => services,
+                    // Missing RBAC is no reason to roll back an update that may well have worked
+                    Err(e) => {
+                        warn!(
+                            msg = "Could not list Services, skipping verification",
+                            error = e.to_string()
+                        );
+                        return Ok(());
+                    }
+                };
+                pending = changes
+                    .iter()
+                    .flat_map(|c| pending_services(&services.items, c))
+                    .collect::<Vec<_>>();
+                if pending.is_empty() {
+                    return Ok::<_, K8sError>(());
+                }
+                debug!(msg = "Services still use previous addresses", services = ?pending);
+                tokio::time::sleep(VERIFY_POLL_INTERVAL).await;
+            }
+        })
+        .await;
+        match verified {
+            Ok(result) => result,
+            Err(_) => Err(K8sError::new(format!(
+                "MetalLB did not adopt the new ranges within {}s, Services still using previous addresses: {}",
+                self.timeout.as_secs(),
+                pending.join(", ")
+            ))),
+        }
+    }
+}
+
+/// Names (`namespace/name`) of the LoadBalancer Services of the changed pool
+/// that still have ingress addresses outside of its new ranges.
+/// Services that pin an address outside of the new ranges can not move and are not waited for.
+fn pending_services(services: &[Service], change: &RangeChange) -> Vec<String> {
+    services
+        .iter()
+        .filter(|s| {
+            uses_pool(s, change)
+                && !outdated_addresses(s, change).is_empty()
+                && !pins_outdated_address(s, change)
+        })
+        .map(|s| format!("{}/{}", s.namespace().unwrap_or_default(), s.name_any()))
+        .collect()
+}
+
+/// Whether the Service is a LoadBalancer that uses the changed pool.
+/// A Service uses the pool if MetalLB annotated it with the pool name or if it has an address from the previous ranges.
+pub(crate) fn uses_pool(service: &Service, change: &RangeChange) -> bool {
+    if service.spec.as_ref().and_then(|s| s.type_.as_deref()) != Some("LoadBalancer") {
+        return false;
+    }
+    service.annotations().get(ALLOCATED_FROM_POOL_ANNOTATION) == Some(&change.pool)
+        || ingress_addresses(service)
+            .iter()
+            .any(|a| change.previous.iter().any(|r| r.contains(*a)))
+}
+
+/// Whether the Service requests an address that is not within the new ranges of the pool
+fn pins_outdated_address(service: &Service, change: &RangeChange) -> bool {
+    requested_addresses(service)
+        .iter()
+        .any(|a| !change.current.iter().any(|r| r.contains(*a)))
+}
+
+/// Ingress addresses of the Service that are not within the new ranges of the pool
+pub(crate) fn outdated_addresses(service: &Service, change: &RangeChange) -> Vec<IpAddr> {
+    ingress_addresses(service)
+        .into_iter()
+        .filter(|a| !change.current.iter().any(|r| r.contains(*a)))
+        .collect()
+}
+
+pub(crate) fn ingress_addresses(service: &Service) -> Vec<IpAddr> {
+    service
+        .status
+        .as_ref()
+        .and_then(|s| s.load_balancer.as_ref())
+        .and_then(|lb| lb.ingress.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|i| i.ip.as_deref()?.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{annotated, load_balancer, range_change as change};
+
+    /// A Service in the default namespace, annotated by MetalLB with the pool it was allocated from
+    fn service(name: &str, pool: Option<&str>, ips: &[&str]) -> Service {
+        let service = load_balancer("default", name, ips);
+        match pool {
+            Some(pool) => annotated(service, ALLOCATED_FROM_POOL_ANNOTATION, pool),
+            None => service,
+        }
+    }
+
+    #[test]
+    fn services_with_new_addresses_are_adopted() {
+        let services = [
+            service("web", Some("public"), &["2001:db8:bbbb::1000"]),
+            service("other-pool", Some("internal"), &["fd00::1"]),
+            service("pending", None, &[]),
+        ];
+        assert!(pending_services(&services, &change()).is_empty());
+    }
+
+    #[test]
+    fn services_with_previous_addresses_are_pending() {
+        let services = [
+            service("annotated", Some("public"), &["2001:db8:aaaa::1000"]),
+            service("unannotated", None, &["2001:db8:aaaa::1001"]),
+        ];
+        assert_eq!(
+            pending_services(&services, &change()),
+            vec!["default/annotated", "default/unannotated"]
+        );
+    }
+
+    #[test]
+    fn services_pinned_to_previous_addresses_are_not_pending() {
+        let pinned = annotated(
+            service("pinned", Some("public"), &["2001:db8:aaaa::1000"]),
+            "metallb.io/loadBalancerIPs",
+            "2001:db8:aaaa::1000",
+        );
+        assert!(pending_services(&[pinned], &change()).is_empty());
+    }
+}
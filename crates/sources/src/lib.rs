@@ -4,9 +4,13 @@ use async_trait::async_trait;
 use ipnet::Ipv6Net;
 use thiserror::Error;
 
+mod mqtt;
 mod my_ip;
+mod stun;
 
+pub use mqtt::{MqttSource, MqttSourceConfig};
 pub use my_ip::MyIpSource;
+pub use stun::StunSource;
 
 #[derive(Error, Debug, PartialEq, Eq, Hash, Clone)]
 #[error("Could not retrieve IPv6 address from source: {msg}")]
@@ -0,0 +1,428 @@
+use std::{
+    net::Ipv6Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ipnet::Ipv6Net;
+use rumqttc::{
+    AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, TlsConfiguration, Transport,
+};
+use rustls::{ClientConfig, RootCertStore};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::{addr_to_network, NetworkSource, SourceError};
+
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MqttSourceConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    /// Topic on which the prefix is published
+    pub topic: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Connect to the broker using TLS
+    pub tls: bool,
+    /// PEM-encoded CA certificate used to verify the broker. Uses the system roots if unset.
+    pub ca_file: Option<PathBuf>,
+    /// If set, the payload is parsed as JSON and the prefix is read from the value at this
+    /// JSON pointer (RFC 6901), such as `/ipv6/prefix`. Otherwise, the raw payload is used.
+    pub json_pointer: Option<String>,
+    /// Time to wait before reconnecting after the connection to the broker was lost
+    pub reconnect_delay: Duration,
+}
+
+/// A push-based source that subscribes to a topic on an MQTT broker.
+/// The last prefix published to the topic is returned, which may be either an address or a network.
+/// Networks shorter than /64 (such as a delegated /56) are truncated to their first /64.
+#[derive(Debug)]
+pub struct MqttSource {
+    prefix: watch::Receiver<Option<Ipv6Net>>,
+    task: JoinHandle<()>,
+}
+
+impl MqttSource {
+    /// Connect to the broker and subscribe to the configured topic in the background.
+    /// Must be called from within a tokio runtime.
+    pub fn new(config: MqttSourceConfig) -> Result<Self, SourceError> {
+        let (client, eventloop) = AsyncClient::new(mqtt_options(&config)?, 10);
+        let (tx, rx) = watch::channel(None);
+        let task = tokio::spawn(run_eventloop(client, eventloop, config, tx));
+
+        Ok(MqttSource { prefix: rx, task })
+    }
+}
+
+impl Drop for MqttSource {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl NetworkSource for MqttSource {
+    async fn get(&self) -> Result<Ipv6Net, SourceError> {
+        self.prefix.borrow().ok_or_else(|| SourceError {
+            msg: "No prefix has been received via MQTT yet".to_string(),
+        })
+    }
+}
+
+fn mqtt_options(config: &MqttSourceConfig) -> Result<MqttOptions, SourceError> {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(MQTT_KEEP_ALIVE);
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    if config.tls {
+        options.set_transport(tls_transport(config.ca_file.as_deref())?);
+    }
+    Ok(options)
+}
+
+/// TLS transport that verifies the broker with the CA certificates in `ca_file`, or with the system roots if unset.
+/// rumqttc is built without a crypto provider, so the ring provider is passed explicitly
+/// instead of relying on a process-wide default.
+fn tls_transport(ca_file: Option<&Path>) -> Result<Transport, SourceError> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let invalid = |e: &dyn std::fmt::Display| SourceError {
+                msg: format!("Invalid MQTT CA file {}: {e}", path.display()),
+            };
+            let pem = std::fs::read(path).map_err(|e| SourceError {
+                msg: format!("Could not read MQTT CA file {}: {e}", path.display()),
+            })?;
+            for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                roots
+                    .add(cert.map_err(|e| invalid(&e))?)
+                    .map_err(|e| invalid(&e))?;
+            }
+            if roots.is_empty() {
+                return Err(invalid(&"no certificates found"));
+            }
+        }
+        None => {
+            let native = rustls_native_certs::load_native_certs();
+            if !native.errors.is_empty() {
+                warn!(msg = "Could not load all system root certificates", errors = ?native.errors);
+            }
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| SourceError {
+                msg: format!("Could not configure MQTT TLS: {e}"),
+            })?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Ok(Transport::tls_with_config(TlsConfiguration::Rustls(
+        Arc::new(config),
+    )))
+}
+
+async fn run_eventloop(
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    config: MqttSourceConfig,
+    tx: watch::Sender<Option<Ipv6Net>>,
+) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                // (Re-)subscribe on every connect, as the broker does not keep our session
+                info!(
+                    msg = "Connected to MQTT broker",
+                    host = config.host,
+                    topic = config.topic
+                );
+                if let Err(e) = client.try_subscribe(&config.topic, QoS::AtLeastOnce) {
+                    warn!(
+                        msg = "Could not subscribe to MQTT topic",
+                        error = e.to_string()
+                    );
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                match parse_payload(&publish.payload, config.json_pointer.as_deref()) {
+                    Ok(prefix) => {
+                        info!(msg = "Received prefix via MQTT", prefix = ?prefix);
+                        tx.send_replace(Some(prefix));
+                    }
+                    Err(e) => warn!(msg = "Ignoring invalid MQTT payload", error = e.msg),
+                }
+            }
+            Ok(event) => debug!(?event),
+            Err(e) => {
+                warn!(
+                    msg = "MQTT connection lost, reconnecting",
+                    error = e.to_string(),
+                    delay = ?config.reconnect_delay
+                );
+                tokio::time::sleep(config.reconnect_delay).await;
+            }
+        }
+    }
+}
+
+fn parse_payload(payload: &[u8], json_pointer: Option<&str>) -> Result<Ipv6Net, SourceError> {
+    let raw = match json_pointer {
+        Some(pointer) => {
+            let json = serde_json::from_slice::<serde_json::Value>(payload)
+                .map_err(|e| SourceError { msg: e.to_string() })?;
+            json.pointer(pointer)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| SourceError {
+                    msg: format!("No string value found at {pointer}"),
+                })?
+                .to_string()
+        }
+        None => {
+            String::from_utf8(payload.to_vec()).map_err(|e| SourceError { msg: e.to_string() })?
+        }
+    };
+    let raw = raw.trim();
+
+    if let Ok(net) = raw.parse::<Ipv6Net>() {
+        if net.prefix_len() > 64 {
+            return Err(SourceError {
+                msg: format!("Prefix {net} is longer than /64"),
+            });
+        }
+        Ok(addr_to_network(net.network()))
+    } else if let Ok(addr) = raw.parse::<Ipv6Addr>() {
+        Ok(addr_to_network(addr))
+    } else {
+        Err(SourceError {
+            msg: format!("{raw} is not an IPv6 address or network"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    const TOPIC: &str = "router/wan/ipv6";
+
+    /// Read a single MQTT control packet, returning the packet type and its body
+    async fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let header = stream.read_u8().await.ok()?;
+        let mut len = 0usize;
+        for shift in (0..28).step_by(7) {
+            let b = stream.read_u8().await.ok()?;
+            len |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.ok()?;
+        Some((header >> 4, body))
+    }
+
+    /// A minimal MQTT 3.1.1 broker serving one client per session.
+    /// Every session accepts a connection and subscription, publishes the given payloads and then
+    /// drops the connection.
+    async fn spawn_broker(sessions: Vec<Vec<&'static str>>) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            for payloads in sessions {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                while let Some((packet_type, body)) = read_packet(&mut stream).await {
+                    match packet_type {
+                        // CONNECT
+                        1 => stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap(),
+                        // SUBSCRIBE
+                        8 => {
+                            let topic_len = u16::from_be_bytes([body[2], body[3]]) as usize;
+                            assert_eq!(&body[4..4 + topic_len], TOPIC.as_bytes());
+                            stream
+                                .write_all(&[0x90, 0x03, body[0], body[1], 0x01])
+                                .await
+                                .unwrap();
+                            for payload in &payloads {
+                                let mut publish =
+                                    vec![0x30, (2 + TOPIC.len() + payload.len()) as u8];
+                                publish.extend_from_slice(&(TOPIC.len() as u16).to_be_bytes());
+                                publish.extend_from_slice(TOPIC.as_bytes());
+                                publish.extend_from_slice(payload.as_bytes());
+                                stream.write_all(&publish).await.unwrap();
+                            }
+                            // give the client time to process the messages before disconnecting
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            break;
+                        }
+                        // PINGREQ
+                        12 => stream.write_all(&[0xd0, 0x00]).await.unwrap(),
+                        _ => {}
+                    }
+                }
+            }
+        });
+        port
+    }
+
+    fn config(port: u16, json_pointer: Option<&str>) -> MqttSourceConfig {
+        MqttSourceConfig {
+            host: Ipv4Addr::LOCALHOST.to_string(),
+            port,
+            client_id: "metallb-dyn6-test".to_string(),
+            topic: TOPIC.to_string(),
+            username: Some("user".to_string()),
+            password: Some("password".to_string()),
+            tls: false,
+            ca_file: None,
+            json_pointer: json_pointer.map(ToString::to_string),
+            reconnect_delay: Duration::from_millis(50),
+        }
+    }
+
+    /// Wait until the source returns the expected prefix
+    async fn wait_for(source: &MqttSource, expected: Ipv6Net) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while source.get().await != Ok(expected) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("expected prefix {expected} was not received"));
+    }
+
+    #[tokio::test]
+    async fn receives_raw_prefix() {
+        let port = spawn_broker(vec![vec!["2001:db8:dead:beef::/64"]]).await;
+        let source = MqttSource::new(config(port, None)).unwrap();
+        wait_for(&source, "2001:db8:dead:beef::/64".parse().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn receives_json_prefix() {
+        let port = spawn_broker(vec![vec![r#"{"wan":{"prefix":"2001:db8:aa00::/56"}}"#]]).await;
+        let source = MqttSource::new(config(port, Some("/wan/prefix"))).unwrap();
+        wait_for(&source, "2001:db8:aa00::/64".parse().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn picks_up_new_prefix_after_reconnect() {
+        let port = spawn_broker(vec![
+            vec!["2001:db8:dead:beef::1"],
+            vec!["2001:db8:d00f:ffff::1"],
+        ])
+        .await;
+        let source = MqttSource::new(config(port, None)).unwrap();
+        wait_for(&source, "2001:db8:d00f:ffff::/64".parse().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn errors_before_first_message() {
+        // nothing is listening on this port
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let source = MqttSource::new(config(port, None)).unwrap();
+        source.get().await.unwrap_err();
+    }
+
+    #[test]
+    fn rejects_invalid_payloads() {
+        parse_payload(b"not an address", None).unwrap_err();
+        parse_payload(b"192.0.2.1", None).unwrap_err();
+        parse_payload(b"2001:db8::/80", None).unwrap_err();
+        parse_payload(br#"{"prefix": 5}"#, Some("/prefix")).unwrap_err();
+    }
+
+    /// Self-signed CA certificate, only used to build the TLS configuration
+    const CA_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBlTCCATugAwIBAgIUM7rBEEiCSqaSbbPQKbeZO/ZdLB0wCgYIKoZIzj0EAwIw
+HzEdMBsGA1UEAwwUbWV0YWxsYi1keW42IHRlc3QgQ0EwIBcNMjYxMDE4MjI1OTM5
+WhgPMjEyNjA5MjQyMjU5MzlaMB8xHTAbBgNVBAMMFG1ldGFsbGItZHluNiB0ZXN0
+IENBMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE2BYD2j65dG/Rmxs4dJ1TUdKf
+4bf3uQlov/YMGncyVnhFF1jpGOgPfQlWGInwt5//ZWAY2yfRk6VfFrDxAoxfXqNT
+MFEwHQYDVR0OBBYEFDnpF/5Ainjz1WFGgeCvJwMDnnnnMB8GA1UdIwQYMBaAFDnp
+F/5Ainjz1WFGgeCvJwMDnnnnMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwID
+SAAwRQIhAIg/16fReYdpb6xsIz4KIFfLjKoPW2b7mQ9WhTETWsEhAiBltwQxpd+8
+M8SsF1V644Z4tomw0GJR47DBLv/KPP3WoQ==
+-----END CERTIFICATE-----
+";
+
+    fn is_rustls(options: &MqttOptions) -> bool {
+        matches!(
+            options.transport(),
+            Transport::Tls(TlsConfiguration::Rustls(_))
+        )
+    }
+
+    #[test]
+    fn builds_tls_options_with_ca_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, CA_PEM).unwrap();
+        let options = mqtt_options(&MqttSourceConfig {
+            tls: true,
+            ca_file: Some(ca_file),
+            ..config(8883, None)
+        })
+        .unwrap();
+        assert!(is_rustls(&options));
+        assert_eq!(options.keep_alive(), MQTT_KEEP_ALIVE);
+    }
+
+    #[test]
+    fn builds_tls_options_with_system_roots() {
+        let options = mqtt_options(&MqttSourceConfig {
+            tls: true,
+            ..config(8883, None)
+        })
+        .unwrap();
+        assert!(is_rustls(&options));
+    }
+
+    #[test]
+    fn rejects_invalid_ca_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, "not a certificate").unwrap();
+        let err = mqtt_options(&MqttSourceConfig {
+            tls: true,
+            ca_file: Some(ca_file.clone()),
+            ..config(8883, None)
+        })
+        .unwrap_err();
+        assert!(err.msg.starts_with("Invalid MQTT CA file"), "{}", err.msg);
+
+        let err = mqtt_options(&MqttSourceConfig {
+            tls: true,
+            ca_file: Some(dir.path().join("missing.pem")),
+            ..config(8883, None)
+        })
+        .unwrap_err();
+        assert!(
+            err.msg.starts_with("Could not read MQTT CA file"),
+            "{}",
+            err.msg
+        );
+    }
+
+    #[test]
+    fn plain_options_use_tcp() {
+        let options = mqtt_options(&config(1883, None)).unwrap();
+        assert!(matches!(options.transport(), Transport::Tcp));
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ipnet::Ipv6Net;
+use tokio::net::{lookup_host, UdpSocket};
+use tracing::{debug, warn};
+
+use crate::{addr_to_network, NetworkSource, SourceError};
+
+/// Magic cookie value as defined in RFC 5389, section 6
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const FAMILY_IPV6: u8 = 0x02;
+const HEADER_LEN: usize = 20;
+
+const STUN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of requests sent to each server before moving on to the next one
+const STUN_ATTEMPTS: usize = 3;
+
+/// Retrieves the public IPv6 address of this host by sending STUN Binding Requests (RFC 5389) over UDP.
+/// The servers are queried in order, the first successful response is used.
+#[derive(Debug, Clone)]
+pub struct StunSource {
+    /// STUN servers in `host:port` notation
+    servers: Vec<String>,
+    timeout: Duration,
+}
+
+impl StunSource {
+    pub fn new(servers: Vec<String>) -> Self {
+        StunSource {
+            servers,
+            timeout: STUN_TIMEOUT,
+        }
+    }
+
+    /// Set the time to wait for a response to each individual request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn query_server(&self, server: &str) -> Result<Ipv6Addr, SourceError> {
+        let addr = lookup_host(server)
+            .await
+            .map_err(|e| SourceError {
+                msg: format!("Could not resolve STUN server {server}: {e}"),
+            })?
+            .find(SocketAddr::is_ipv6)
+            .ok_or_else(|| SourceError {
+                msg: format!("STUN server {server} has no IPv6 address"),
+            })?;
+
+        let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+            .await
+            .map_err(|e| SourceError { msg: e.to_string() })?;
+        socket
+            .connect(addr)
+            .await
+            .map_err(|e| SourceError { msg: e.to_string() })?;
+
+        let transaction_id: [u8; 12] = rand::random();
+        let request = binding_request(&transaction_id);
+        let mut last_error = None;
+
+        for attempt in 1..=STUN_ATTEMPTS {
+            debug!(msg = "Sending STUN binding request", server, attempt);
+            socket
+                .send(&request)
+                .await
+                .map_err(|e| SourceError { msg: e.to_string() })?;
+            if let Some(ip) = self
+                .receive_response(&socket, addr, &transaction_id, &mut last_error)
+                .await?
+            {
+                return Ok(ip);
+            }
+        }
+        Err(SourceError {
+            msg: match last_error {
+                Some(e) => format!("STUN server {server} sent no valid response: {}", e.msg),
+                None => format!("STUN server {server} did not respond"),
+            },
+        })
+    }
+
+    /// Wait for a valid response to our request until the timeout expires.
+    /// Datagrams from other peers or for other transactions (such as late responses to an earlier request)
+    /// are ignored, the parse error of the last invalid one is stored in `last_error`.
+    /// Returns None if no valid response arrived in time.
+    async fn receive_response(
+        &self,
+        socket: &UdpSocket,
+        server: SocketAddr,
+        transaction_id: &[u8; 12],
+        last_error: &mut Option<SourceError>,
+    ) -> Result<Option<Ipv6Addr>, SourceError> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buf = [0u8; 576];
+        loop {
+            match tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+                Ok(Ok((_, peer))) if peer != server => {
+                    debug!(msg = "Ignoring datagram from unexpected peer", %peer);
+                }
+                Ok(Ok((len, _))) => match parse_binding_response(&buf[..len], transaction_id) {
+                    Ok(ip) => return Ok(Some(ip)),
+                    Err(e) => {
+                        debug!(msg = "Ignoring invalid STUN response", error = e.msg);
+                        *last_error = Some(e);
+                    }
+                },
+                Ok(Err(e)) => return Err(SourceError { msg: e.to_string() }),
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl NetworkSource for StunSource {
+    async fn get(&self) -> Result<Ipv6Net, SourceError> {
+        let mut errors = Vec::new();
+        for server in &self.servers {
+            match self.query_server(server).await {
+                Ok(addr) => return Ok(addr_to_network(addr)),
+                Err(e) => {
+                    warn!(msg = "STUN query failed", server, error = e.msg);
+                    errors.push(e.msg);
+                }
+            }
+        }
+        Err(SourceError {
+            msg: format!(
+                "No STUN server returned an address: [{}]",
+                errors.join(", ")
+            ),
+        })
+    }
+}
+
+fn binding_request(transaction_id: &[u8; 12]) -> [u8; HEADER_LEN] {
+    let mut req = [0u8; HEADER_LEN];
+    req[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // message length is 0, as we do not send any attributes
+    req[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    req[8..20].copy_from_slice(transaction_id);
+    req
+}
+
+/// Extract the IPv6 address from the XOR-MAPPED-ADDRESS attribute of a Binding Success Response
+fn parse_binding_response(msg: &[u8], transaction_id: &[u8; 12]) -> Result<Ipv6Addr, SourceError> {
+    let err = |msg: &str| SourceError {
+        msg: format!("Invalid STUN response: {msg}"),
+    };
+
+    if msg.len() < HEADER_LEN {
+        return Err(err("message too short"));
+    }
+    if u16::from_be_bytes([msg[0], msg[1]]) != BINDING_SUCCESS_RESPONSE {
+        return Err(err("not a binding success response"));
+    }
+    let body_len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    if msg[4..8] != MAGIC_COOKIE.to_be_bytes() || &msg[8..20] != transaction_id {
+        return Err(err("magic cookie or transaction ID mismatch"));
+    }
+    let body = msg
+        .get(HEADER_LEN..HEADER_LEN + body_len)
+        .ok_or_else(|| err("truncated message"))?;
+
+    let mut attrs = body;
+    while attrs.len() >= 4 {
+        let attr_type = u16::from_be_bytes([attrs[0], attrs[1]]);
+        let attr_len = u16::from_be_bytes([attrs[2], attrs[3]]) as usize;
+        let value = attrs
+            .get(4..4 + attr_len)
+            .ok_or_else(|| err("truncated attribute"))?;
+
+        if attr_type == ATTR_XOR_MAPPED_ADDRESS {
+            // value layout: reserved (1), family (1), x-port (2), x-address (16)
+            if value.len() != 20 || value[1] != FAMILY_IPV6 {
+                return Err(err("XOR-MAPPED-ADDRESS is not an IPv6 address"));
+            }
+            let mut key = [0u8; 16];
+            key[0..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            key[4..16].copy_from_slice(transaction_id);
+            let mut addr = [0u8; 16];
+            for (i, b) in addr.iter_mut().enumerate() {
+                *b = value[4 + i] ^ key[i];
+            }
+            return Ok(Ipv6Addr::from(addr));
+        }
+
+        // attributes are padded to a multiple of 4 bytes
+        let padded_len = attr_len.div_ceil(4) * 4;
+        attrs = attrs.get(4 + padded_len..).unwrap_or_default();
+    }
+    Err(err("no XOR-MAPPED-ADDRESS attribute"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_ADDR: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0xdead, 0xbeef, 0, 0, 0, 0x1234);
+
+    /// Build a binding success response for the given request, reporting `mapped` as our address
+    fn binding_response(request: &[u8], mapped: Ipv6Addr) -> Vec<u8> {
+        let mut resp = Vec::new();
+        resp.extend_from_slice(&BINDING_SUCCESS_RESPONSE.to_be_bytes());
+        // SOFTWARE attribute (4 header + 5 value + 3 padding) followed by XOR-MAPPED-ADDRESS (4 + 20)
+        resp.extend_from_slice(&36u16.to_be_bytes());
+        resp.extend_from_slice(&request[4..20]);
+
+        resp.extend_from_slice(&0x8022u16.to_be_bytes());
+        resp.extend_from_slice(&5u16.to_be_bytes());
+        resp.extend_from_slice(b"test\0\0\0\0");
+
+        resp.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+        resp.extend_from_slice(&20u16.to_be_bytes());
+        resp.extend_from_slice(&[0, FAMILY_IPV6]);
+        resp.extend_from_slice(&(3478u16 ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+        for (i, b) in mapped.octets().iter().enumerate() {
+            resp.push(b ^ request[4 + i]);
+        }
+        resp
+    }
+
+    /// Spawn a STUN responder on localhost that answers every binding request with `mapped`
+    async fn spawn_responder(mapped: Ipv6Addr) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 576];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                assert_eq!(len, HEADER_LEN);
+                assert_eq!(buf[0..2], BINDING_REQUEST.to_be_bytes());
+                let resp = binding_response(&buf[..len], mapped);
+                socket.send_to(&resp, peer).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn retrieves_prefix_from_server() {
+        let server = spawn_responder(PUBLIC_ADDR).await;
+        let source = StunSource::new(vec![server.to_string()]);
+        assert_eq!(
+            source.get().await.unwrap(),
+            Ipv6Net::new_assert(Ipv6Addr::new(0x2001, 0xdb8, 0xdead, 0xbeef, 0, 0, 0, 0), 64)
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_server() {
+        // bound, but never answers
+        let silent = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+        let server = spawn_responder(PUBLIC_ADDR).await;
+        let source = StunSource::new(vec![
+            silent.local_addr().unwrap().to_string(),
+            server.to_string(),
+        ])
+        .with_timeout(Duration::from_millis(50));
+        assert_eq!(source.get().await.unwrap(), addr_to_network(PUBLIC_ADDR));
+    }
+
+    #[tokio::test]
+    async fn ignores_responses_to_other_transactions() {
+        let socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+        let server = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 576];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            // a stale response for another transaction arrives first
+            let stale = binding_response(&binding_request(&[7; 12]), Ipv6Addr::LOCALHOST);
+            socket.send_to(&stale, peer).await.unwrap();
+            let resp = binding_response(&buf[..len], PUBLIC_ADDR);
+            socket.send_to(&resp, peer).await.unwrap();
+        });
+        let source = StunSource::new(vec![server.to_string()]);
+        assert_eq!(source.get().await.unwrap(), addr_to_network(PUBLIC_ADDR));
+    }
+
+    #[tokio::test]
+    async fn fails_without_responding_server() {
+        let silent = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+        let source = StunSource::new(vec![silent.local_addr().unwrap().to_string()])
+            .with_timeout(Duration::from_millis(50));
+        source.get().await.unwrap_err();
+    }
+
+    #[test]
+    fn rejects_mismatched_transaction_id() {
+        let request = binding_request(&[1; 12]);
+        let response = binding_response(&request, PUBLIC_ADDR);
+        parse_binding_response(&response, &[2; 12]).unwrap_err();
+    }
+
+    #[test]
+    fn rejects_ipv4_mapped_address() {
+        let transaction_id = [1; 12];
+        let mut response = binding_response(&binding_request(&transaction_id), PUBLIC_ADDR);
+        // family byte of the XOR-MAPPED-ADDRESS attribute
+        response[HEADER_LEN + 12 + 5] = 0x01;
+        parse_binding_response(&response, &transaction_id).unwrap_err();
+    }
+}